@@ -0,0 +1,90 @@
+//! Baselines for the operations on [`Timers`] which touch every timer, to
+//! judge changes to how timers are stored against.
+//!
+//! Run with `cargo bench`.
+
+use std::time::{Duration, Instant, SystemTime};
+
+use criterion::{criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion};
+use sand::timer::RunState;
+use sand::{StartSpec, Timer, TimerId, Timers};
+use tokio::runtime::Runtime;
+use tokio::task::JoinHandle;
+
+const SIZES: [usize; 2] = [1_000, 10_000];
+
+fn pending_countdown() -> JoinHandle<()> {
+    tokio::spawn(std::future::pending())
+}
+
+/// `n` timers, alternately running and paused.
+fn populated(n: usize, now: Instant) -> Timers {
+    let timers = Timers::default();
+    for i in 0..n {
+        let remaining = Duration::from_secs(60 + i as u64);
+        let state = if i % 2 == 0 {
+            RunState::Running { due: now + remaining, countdown: pending_countdown() }
+        } else {
+            RunState::Paused { remaining, after: None, resume_at: None }
+        };
+        timers.add_new(|_| Timer {
+            spec: StartSpec::new(remaining),
+            state,
+            created_at: SystemTime::now(),
+        });
+    }
+    timers
+}
+
+fn bench_timers(c: &mut Criterion) {
+    let rt = Runtime::new().unwrap();
+    let _guard = rt.enter();
+    let now = Instant::now();
+
+    let mut group = c.benchmark_group("get_timerinfo_for_client");
+    for n in SIZES {
+        let timers = populated(n, now);
+        group.bench_with_input(BenchmarkId::from_parameter(n), &timers, |b, timers| {
+            b.iter(|| timers.get_timerinfo_for_client(now))
+        });
+    }
+    group.finish();
+
+    // What `DaemonCtx::awaken` does with the timers after a suspend.
+    let mut group = c.benchmark_group("reschedule_running");
+    for n in SIZES {
+        let timers = populated(n, now);
+        group.bench_with_input(BenchmarkId::from_parameter(n), &timers, |b, timers| {
+            b.iter(|| timers.reschedule_running(now, Duration::from_secs(1), |_, _, _| pending_countdown()))
+        });
+    }
+    group.finish();
+
+    // Adding a timer searches for the lowest free id. The worst case is
+    // when the only gap is at the end.
+    let mut group = c.benchmark_group("add_new");
+    for n in SIZES {
+        group.bench_function(BenchmarkId::from_parameter(n), |b| {
+            b.iter_batched(
+                || {
+                    let timers = populated(n, now);
+                    timers.cancel(TimerId(n as u64));
+                    timers
+                },
+                |timers| {
+                    timers.add_new(|_| Timer {
+                        spec: StartSpec::new(Duration::from_secs(60)),
+                        state: RunState::Paused { remaining: Duration::from_secs(60), after: None, resume_at: None },
+                        created_at: SystemTime::now(),
+                    });
+                    timers
+                },
+                BatchSize::LargeInput,
+            )
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_timers);
+criterion_main!(benches);
@@ -0,0 +1,488 @@
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime};
+
+use clap::{Args, CommandFactory, Parser, Subcommand};
+use sand::clock::{self, TimeOfDay};
+use sand::config::Urgency;
+use sand::duration::DurationFormat;
+use sand::message::{Command, ListArgs, TimerFilter};
+use sand::timer::{SortKey, TimerId, TimerState};
+
+
+#[derive(Args)]
+pub struct DaemonArgs {
+    /// Never play sounds, and don't initialize audio at all. Can also be set
+    /// with the SAND_NO_SOUND environment variable.
+    #[clap(long)]
+    pub no_sound: bool,
+    /// Exit once there are no timers left, and none have been started for a
+    /// few seconds. For one-off use, like in scripts.
+    #[clap(long)]
+    pub until_empty: bool,
+    /// Don't keep anything in the state directory, so `sand again` forgets
+    /// the last timer when the daemon restarts. For tests.
+    #[clap(long)]
+    pub no_state: bool,
+}
+
+#[derive(Parser)]
+#[clap(
+    name = "sand",
+    about = "Command line countdown timers that don't take up a terminal.",
+    version
+)]
+pub struct Cli {
+    #[clap(subcommand)]
+    pub command: CliCommand,
+    /// Print output in a stable, tab separated format for scripts, which
+    /// won't change between releases
+    #[clap(long, global = true)]
+    pub porcelain: bool,
+    /// Explain how the daemon is found and connected to, and print each
+    /// message sent to and received from it as JSON, on stderr. Given twice,
+    /// also print keepalives. Without it, SAND_CLIENT_LOG sets the log level
+    #[clap(short, long, global = true, action = clap::ArgAction::Count)]
+    pub verbose: u8,
+}
+
+impl Cli {
+    /// Reject arguments clap doesn't catch itself: durations whose
+    /// components add up to more than can be represented, and combinations
+    /// involving global flags given before the subcommand, as in `sand
+    /// --porcelain ls`, which aren't checked against the subcommand's
+    /// `conflicts_with`.
+    pub fn check(self) -> Result<Self, clap::Error> {
+        let durations = match &self.command {
+            CliCommand::Start(args) => Some(&args.durations),
+            CliCommand::Set { duration, .. }
+            | CliCommand::Snooze { duration, .. }
+            | CliCommand::ParseDuration(duration) => Some(&duration.durations),
+            _ => None,
+        };
+        if let Some(Err(e)) = durations.map(sand::duration::checked_sum) {
+            return Err(Self::command().error(clap::error::ErrorKind::ValueValidation, e));
+        }
+        if let CliCommand::Ls(args) = &self.command {
+            if args.oneline && self.porcelain {
+                return Err(Self::command().error(
+                    clap::error::ErrorKind::ArgumentConflict,
+                    "the argument '--oneline' cannot be used with '--porcelain'",
+                ));
+            }
+        }
+        Ok(self)
+    }
+}
+
+/// One or more duration components, like `1h 30m`, which are added together.
+#[derive(Args)]
+pub struct DurationArgs {
+    #[clap(name = "DURATION", value_parser = sand::duration::parse_duration_component, num_args = 1..)]
+    pub durations: Vec<Duration>,
+}
+
+impl DurationArgs {
+    /// The components added up. [`Cli::check`] rejects totals too long to
+    /// represent, so this only saturates if that was skipped.
+    pub fn total(&self) -> Duration {
+        sand::duration::checked_sum(&self.durations).unwrap_or(Duration::MAX)
+    }
+}
+
+/// Options for a new timer, other than when it's due.
+#[derive(Args)]
+pub struct TimerOptions {
+    /// A label for the timer, shown in `ls` and in the notification
+    #[clap(short, long)]
+    pub name: Option<String>,
+    /// Urgency of the notification, overriding the configured default
+    #[clap(long, value_enum)]
+    pub urgency: Option<Urgency>,
+    /// The notification's icon, as an icon name like `alarm` or a path to an
+    /// image
+    #[clap(long)]
+    pub icon: Option<String>,
+    /// Don't show a notification when the timer elapses, just play the sound
+    #[clap(long)]
+    pub no_notify_on_elapse: bool,
+}
+
+#[derive(Args)]
+pub struct StartArgs {
+    /// Duration components, like `1h 30m`, which are added together. If
+    /// there are none, the config's `default_duration` is used.
+    #[clap(name = "DURATION", value_parser = sand::duration::parse_duration_component)]
+    pub durations: Vec<Duration>,
+    #[clap(flatten)]
+    pub options: TimerOptions,
+    /// Don't exit until the timer elapses, like `sand wait`
+    #[clap(long)]
+    pub wait: bool,
+    /// Once the timer elapses, run this shell command. Implies --wait.
+    ///
+    /// This is run by the `sand start` process rather than the daemon, so it
+    /// only happens if that process is still running when the timer goes
+    /// off. If the timer is cancelled, the command isn't run.
+    #[clap(long, value_name = "COMMAND")]
+    pub then: Option<String>,
+    /// Start the timer again each time it elapses, until it's cancelled
+    #[clap(long)]
+    pub repeat: bool,
+    /// With --repeat, stop repeating at this local time, like 17:00, rather
+    /// than going off after it
+    #[clap(long, alias = "repeat-until", value_name = "TIME", requires = "repeat")]
+    pub until: Option<TimeOfDay>,
+    /// Print only the new timer's ID, like `3`, for scripts to capture
+    #[clap(long)]
+    pub print_id: bool,
+    /// If a running timer has the same duration and name, or one is waiting
+    /// for the same --after timer, don't start another, and use that one
+    /// instead, eg for --wait
+    #[clap(long)]
+    pub unique: bool,
+    /// Don't start counting down until the timer with this ID elapses
+    #[clap(long, value_name = "TIMER_ID", value_parser = TimerId::parse)]
+    pub after: Option<TimerId>,
+    /// Run the timer in this process instead of the daemon, which doesn't
+    /// need to be running. Implies --wait.
+    #[clap(long, conflicts_with_all = ["repeat", "after"])]
+    pub no_daemon: bool,
+}
+
+impl StartArgs {
+    /// The duration given on the command line, or else `default_secs`. If
+    /// there's neither, there's nothing to start, so this exits.
+    pub fn duration_or(&self, default_secs: Option<u64>) -> Duration {
+        if !self.durations.is_empty() {
+            // Cli::check rejects totals too long to represent.
+            return sand::duration::checked_sum(&self.durations).unwrap_or(Duration::MAX);
+        }
+        default_secs.map(Duration::from_secs).unwrap_or_else(|| {
+            eprintln!("Error: no duration given and no default configured");
+            std::process::exit(1)
+        })
+    }
+}
+
+#[derive(Args)]
+pub struct AtArgs {
+    /// The local time to go off at, like 14:30. If it's already passed
+    /// today, the timer is set for tomorrow.
+    #[clap(name = "TIME")]
+    pub time: TimeOfDay,
+    #[clap(flatten)]
+    pub options: TimerOptions,
+}
+
+#[derive(Args)]
+pub struct LsArgs {
+    /// How to display the time remaining on each timer
+    #[clap(long, value_enum, default_value_t)]
+    pub duration_format: DurationFormat,
+    /// Only print timer IDs, one per line, for piping into other commands
+    #[clap(long, conflicts_with = "json")]
+    pub ids: bool,
+    /// Print the timers as JSON, including when each was started
+    #[clap(long)]
+    pub json: bool,
+    /// The order to list timers in
+    #[clap(long, value_enum, default_value_t)]
+    pub sort: SortKey,
+    /// Only list timers which are running, or paused. There's no elapsed,
+    /// since timers are removed as soon as they elapse
+    #[clap(long, value_name = "running|paused", value_parser = timer_state)]
+    pub state: Option<TimerState>,
+    /// Only list timers with a name containing this, ignoring case
+    #[clap(long, value_name = "TEXT")]
+    pub name_contains: Option<String>,
+    /// Only list timers with at least this long left, like `5m`
+    #[clap(long, value_name = "DURATION", value_parser = sand::duration::parse_duration_component)]
+    pub min_remaining: Option<Duration>,
+    /// Only list timers with at most this long left, like `5m`
+    #[clap(long, value_name = "DURATION", value_parser = sand::duration::parse_duration_component)]
+    pub max_remaining: Option<Duration>,
+    /// Don't print the line counting the timers after the list
+    #[clap(long)]
+    pub no_summary: bool,
+    /// Show when running timers are due, rather than the time remaining,
+    /// as a local time or an RFC 3339 timestamp
+    #[clap(long, value_enum, value_name = "FORMAT", num_args = 0..=1, default_missing_value = "local")]
+    pub due: Option<DueFormat>,
+    /// Print all the timers on one line, like `▶#1 00:04:12:000 | ⏸#2
+    /// 00:01:00:000`, for status bars. Prints nothing if there are none. If
+    /// NO_COLOR is set or stdout isn't a terminal, states are shown as R and P
+    #[clap(long, conflicts_with_all = ["json", "ids", "porcelain"])]
+    pub oneline: bool,
+}
+
+/// How to show when a timer is due.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum DueFormat {
+    /// 14:30:05
+    Local,
+    /// 2024-08-02T14:30:05+10:00
+    Rfc3339,
+}
+
+impl DueFormat {
+    pub fn format(self, due: SystemTime) -> String {
+        match self {
+            Self::Local => clock::format_local(due),
+            Self::Rfc3339 => clock::format_rfc3339(due),
+        }
+    }
+}
+
+impl LsArgs {
+    pub fn filter(&self) -> TimerFilter {
+        TimerFilter {
+            state: self.state,
+            name_contains: self.name_contains.clone(),
+            min_remaining_millis: self.min_remaining.map(|min| min.as_millis() as u64),
+            max_remaining_millis: self.max_remaining.map(|max| max.as_millis() as u64),
+        }
+    }
+
+    /// Daemons from before [`Command::Query`] only understand
+    /// [`Command::ListWith`], so that's sent unless it can't express the
+    /// filter.
+    pub fn list_command(&self) -> Command {
+        let filter = self.filter();
+        if filter == (TimerFilter { state: self.state, ..Default::default() }) {
+            Command::ListWith(ListArgs { sort_by: self.sort, state: self.state })
+        } else {
+            Command::Query { filter, sort_by: self.sort }
+        }
+    }
+}
+
+#[derive(Args)]
+pub struct WatchArgs {
+    #[clap(flatten)]
+    pub ls: LsArgs,
+    /// How long to wait between refreshes
+    #[clap(long, value_parser = sand::duration::parse_duration_component, default_value = "1s")]
+    pub interval: Duration,
+    /// Exit after this many refreshes, rather than running until interrupted
+    #[clap(long)]
+    pub count: Option<u64>,
+}
+
+/// Given in place of timer IDs, means every timer the daemon has.
+pub const ALL_TIMERS: &str = "all";
+
+/// Check a timer id argument, which may be a range or list, or
+/// [`ALL_TIMERS`], is valid before connecting to the daemon. It's parsed
+/// properly by [`TimerId::parse_many_or_quit`].
+fn timer_ids(arg: &str) -> Result<String, String> {
+    if arg == ALL_TIMERS {
+        return Ok(arg.to_string());
+    }
+    TimerId::parse_many(arg).map(|_| arg.to_string())
+}
+
+/// A [`TimerState`] for `ls --state`. Asking for elapsed timers, which
+/// there's no such state for, explains why rather than just listing the
+/// states there are.
+fn timer_state(arg: &str) -> Result<TimerState, String> {
+    if arg.eq_ignore_ascii_case("elapsed") {
+        return Err("elapsed timers aren't kept, they're removed as soon as they elapse".into());
+    }
+    <TimerState as clap::ValueEnum>::from_str(arg, true).map_err(|_| "expected running or paused".into())
+}
+
+#[derive(Subcommand)]
+pub enum CliCommand {
+    /// Start a new timer for the given duration
+    Start(StartArgs),
+    /// Start a new timer which goes off at the given time of day
+    At(AtArgs),
+    /// Start a new timer like the last one started, including its name
+    Again {
+        /// Use this name instead of the last timer's
+        #[clap(short, long)]
+        name: Option<String>,
+    },
+    /// List active timers
+    #[clap(alias = "list")]
+    Ls(LsArgs),
+    /// Continuously list active timers
+    ///
+    /// If the daemon restarts, this reconnects to it, giving up if it's
+    /// still down after about 25 seconds.
+    Watch(WatchArgs),
+    /// Pause the timers with the given IDs
+    ///
+    /// IDs may be given as ranges (`3-7`) or comma separated lists (`1,3,5`),
+    /// or as `all` for every timer.
+    Pause {
+        #[clap(name = "TIMER_ID", value_parser = timer_ids, required = true, num_args = 1..)]
+        timer_ids: Vec<String>,
+    },
+    /// Resume the timers with the given IDs
+    ///
+    /// IDs may be given as ranges (`3-7`) or comma separated lists (`1,3,5`),
+    /// or as `all` for every timer.
+    Resume {
+        #[clap(name = "TIMER_ID", value_parser = timer_ids, required = true, num_args = 1..)]
+        timer_ids: Vec<String>,
+        /// Leave the timers paused until this local time, like 08:00, and
+        /// resume them then. If it's already passed today, that's tomorrow.
+        #[clap(long, value_name = "TIME")]
+        at: Option<TimeOfDay>,
+    },
+    /// Pause the timers with the given IDs which are running, and resume
+    /// those which are paused
+    ///
+    /// IDs may be given as ranges (`3-7`) or comma separated lists (`1,3,5`),
+    /// or as `all` for every timer.
+    Toggle {
+        #[clap(name = "TIMER_ID", value_parser = timer_ids, required = true, num_args = 1..)]
+        timer_ids: Vec<String>,
+    },
+    /// Add time to a timer, or take it away
+    ///
+    /// Adjustments like `+30s` or `-1m` are added together, with no sign
+    /// meaning `+`. A timer can't be taken below zero remaining, so taking
+    /// away more time than it has left makes it go off straight away.
+    Extend {
+        #[clap(value_parser = TimerId::parse)]
+        timer_id: TimerId,
+        #[clap(
+            name = "ADJUSTMENT",
+            value_parser = sand::duration::parse_duration_adjustment,
+            allow_hyphen_values = true,
+            num_args = 1..,
+        )]
+        adjustments: Vec<i64>,
+    },
+    /// Set the time remaining on a timer, regardless of how much it had left
+    Set {
+        #[clap(value_parser = TimerId::parse)]
+        timer_id: TimerId,
+        #[clap(flatten)]
+        duration: DurationArgs,
+    },
+    /// Start a timer which has elapsed again, with the same ID and name
+    Snooze {
+        #[clap(value_parser = TimerId::parse)]
+        timer_id: TimerId,
+        #[clap(flatten)]
+        duration: DurationArgs,
+    },
+    /// Cancel the timers with the given IDs
+    ///
+    /// IDs may be given as ranges (`3-7`) or comma separated lists (`1,3,5`),
+    /// or as `all` for every timer.
+    Cancel {
+        #[clap(name = "TIMER_ID", value_parser = timer_ids, required_unless_present = "all", conflicts_with = "all", num_args = 1..)]
+        timer_ids: Vec<String>,
+        /// Cancel all timers, like giving `all` as the ID
+        #[clap(long)]
+        all: bool,
+        /// Don't ask for confirmation before cancelling all timers
+        #[clap(short, long)]
+        yes: bool,
+    },
+    /// Wait for the timer with the given ID to elapse
+    ///
+    /// Exits successfully once the timer elapses, or unsuccessfully if it's
+    /// cancelled or doesn't exist. Elapsed timers aren't kept, so that
+    /// includes one which has already elapsed.
+    Wait {
+        #[clap(value_parser = TimerId::parse)]
+        timer_id: TimerId,
+    },
+    /// Print how much time is left on all the timers, added up
+    ///
+    /// Running and paused timers are counted separately.
+    Stats,
+    /// List the audio formats the timer sound can be in
+    Formats,
+    /// Print where the timer sound was loaded from, and how long it plays for
+    ///
+    /// The length is "unknown" when the sound's format doesn't say without
+    /// decoding it all.
+    SoundInfo,
+    /// Print every timer as JSON, for `sand import`
+    ///
+    /// This includes each timer's time remaining, whether it's paused, and
+    /// everything it was started with, but not its ID.
+    Export,
+    /// Add the timers from a file written by `sand export`
+    ///
+    /// Running timers carry on with the time they had left when exported,
+    /// and paused ones stay paused. They're given new IDs.
+    Import {
+        /// The file to read, or `-` for standard input
+        file: PathBuf,
+    },
+    /// Print the directory to put a custom timer sound in
+    EditSound {
+        /// Create the directory if needed, and open it with xdg-open
+        #[clap(long)]
+        open: bool,
+    },
+    /// Print the number of milliseconds in a duration, as understood by `start`
+    ParseDuration(DurationArgs),
+    Version,
+    /// Print the configuration the daemon is using, as JSON
+    ///
+    /// This is the config file with defaults filled in, as of the daemon's
+    /// last reload.
+    Config,
+    /// Print the daemon's internal state, for bug reports
+    DebugDump,
+    /// Print timer lifecycle events as they happen, one JSON object per line
+    ///
+    /// Each line looks like an event log entry:
+    /// {"timestamp_millis":1722556800000,"event":"started","id":1,"name":"tea"}
+    /// where the event is one of started, paused, resumed, cancelled or
+    /// elapsed. Runs until interrupted, or the daemon exits.
+    Events,
+    /// Open a graphical view of the timers, if a viewer is installed
+    ///
+    /// The viewer is the program named by the SAND_GUI environment variable,
+    /// or `sand-gui`. It's run with the output of `sand events` on its
+    /// standard input.
+    Gui,
+    /// Check the daemon's timers for impossible states, for debugging
+    #[clap(hide = true)]
+    Fsck,
+
+    /// Launch the daemon
+    Daemon(DaemonArgs),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Cli, clap::Error> {
+        Cli::try_parse_from(std::iter::once("sand").chain(args.iter().copied()))?.check()
+    }
+
+    #[test]
+    fn state_filter_explains_elapsed() {
+        assert!(parse(&["ls", "--state", "paused"]).is_ok());
+        assert!(parse(&["ls", "--state", "Running"]).is_ok());
+        let err = parse(&["ls", "--state", "elapsed"]).err().unwrap().to_string();
+        assert!(err.contains("elapsed timers aren't kept"), "{err}");
+        assert!(parse(&["ls", "--state", "done"]).is_err());
+    }
+
+    #[test]
+    fn oneline_conflicts_with_porcelain() {
+        assert!(parse(&["ls", "--oneline"]).is_ok());
+        assert!(parse(&["--porcelain", "ls"]).is_ok());
+        assert!(parse(&["ls", "--oneline", "--porcelain"]).is_err());
+        assert!(parse(&["--porcelain", "ls", "--oneline"]).is_err());
+    }
+
+    #[test]
+    fn durations_too_long_to_add_up() {
+        assert!(parse(&["start", "500000000000h", "1h"]).is_ok());
+        assert!(parse(&["start", "5000000000000000h", "5000000000000000h"]).is_err());
+        assert!(parse(&["set", "1", "5000000000000000h", "5000000000000000h"]).is_err());
+    }
+}
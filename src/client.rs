@@ -1,49 +1,243 @@
-use std::io::{self, BufRead, BufReader, LineWriter, Write};
-use std::path::PathBuf;
+use std::io::{self, BufRead, BufReader, IsTerminal, LineWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::net::TcpStream;
 use std::os::unix::net::UnixStream;
-use std::process::exit;
-use std::time::Duration;
+use std::process::{self, exit};
+use std::time::{Duration, SystemTime};
 
-use dirs;
 use serde::Deserialize;
 
-use crate::sand::cli::StartArgs;
+use sand::message::{self, AddTimerResponse, AdjustTimerResponse, AgainResponse, Command, DebugDumpResponse, ExportResponse, ExportedTimer, FormatsResponse, FsckResponse, GetConfigResponse, ListResponse, PauseTimerResponse, ResumeTimerResponse, SnoozeResponse, SoundInfoResponse, StatsResponse, SubscribeResponse, TimerFilter, ToggleTimerResponse, WaitResponse};
+use sand::audio;
+use sand::clock;
+use sand::event_log::EventLogEntry;
+use sand::socket;
+use sand::duration::{DurationExt, DurationFormat, UnitLabels};
+use sand::timer::{StartSpec, TimerId, TimerInfoForClient, TimerState, MAX_DURATION};
+
+use crate::cli::{AtArgs, LsArgs, StartArgs, TimerOptions, WatchArgs};
 use crate::cli;
-use crate::sand::message::{self, AddTimerResponse, Command, ListResponse, PauseTimerResponse, ResumeTimerResponse};
-use crate::sand::duration::DurationExt;
-use crate::sand::timer::{TimerId, TimerInfoForClient};
+use crate::logger;
+use crate::output::{self, Printer};
 
-fn get_sock_path() -> Option<PathBuf> {
-    if let Ok(path) = std::env::var("SAND_SOCK_PATH") {
-        Some(path.into())
-    } else {
-        Some(dirs::runtime_dir()?.join("sand.sock"))
+/// Where to find the daemon.
+#[derive(Clone)]
+enum DaemonAddr {
+    Unix(PathBuf),
+    /// A `host:port` to connect to over TCP.
+    Tcp(String),
+}
+
+/// `SAND_ADDR=tcp://host:port` connects to a daemon listening on TCP (see
+/// `SAND_LISTEN`). Otherwise we use the daemon's Unix socket. Which it was
+/// and why is logged.
+fn get_daemon_addr() -> io::Result<DaemonAddr> {
+    let Ok(addr) = std::env::var("SAND_ADDR") else {
+        let (path, source) = socket::sock_path_with_source()?;
+        log::info!("using the socket {} from {source}", path.display());
+        return Ok(DaemonAddr::Unix(path));
+    };
+    match addr.strip_prefix("tcp://") {
+        Some(host_port) => {
+            log::info!("using tcp://{host_port} from SAND_ADDR");
+            Ok(DaemonAddr::Tcp(host_port.to_string()))
+        }
+        None => {
+            eprintln!("SAND_ADDR should look like tcp://127.0.0.1:7654, got {addr:?}");
+            exit(1)
+        }
     }
 }
 
-struct DaemonConnection {
-    read: BufReader<UnixStream>,
-    write: LineWriter<UnixStream>,
+/// A stream to the daemon over either of the transports it supports.
+enum Stream {
+    Unix(UnixStream),
+    Tcp(TcpStream),
 }
 
+impl Stream {
+    fn connect(addr: DaemonAddr) -> io::Result<Self> {
+        match addr {
+            DaemonAddr::Unix(path) => {
+                log::debug!("connecting to {}", path.display());
+                UnixStream::connect(path).map(Self::Unix)
+            }
+            DaemonAddr::Tcp(host_port) => {
+                log::debug!("connecting to tcp://{host_port}");
+                TcpStream::connect(host_port).map(Self::Tcp)
+            }
+        }
+    }
+
+    fn try_clone(&self) -> io::Result<Self> {
+        match self {
+            Self::Unix(stream) => stream.try_clone().map(Self::Unix),
+            Self::Tcp(stream) => stream.try_clone().map(Self::Tcp),
+        }
+    }
+
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        match self {
+            Self::Unix(stream) => stream.set_read_timeout(timeout),
+            Self::Tcp(stream) => stream.set_read_timeout(timeout),
+        }
+    }
+}
+
+impl Read for Stream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Self::Unix(stream) => stream.read(buf),
+            Self::Tcp(stream) => stream.read(buf),
+        }
+    }
+}
+
+impl Write for Stream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Self::Unix(stream) => stream.write(buf),
+            Self::Tcp(stream) => stream.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Self::Unix(stream) => stream.flush(),
+            Self::Tcp(stream) => stream.flush(),
+        }
+    }
+}
+
+/// A connection to the daemon. This is normally over its Unix socket, but
+/// tests can use in-memory buffers instead.
+struct DaemonConnection<R = Stream, W: Write = Stream> {
+    read: BufReader<R>,
+    write: LineWriter<W>,
+    /// Where to connect again if the daemon goes away, if reconnecting is
+    /// enabled. See [`DaemonConnection::with_reconnect`].
+    reconnect_to: Option<DaemonAddr>,
+}
+
+/// How long to wait for the daemon to respond, unless overridden by
+/// `SAND_TIMEOUT`.
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How long to wait for the daemon to respond before giving up, or `None`
+/// to wait forever. Set by `SAND_TIMEOUT`, where `0` means no timeout.
+fn response_timeout() -> Option<Duration> {
+    let timeout = match std::env::var("SAND_TIMEOUT") {
+        Ok(timeout) => sand::duration::parse_duration_component(&timeout).unwrap_or_else(|e| {
+            eprintln!("Ignoring SAND_TIMEOUT={timeout:?}: {e}");
+            DEFAULT_TIMEOUT
+        }),
+        Err(_) => DEFAULT_TIMEOUT,
+    };
+    Some(timeout).filter(|timeout| !timeout.is_zero())
+}
+
+/// How many times to try connecting to the daemon again after it goes away,
+/// waiting twice as long before each attempt as the last. Altogether that's
+/// about 25 seconds, enough for the daemon to be restarted.
+const RECONNECT_ATTEMPTS: u32 = 8;
+const RECONNECT_FIRST_DELAY: Duration = Duration::from_millis(100);
+
 impl DaemonConnection {
-    fn new(sock_path: PathBuf) -> io::Result<Self> {
-        let stream = UnixStream::connect(sock_path)?;
+    fn new(addr: DaemonAddr) -> io::Result<Self> {
+        let stream = Stream::connect(addr)?;
+        stream.set_read_timeout(response_timeout())?;
+        Ok(Self::from_parts(stream.try_clone()?, stream))
+    }
+
+    /// Wait forever for responses, for commands like `wait` which are
+    /// expected to take a long time.
+    fn disable_timeout(&self) -> io::Result<()> {
+        self.read.get_ref().set_read_timeout(None)
+    }
+
+    /// Connect to the daemon at `addr` again if it goes away, as when it's
+    /// restarted, so long running commands like `watch` survive it. Only
+    /// [`DaemonConnection::request`] reconnects.
+    fn with_reconnect(self, addr: DaemonAddr) -> Self {
+        Self { reconnect_to: Some(addr), ..self }
+    }
+
+    /// Send `cmd` and receive its response. If reconnecting is enabled and
+    /// the daemon has gone away, it's dialled again with exponential backoff
+    /// and `cmd` is sent again on the new connection.
+    fn request<T: for<'de> Deserialize<'de>>(&mut self, cmd: Command) -> io::Result<T> {
+        match self.send(cmd.clone()).and_then(|()| self.recv()) {
+            Err(e) if self.reconnect_to.is_some() && connection_lost(&e) => {
+                self.reconnect(e)?;
+                self.send(cmd)?;
+                self.recv()
+            }
+            result => result,
+        }
+    }
 
-        let read = BufReader::new(stream.try_clone()?);
-        let write = LineWriter::new(stream);
+    /// Replace the connection with a new one, giving up after
+    /// [`RECONNECT_ATTEMPTS`] with the error from the last attempt.
+    fn reconnect(&mut self, lost: io::Error) -> io::Result<()> {
+        let addr = self.reconnect_to.clone().expect("BUG: reconnecting without an address");
+        eprintln!("Lost the connection to the daemon ({lost}), reconnecting...");
+        let mut delay = RECONNECT_FIRST_DELAY;
+        let mut last_error = lost;
+        for attempt in 1..=RECONNECT_ATTEMPTS {
+            std::thread::sleep(delay);
+            match Self::new(addr.clone()) {
+                Ok(conn) => {
+                    *self = conn.with_reconnect(addr);
+                    return Ok(());
+                }
+                Err(e) => {
+                    log::info!("reconnection attempt {attempt} failed: {e}");
+                    last_error = e;
+                }
+            }
+            delay *= 2;
+        }
+        Err(io::Error::new(
+            last_error.kind(),
+            format!("gave up reconnecting after {RECONNECT_ATTEMPTS} attempts: {last_error}"),
+        ))
+    }
+}
 
-        Ok(Self { read, write })
+impl<R: Read, W: Write> DaemonConnection<R, W> {
+    fn from_parts(read: R, write: W) -> Self {
+        Self { read: BufReader::new(read), write: LineWriter::new(write), reconnect_to: None }
     }
 
     fn send(&mut self, cmd: Command) -> io::Result<()> {
         let str = serde_json::to_string(&cmd).expect("failed to serialize Command {cmd}");
+        log::debug!("> {str}");
         writeln!(self.write, "{str}")
     }
 
     fn recv<T: for<'de> Deserialize<'de>>(&mut self) -> io::Result<T> {
         let mut recv_buf = String::with_capacity(128);
-        self.read.read_line(&mut recv_buf)?;
+        loop {
+            recv_buf.clear();
+            let read = self.read.read_line(&mut recv_buf).map_err(|e| match e.kind() {
+                io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut => {
+                    io::Error::new(io::ErrorKind::TimedOut, "the daemon didn't respond in time")
+                }
+                _ => e,
+            })?;
+            if read == 0 {
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "the daemon closed the connection without responding",
+                ));
+            }
+            if recv_buf.trim_end() == message::KEEPALIVE_FRAME {
+                log::trace!("< {}", recv_buf.trim_end());
+                continue;
+            }
+            log::debug!("< {}", recv_buf.trim_end());
+            break;
+        }
         let resp: T = serde_json::from_str(&recv_buf).expect(
             "Bug: failed to deserialize response from daemon"
         );
@@ -51,100 +245,922 @@ impl DaemonConnection {
     }
 }
 
-fn display_timer_info(timers: &[TimerInfoForClient]) -> String {
-    if timers.len() == 0 {
-        "No timers running.".into()
-    } else {
+fn display_timer_info(timers: &[TimerInfoForClient], args: &LsArgs, labels: &UnitLabels, out: &Printer) -> String {
+    if args.json {
+        serde_json::to_string_pretty(timers).expect("failed to serialize timers")
+    } else if args.ids {
         timers.iter()
-            .map(TimerInfoForClient::display)
+            .map(|timer| timer.id().0.to_string())
             .intersperse("\n".to_string())
             .collect()
+    } else if out.porcelain() {
+        timers.iter()
+            .map(output::porcelain_timer)
+            .intersperse("\n".to_string())
+            .collect()
+    } else if args.oneline {
+        let plain = output::plain_symbols();
+        output::oneline(timers.iter().map(|timer| output::oneline_timer(timer, remaining_column(timer, args, labels), plain)))
+    } else if timers.is_empty() {
+        if args.filter() != (TimerFilter { state: args.state, ..Default::default() }) {
+            return "No matching timers.".into();
+        }
+        match args.state {
+            None => "No timers running.".into(),
+            Some(TimerState::Running) => "No running timers.".into(),
+            Some(TimerState::Paused) => "No paused timers.".into(),
+        }
+    } else {
+        // Names come last, so long ones are what gets cut off. They can be
+        // seen in full with --json.
+        let width = output::terminal_width();
+        let rows = timers.iter()
+            .map(|timer| timer.columns(remaining_column(timer, args, labels)))
+            .collect();
+        let mut lines: Vec<String> = output::align_columns(rows).into_iter()
+            .map(|line| match width {
+                Some(width) => output::truncate_to_width(line, width),
+                None => line,
+            })
+            .collect();
+        if !args.no_summary {
+            lines.push(summary_line(timers, args.duration_format, labels));
+        }
+        lines.join("\n")
     }
 }
 
-fn exit_timer_not_found(id: TimerId) -> ! {
-    println!("Timer {id} not found.");
+/// The time remaining on `timer` as `ls` shows it, or when it's due, with
+/// `--due`.
+fn remaining_column(timer: &TimerInfoForClient, args: &LsArgs, labels: &UnitLabels) -> String {
+    let remaining = Duration::from_millis(timer.remaining_millis());
+    match (args.due, timer.state()) {
+        (Some(due), TimerState::Running) => format!("due {}", due.format(SystemTime::now() + remaining)),
+        _ => args.duration_format.format_with(remaining, labels),
+    }
+}
+
+/// How many timers are in each state, and when the next one is due, eg
+/// `3 running, 1 paused, next due in 00:04:12:000`.
+fn summary_line(timers: &[TimerInfoForClient], format: DurationFormat, labels: &UnitLabels) -> String {
+    let running: Vec<&TimerInfoForClient> = timers.iter()
+        .filter(|timer| timer.state() == TimerState::Running)
+        .collect();
+    let paused = timers.len() - running.len();
+    let mut summary = format!("{} running, {paused} paused", running.len());
+    if let Some(next_due) = running.iter().map(|timer| timer.remaining_millis()).min() {
+        summary.push_str(&format!(", next due in {}", format.format_with(Duration::from_millis(next_due), labels)));
+    }
+    summary
+}
+
+fn print_adjusted(out: &Printer, id: TimerId, resp: AdjustTimerResponse) -> io::Result<()> {
+    match resp {
+        AdjustTimerResponse::Ok { remaining_millis } => {
+            out.adjusted(id, remaining_millis);
+            Ok(())
+        }
+        AdjustTimerResponse::TimerNotFound => {
+            out.not_found(id);
+            exit(1)
+        }
+        AdjustTimerResponse::InvalidDuration { duration } => {
+            let duration = Duration::from_millis(duration);
+            eprintln!("Can't leave timer {id} with {}: {}.", duration.format_colon_separated(), why_invalid(duration));
+            exit(1)
+        }
+    }
+}
+
+/// Ask the user to confirm cancelling every timer. Refuses outright if
+/// there's nobody at the terminal to ask.
+fn confirm_cancel_all(n_timers: usize) -> io::Result<bool> {
+    let stdin = io::stdin();
+    if !stdin.is_terminal() {
+        eprintln!("Refusing to cancel all timers without confirmation. Pass --yes to skip the prompt.");
+        return Ok(false);
+    }
+    print!("Cancel {n_timers} timers? [y/N] ");
+    io::stdout().flush()?;
+    let mut answer = String::new();
+    stdin.read_line(&mut answer)?;
+    Ok(matches!(answer.trim().to_lowercase().as_str(), "y" | "yes"))
+}
+
+/// Whether timer ID arguments include [`cli::ALL_TIMERS`].
+fn means_all_timers(args: &[String]) -> bool {
+    args.iter().any(|arg| arg == cli::ALL_TIMERS)
+}
+
+/// The IDs of every timer the daemon has, in ID order.
+fn all_timer_ids<R: Read, W: Write>(conn: &mut DaemonConnection<R, W>) -> io::Result<Vec<TimerId>> {
+    conn.send(Command::List)?;
+    let ListResponse::Ok { timers } = conn.recv::<ListResponse>()?;
+    let mut ids: Vec<TimerId> = timers.iter().map(TimerInfoForClient::id).collect();
+    ids.sort_by_key(|id| id.0);
+    Ok(ids)
+}
+
+/// The daemon's configured unit labels, if `format` has any use for them.
+fn unit_labels<R: Read, W: Write>(conn: &mut DaemonConnection<R, W>, format: DurationFormat) -> io::Result<UnitLabels> {
+    if format != DurationFormat::Compact {
+        return Ok(UnitLabels::default());
+    }
+    conn.send(Command::GetConfig)?;
+    let GetConfigResponse::Ok(config) = conn.recv::<GetConfigResponse>()?;
+    Ok(config.unit_labels)
+}
+
+/// The timers meant by timer ID arguments, asking the daemon for them all
+/// if one of the arguments is [`cli::ALL_TIMERS`].
+fn resolve_timer_ids<R: Read, W: Write>(conn: &mut DaemonConnection<R, W>, args: &[String]) -> io::Result<Vec<TimerId>> {
+    if means_all_timers(args) {
+        all_timer_ids(conn)
+    } else {
+        Ok(TimerId::parse_many_or_quit(args))
+    }
+}
+
+pub fn timer_spec(dur: Duration, options: TimerOptions) -> StartSpec {
+    let TimerOptions { name, urgency, icon, no_notify_on_elapse } = options;
+    StartSpec { name, urgency, icon, no_notify: no_notify_on_elapse, ..StartSpec::new(dur) }
+}
+
+/// Start a timer, saying so, or with `print_id`, only printing its id.
+fn start_timer(conn: &mut DaemonConnection, out: &Printer, spec: StartSpec, print_id: bool) -> io::Result<TimerId> {
+    let dur = spec.duration();
+    let name = spec.name.clone();
+    let after = spec.after;
+    conn.send(Command::AddTimer(spec))?;
+    match conn.recv::<AddTimerResponse>()? {
+        AddTimerResponse::Ok { id } | AddTimerResponse::AlreadyExists { id } if print_id => {
+            out.id(id);
+            Ok(id)
+        }
+        AddTimerResponse::Ok { id } => {
+            out.timer_created(id, dur, name.as_deref());
+            if let Some(after) = after {
+                out.waiting(id, after);
+            }
+            Ok(id)
+        }
+        AddTimerResponse::LimitReached { max } => exit_limit_reached(max),
+        AddTimerResponse::AfterNotFound { after } => {
+            eprintln!("Can't start a timer after timer {after}: it doesn't exist.");
+            exit(1)
+        }
+        AddTimerResponse::InvalidDuration { .. } => {
+            eprintln!("Can't start a timer for {}: {}.", dur.format_colon_separated(), why_invalid(dur));
+            exit(1)
+        }
+        AddTimerResponse::AlreadyExists { id } => {
+            out.already_exists(id);
+            Ok(id)
+        }
+    }
+}
+
+/// Return once the timer elapses. Exits unsuccessfully if it's cancelled or
+/// doesn't exist.
+fn wait_for(conn: &mut DaemonConnection, out: &Printer, timer_id: TimerId) -> io::Result<()> {
+    conn.disable_timeout()?;
+    conn.send(Command::Wait(timer_id))?;
+    match conn.recv::<WaitResponse>()? {
+        WaitResponse::Elapsed => {
+            out.elapsed(timer_id);
+            Ok(())
+        }
+        WaitResponse::Cancelled => {
+            out.wait_cancelled(timer_id);
+            exit(1)
+        }
+        WaitResponse::TimerNotFound => {
+            out.not_found(timer_id);
+            exit(1)
+        }
+    }
+}
+
+/// Run `command` with the shell, exiting with its status if it fails.
+pub fn run_then(command: &str) -> io::Result<()> {
+    let status = process::Command::new("sh").arg("-c").arg(command).status()?;
+    if !status.success() {
+        eprintln!("--then command failed: {status}");
+        exit(status.code().unwrap_or(1))
+    }
+    Ok(())
+}
+
+/// Subscribe to timer lifecycle events, calling `on_event` with each one
+/// until the daemon goes away or `on_event` fails.
+fn subscribe<R, W, F>(conn: &mut DaemonConnection<R, W>, mut on_event: F) -> io::Result<()>
+where
+    R: Read,
+    W: Write,
+    F: FnMut(EventLogEntry) -> io::Result<()>,
+{
+    conn.send(Command::Subscribe)?;
+    match conn.recv::<SubscribeResponse>()? {
+        SubscribeResponse::Subscribed => {}
+        SubscribeResponse::Unsupported => {
+            return Err(io::Error::other("the daemon doesn't support subscribing to events"));
+        }
+    }
+    loop {
+        match conn.recv::<EventLogEntry>() {
+            Ok(entry) => on_event(entry)?,
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(()),
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Write `entry` to `out` as a line of JSON, as `sand events` prints it.
+fn write_event(out: &mut impl Write, entry: &EventLogEntry) -> io::Result<()> {
+    let line = serde_json::to_string(entry).expect("failed to serialize EventLogEntry");
+    writeln!(out, "{line}")
+}
+
+const DEFAULT_GUI: &str = "sand-gui";
+
+fn launch_gui(conn: &mut DaemonConnection) -> io::Result<()> {
+    let viewer = std::env::var("SAND_GUI").unwrap_or_else(|_| DEFAULT_GUI.to_string());
+    let spawned = process::Command::new(&viewer).stdin(process::Stdio::piped()).spawn();
+    let mut child = match spawned {
+        Ok(child) => child,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            eprintln!("No timer viewer found: {viewer:?} isn't installed.");
+            eprintln!("sand doesn't come with a graphical view of its own. Any program which");
+            eprintln!("reads the output of `sand events` on its standard input can be used;");
+            eprintln!("set SAND_GUI to its name or path. See `sand events --help` for the format.");
+            exit(1)
+        }
+        Err(e) => return Err(e),
+    };
+    let mut stdin = child.stdin.take().expect("viewer stdin is piped");
+    let streamed = subscribe(conn, |entry| write_event(&mut stdin, &entry));
+    // Let the viewer see the stream end.
+    drop(stdin);
+    match streamed {
+        Err(e) if e.kind() == io::ErrorKind::BrokenPipe => {}
+        other => other?,
+    }
+    let status = child.wait()?;
+    if !status.success() {
+        eprintln!("{viewer} exited with {status}");
+        exit(status.code().unwrap_or(1))
+    }
+    Ok(())
+}
+
+/// Timers as written by `sand export`, from `path`, or standard input if
+/// it's `-`.
+fn read_exported(path: &Path) -> io::Result<Vec<ExportedTimer>> {
+    let json = if path == Path::new("-") {
+        io::read_to_string(io::stdin())?
+    } else {
+        std::fs::read_to_string(path)?
+    };
+    serde_json::from_str(&json).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+/// Why the daemon refused a timer `duration` long.
+fn why_invalid(duration: Duration) -> String {
+    if duration.is_zero() {
+        "timers can't be zero length".into()
+    } else {
+        format!("that's longer than the limit of {}", MAX_DURATION.format_colon_separated())
+    }
+}
+
+fn exit_limit_reached(max: usize) -> ! {
+    eprintln!("Can't start a new timer: the daemon already has the maximum of {max} timers.");
+    eprintln!("Cancel some timers, or raise SAND_MAX_TIMERS for the daemon.");
     exit(1)
 }
 
-pub fn main(cmd: cli::CliCommand) -> io::Result<()> {
-    let Some(sock_path) = get_sock_path() else {
-        eprintln!("socket not provided and runtime directory does not exist.");
-        eprintln!("no socket to use.");
-        std::process::exit(1)
+/// Exit with a failure status if any of the timers in a multi-id command
+/// couldn't be acted on.
+fn exit_unless(all_ok: bool) -> io::Result<()> {
+    if !all_ok {
+        exit(1)
+    }
+    Ok(())
+}
+
+/// Show the user where to put their own timer sound, optionally opening it in
+/// their file manager. Doesn't need the daemon.
+pub fn edit_sound(open: bool) -> io::Result<()> {
+    let (Some(dir), Some(sound_path)) = (audio::sand_user_data_dir(), audio::xdg_sound_path()) else {
+        eprintln!("Couldn't determine the user data directory.");
+        exit(1)
     };
+    println!("{}", dir.display());
+    if !dir.exists() {
+        if !open {
+            eprintln!("(doesn't exist yet; pass --open to create it)");
+            return Ok(());
+        }
+        std::fs::create_dir_all(&dir)?;
+    }
+    eprintln!("Put your sound at {} and the daemon will pick it up.", sound_path.display());
+    if open {
+        let status = std::process::Command::new("xdg-open").arg(&dir).status()?;
+        if !status.success() {
+            eprintln!("xdg-open exited with {status}");
+            exit(1)
+        }
+    }
+    Ok(())
+}
+
+/// Something that went wrong talking to the daemon.
+#[derive(Debug)]
+enum ClientError {
+    /// There's no daemon to connect to.
+    DaemonUnavailable(io::Error),
+    /// The daemon went away partway through.
+    ConnectionLost(io::Error),
+    Io(io::Error),
+}
+
+/// Whether `e` means the daemon went away partway through.
+fn connection_lost(e: &io::Error) -> bool {
+    use io::ErrorKind as K;
+    matches!(e.kind(), K::BrokenPipe | K::ConnectionReset | K::ConnectionAborted | K::UnexpectedEof)
+}
+
+impl From<io::Error> for ClientError {
+    fn from(e: io::Error) -> Self {
+        use io::ErrorKind as K;
+        match e.kind() {
+            K::NotFound | K::ConnectionRefused | K::AddrNotAvailable => Self::DaemonUnavailable(e),
+            _ if connection_lost(&e) => Self::ConnectionLost(e),
+            _ => Self::Io(e),
+        }
+    }
+}
+
+impl std::fmt::Display for ClientError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::DaemonUnavailable(e) => write!(
+                f,
+                "couldn't connect to the daemon ({e}). Is it running? \
+                 With systemd, try `systemctl --user start sand.socket`."
+            ),
+            Self::ConnectionLost(e) => write!(f, "lost the connection to the daemon: {e}"),
+            Self::Io(e) => write!(f, "error communicating with the daemon: {e}"),
+        }
+    }
+}
+
+impl ClientError {
+    /// Distinct exit codes, so scripts can tell a missing daemon apart from
+    /// a command which failed.
+    fn exit_code(&self) -> i32 {
+        match self {
+            Self::Io(_) => 1,
+            Self::DaemonUnavailable(_) => 3,
+            Self::ConnectionLost(_) => 4,
+        }
+    }
+
+    fn exit(self) -> ! {
+        eprintln!("Error: {self}");
+        exit(self.exit_code())
+    }
+}
+
+/// How much the client logs: with `-v`, how it finds and talks to the
+/// daemon, including each message sent and received, and with `-vv`,
+/// keepalives too. Without `-v`, `SAND_CLIENT_LOG` sets it, like the daemon's
+/// `RUST_LOG`.
+fn log_level(verbose: u8) -> log::LevelFilter {
+    match verbose {
+        0 => logger::level_from_env("SAND_CLIENT_LOG"),
+        1 => log::LevelFilter::Debug,
+        _ => log::LevelFilter::Trace,
+    }
+}
+
+pub fn main(cmd: cli::CliCommand, porcelain: bool, verbose: u8) -> io::Result<()> {
+    logger::init(log_level(verbose));
+    let addr = get_daemon_addr().unwrap_or_else(|e| {
+        eprintln!("Error finding the daemon's socket: {e}");
+        std::process::exit(1)
+    });
     
-    let mut conn = match DaemonConnection::new(sock_path) {
-        Ok(conn) => conn,
-        Err(e) => {
-            eprintln!("Error establishing connection with daemon: {e}");
-            std::process::exit(1);
-        },
-    };
+    let reconnect_to = matches!(cmd, cli::CliCommand::Watch(_)).then(|| addr.clone());
+    let mut conn = DaemonConnection::new(addr)
+        .unwrap_or_else(|e| ClientError::from(e).exit());
+    log::info!("connected");
+    if let Some(addr) = reconnect_to {
+        conn = conn.with_reconnect(addr);
+    }
+
+    run(&mut conn, cmd, &Printer::new(porcelain)).or_else(|e| ClientError::from(e).exit())
+}
 
+fn run(conn: &mut DaemonConnection, cmd: cli::CliCommand, out: &Printer) -> io::Result<()> {
     // TODO: make sure to parse Error Messages. we should prob move sending, 
     // receiving, and parsing fully into DaemonConnection, and present
     // Command -> Result<CmdResponse, Error> type api
     match cmd {
-        cli::CliCommand::Start(StartArgs{ durations }) => {
-            let dur: Duration = durations.iter().sum();
-            conn.send(Command::AddTimer { duration: dur.as_millis() as u64 })?;
-            let AddTimerResponse::Ok { id } = conn.recv::<AddTimerResponse>()?;
-            
-            let dur_string = dur.format_colon_separated();
-            println!("Timer {id} created for {dur_string}.");
-            Ok(())
+        cli::CliCommand::Start(args) => {
+            let dur = if args.durations.is_empty() {
+                conn.send(Command::GetConfig)?;
+                let GetConfigResponse::Ok(config) = conn.recv::<GetConfigResponse>()?;
+                args.duration_or(config.default_duration)
+            } else {
+                args.duration_or(None)
+            };
+            let StartArgs { options, wait, then, repeat, until, after, unique, print_id, .. } = args;
+            let repeat_until = until.map(|time| {
+                let until = clock::next_local_time(SystemTime::now(), time).unwrap_or_else(|e| {
+                    eprintln!("Can't repeat until {}:{:02}: {e}", time.hour, time.minute);
+                    exit(1)
+                });
+                clock::unix_millis(until)
+            });
+            let spec = StartSpec { repeat, repeat_until, after, unique, ..timer_spec(dur, options) };
+            let id = start_timer(conn, out, spec, print_id)?;
+            if wait || then.is_some() {
+                wait_for(conn, out, id)?;
+            }
+            match then {
+                Some(command) => run_then(&command),
+                None => Ok(()),
+            }
+        }
+        cli::CliCommand::At(AtArgs { time, options }) => {
+            let now = SystemTime::now();
+            let due = clock::next_local_time(now, time).unwrap_or_else(|e| {
+                eprintln!("Can't set a timer for {}:{:02}: {e}", time.hour, time.minute);
+                exit(1)
+            });
+            let dur = due.duration_since(now).unwrap_or_default();
+            start_timer(conn, out, timer_spec(dur, options), false).map(|_| ())
         }
-        cli::CliCommand::Ls => {
-            conn.send(Command::List)?;
+        cli::CliCommand::Again { name } => {
+            conn.send(Command::Again { name })?;
+            match conn.recv::<AgainResponse>()? {
+                AgainResponse::Ok { id, duration, name } => {
+                    out.timer_created(id, Duration::from_millis(duration), name.as_deref());
+                    Ok(())
+                }
+                AgainResponse::NonePrevious => {
+                    eprintln!("No timer has been started yet.");
+                    exit(1)
+                }
+                AgainResponse::LimitReached { max } => exit_limit_reached(max),
+                AgainResponse::InvalidStoredDuration { duration } => {
+                    let why = why_invalid(Duration::from_millis(duration));
+                    let duration = Duration::from_millis(duration).format_colon_separated();
+                    eprintln!("The last timer started was for {duration}, which is no longer a valid duration: {why}.");
+                    exit(1)
+                }
+            }
+        }
+        cli::CliCommand::Ls(ref args) => {
+            let labels = unit_labels(conn, args.duration_format)?;
+            conn.send(args.list_command())?;
             let ListResponse::Ok {ref timers } = conn.recv::<ListResponse>()?;
-            println!("{}", display_timer_info(timers));
+            let output = display_timer_info(timers, args, &labels, out);
+            if !output.is_empty() {
+                println!("{output}");
+            }
             Ok(())
         }
-        cli::CliCommand::Pause { timer_id } => {
-            let timer_id = TimerId::parse_or_quit(&timer_id);
-            conn.send(Command::PauseTimer(timer_id))?;
-            match conn.recv::<PauseTimerResponse>()? {
-                PauseTimerResponse::Ok => {
-                    println!("Paused timer {timer_id}.");
-                    Ok(())
-                },
-                PauseTimerResponse::TimerNotFound => exit_timer_not_found(timer_id),
-                PauseTimerResponse::AlreadyPaused => {
-                    println!("Timer {timer_id} is already paused.");
-                    exit(1);
-                },
+        cli::CliCommand::Watch(WatchArgs { ref ls, interval, count }) => {
+            let labels = unit_labels(conn, ls.duration_format)?;
+            let mut refreshes = 0;
+            while count.is_none_or(|count| refreshes < count) {
+                if refreshes > 0 {
+                    std::thread::sleep(interval);
+                }
+                let ListResponse::Ok { ref timers } = conn.request::<ListResponse>(ls.list_command())?;
+                if !out.porcelain() {
+                    // Clear the screen and move the cursor to the top left
+                    print!("\x1b[2J\x1b[H");
+                }
+                println!("{}", display_timer_info(timers, ls, &labels, out));
+                io::stdout().flush()?;
+                refreshes += 1;
             }
+            Ok(())
         }
-        cli::CliCommand::Resume { timer_id } => {
-            let timer_id = TimerId::parse_or_quit(&timer_id);
-            conn.send(Command::ResumeTimer(timer_id))?;
+        cli::CliCommand::Pause { timer_ids } => {
+            let mut all_ok = true;
+            for timer_id in resolve_timer_ids(conn, &timer_ids)? {
+                conn.send(Command::PauseTimer(timer_id))?;
+                all_ok &= match conn.recv::<PauseTimerResponse>()? {
+                    PauseTimerResponse::Ok => {
+                        out.paused(timer_id);
+                        true
+                    },
+                    PauseTimerResponse::TimerNotFound => out.not_found(timer_id),
+                    PauseTimerResponse::AlreadyPaused => {
+                        out.already_paused(timer_id);
+                        false
+                    },
+                };
+            }
+            exit_unless(all_ok)
+        }
+        cli::CliCommand::Resume { timer_ids, at } => {
             use ResumeTimerResponse as Resp;
-            match conn.recv::<ResumeTimerResponse>()? {
-                Resp::Ok => {
-                    println!("Resumed timer {timer_id}.");
+            let at = at.map(|time| {
+                clock::next_local_time(SystemTime::now(), time).unwrap_or_else(|e| {
+                    eprintln!("Can't resume at {}:{:02}: {e}", time.hour, time.minute);
+                    exit(1)
+                })
+            });
+            let mut all_ok = true;
+            for timer_id in resolve_timer_ids(conn, &timer_ids)? {
+                match at {
+                    Some(at) => conn.send(Command::ResumeAt { id: timer_id, at_millis: clock::unix_millis(at) })?,
+                    None => conn.send(Command::ResumeTimer(timer_id))?,
+                }
+                all_ok &= match conn.recv::<ResumeTimerResponse>()? {
+                    Resp::Ok => {
+                        match at {
+                            Some(at) => out.resume_scheduled(timer_id, at),
+                            None => out.resumed(timer_id),
+                        }
+                        true
+                    },
+                    Resp::TimerNotFound => out.not_found(timer_id),
+                    Resp::AlreadyRunning => {
+                        out.already_running(timer_id);
+                        false
+                    },
+                };
+            }
+            exit_unless(all_ok)
+        }
+        cli::CliCommand::Toggle { timer_ids } => {
+            use ToggleTimerResponse as Resp;
+            let mut all_ok = true;
+            for timer_id in resolve_timer_ids(conn, &timer_ids)? {
+                conn.send(Command::ToggleTimer(timer_id))?;
+                all_ok &= match conn.recv::<ToggleTimerResponse>()? {
+                    Resp::Paused => {
+                        out.paused(timer_id);
+                        true
+                    },
+                    Resp::Resumed => {
+                        out.resumed(timer_id);
+                        true
+                    },
+                    Resp::TimerNotFound => out.not_found(timer_id),
+                };
+            }
+            exit_unless(all_ok)
+        }
+        cli::CliCommand::Extend { timer_id, adjustments } => {
+            let millis = adjustments.iter().fold(0i64, |total, &millis| total.saturating_add(millis));
+            conn.send(Command::ExtendTimer { id: timer_id, millis })?;
+            print_adjusted(out, timer_id, conn.recv::<AdjustTimerResponse>()?)
+        }
+        cli::CliCommand::Set { timer_id, duration } => {
+            let total = duration.total();
+            let Ok(millis) = u64::try_from(total.as_millis()) else {
+                eprintln!("Can't leave timer {timer_id} with {}: {}.", total.format_colon_separated(), why_invalid(total));
+                exit(1)
+            };
+            conn.send(Command::SetTimer { id: timer_id, millis })?;
+            print_adjusted(out, timer_id, conn.recv::<AdjustTimerResponse>()?)
+        }
+        cli::CliCommand::Snooze { timer_id, duration } => {
+            let dur = duration.total();
+            conn.send(Command::Snooze { id: timer_id, millis: dur.as_millis() as u64 })?;
+            match conn.recv::<SnoozeResponse>()? {
+                SnoozeResponse::Ok => {
+                    out.snoozed(timer_id, dur);
                     Ok(())
-                },
-                Resp::TimerNotFound => exit_timer_not_found(timer_id),
-                Resp::AlreadyRunning => {
-                    println!("Timer {timer_id} is already running.");
-                    exit(1);
-                },
+                }
+                SnoozeResponse::TimerNotFound => {
+                    out.not_found(timer_id);
+                    exit(1)
+                }
+                SnoozeResponse::NotElapsed => {
+                    eprintln!("Timer {timer_id} hasn't elapsed yet.");
+                    exit(1)
+                }
+                SnoozeResponse::LimitReached { max } => exit_limit_reached(max),
+                SnoozeResponse::InvalidDuration { .. } => {
+                    eprintln!("Can't snooze a timer for {}: {}.", dur.format_colon_separated(), why_invalid(dur));
+                    exit(1)
+                }
             }
         }
-        cli::CliCommand::Cancel { timer_id } => {
-            let timer_id = TimerId::parse_or_quit(&timer_id);
-            conn.send(Command::CancelTimer(timer_id))?;
+        cli::CliCommand::Cancel { timer_ids, all, yes } => {
+            let timer_ids = if all || means_all_timers(&timer_ids) {
+                let timer_ids = all_timer_ids(conn)?;
+                if timer_ids.is_empty() {
+                    out.no_timers();
+                    return Ok(());
+                }
+                if !yes && !confirm_cancel_all(timer_ids.len())? {
+                    exit(1);
+                }
+                timer_ids
+            } else {
+                TimerId::parse_many_or_quit(&timer_ids)
+            };
+
             use message::CancelTimerResponse as Resp;
-            match conn.recv::<Resp>()? {
-                Resp::Ok => {
-                    println!("Cancelled timer {timer_id}.");
+            let mut all_ok = true;
+            for timer_id in timer_ids {
+                conn.send(Command::CancelTimer(timer_id))?;
+                all_ok &= match conn.recv::<Resp>()? {
+                    Resp::Ok => {
+                        out.cancelled(timer_id);
+                        true
+                    },
+                    Resp::TimerNotFound => out.not_found(timer_id),
+                };
+            }
+            exit_unless(all_ok)
+        }
+        cli::CliCommand::Wait { timer_id } => {
+            wait_for(conn, out, timer_id)
+        }
+        cli::CliCommand::Stats => {
+            conn.send(Command::Stats)?;
+            let StatsResponse::Ok { running, paused, running_remaining_millis, paused_remaining_millis } =
+                conn.recv::<StatsResponse>()?;
+            out.stats(
+                (running, Duration::from_millis(running_remaining_millis)),
+                (paused, Duration::from_millis(paused_remaining_millis)),
+            );
+            Ok(())
+        }
+        cli::CliCommand::Formats => {
+            conn.send(Command::Formats)?;
+            let FormatsResponse::Ok { formats } = conn.recv::<FormatsResponse>()?;
+            for format in formats {
+                println!("{format}");
+            }
+            Ok(())
+        }
+        cli::CliCommand::SoundInfo => {
+            conn.send(Command::SoundInfo)?;
+            match conn.recv::<SoundInfoResponse>()? {
+                SoundInfoResponse::Ok { path, duration_millis } => {
+                    out.sound_info(&path, duration_millis.map(Duration::from_millis));
                     Ok(())
-                },
-                Resp::TimerNotFound => exit_timer_not_found(timer_id),
+                }
+                SoundInfoResponse::NoSound { reason } => {
+                    eprintln!("The daemon has no timer sound loaded: {reason}");
+                    exit(1)
+                }
             }
         }
+        cli::CliCommand::Export => {
+            conn.send(Command::Export)?;
+            let ExportResponse::Ok { timers } = conn.recv::<ExportResponse>()?;
+            let timers = serde_json::to_string_pretty(&timers).expect("failed to serialize timers");
+            println!("{timers}");
+            Ok(())
+        }
+        cli::CliCommand::Import { file } => {
+            let timers = read_exported(&file).unwrap_or_else(|e| {
+                eprintln!("Error reading timers from {}: {e}", file.display());
+                exit(1)
+            });
+            let mut all_ok = true;
+            for timer in timers {
+                let remaining = Duration::from_millis(timer.remaining_millis);
+                let name = timer.spec.name.clone();
+                let paused = timer.state == TimerState::Paused;
+                conn.send(Command::ImportTimer(timer))?;
+                match conn.recv::<AddTimerResponse>()? {
+                    AddTimerResponse::Ok { id } => {
+                        out.timer_created(id, remaining, name.as_deref());
+                        if paused {
+                            out.paused(id);
+                        }
+                    }
+                    AddTimerResponse::LimitReached { max } => exit_limit_reached(max),
+                    AddTimerResponse::InvalidDuration { duration } => {
+                        eprintln!("Can't import a timer with {duration}ms remaining.");
+                        all_ok = false;
+                    }
+                    AddTimerResponse::AfterNotFound { .. } | AddTimerResponse::AlreadyExists { .. } => {
+                        unreachable!("BUG: imported timers don't wait for others, and aren't unique")
+                    }
+                }
+            }
+            exit_unless(all_ok)
+        }
+        cli::CliCommand::Config => {
+            conn.send(Command::GetConfig)?;
+            let GetConfigResponse::Ok(config) = conn.recv::<GetConfigResponse>()?;
+            let config = serde_json::to_string_pretty(&config).expect("failed to serialize Config");
+            println!("{config}");
+            Ok(())
+        }
+        cli::CliCommand::DebugDump => {
+            conn.send(Command::DebugDump)?;
+            let DebugDumpResponse::Ok(dump) = conn.recv::<DebugDumpResponse>()?;
+            let dump = serde_json::to_string_pretty(&dump).expect("failed to serialize DebugDump");
+            println!("{dump}");
+            Ok(())
+        }
+        cli::CliCommand::Events => {
+            conn.disable_timeout()?;
+            let mut stdout = io::stdout().lock();
+            match subscribe(conn, |entry| write_event(&mut stdout, &entry)) {
+                Err(e) if e.kind() == io::ErrorKind::BrokenPipe => Ok(()),
+                other => other,
+            }
+        }
+        cli::CliCommand::Gui => {
+            conn.disable_timeout()?;
+            launch_gui(conn)
+        }
+        cli::CliCommand::Fsck => {
+            conn.send(Command::Fsck)?;
+            let FsckResponse::Ok { findings } = conn.recv::<FsckResponse>()?;
+            if findings.is_empty() {
+                println!("No problems found.");
+                return Ok(());
+            }
+            for finding in findings {
+                println!("Timer {}: {}", finding.id, finding.problem);
+            }
+            exit(1)
+        }
         cli::CliCommand::Version => unreachable!("handled in top level main"),
+        cli::CliCommand::EditSound { .. } => unreachable!("handled in top level main"),
+        cli::CliCommand::ParseDuration(_) => unreachable!("handled in top level main"),
         cli::CliCommand::Daemon(_) => unreachable!("handled in top level main"),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use sand::event_log::LifecycleEvent;
+
+    use super::*;
+
+    /// A connection to a pretend daemon, which replies with `responses`
+    /// regardless of what it's sent.
+    fn fake_daemon(responses: &str) -> DaemonConnection<&[u8], Vec<u8>> {
+        DaemonConnection::from_parts(responses.as_bytes(), Vec::new())
+    }
+
+    fn sent<'a>(conn: &'a DaemonConnection<&[u8], Vec<u8>>) -> &'a str {
+        std::str::from_utf8(conn.write.get_ref()).unwrap()
+    }
+
+    #[test]
+    fn add_timer_on_the_wire() {
+        let mut conn = fake_daemon("{\"ok\":{\"id\":1}}\n{\"limitreached\":{\"max\":1}}\n");
+        let spec = StartSpec { name: Some("tea".into()), ..StartSpec::new(Duration::from_secs(60)) };
+        conn.send(Command::AddTimer(spec)).unwrap();
+        assert_eq!(sent(&conn), "{\"addtimer\":{\"duration\":60000,\"name\":\"tea\"}}\n");
+        assert_eq!(conn.recv::<AddTimerResponse>().unwrap(), AddTimerResponse::ok(TimerId(1)));
+        assert_eq!(conn.recv::<AddTimerResponse>().unwrap(), AddTimerResponse::LimitReached { max: 1 });
+    }
+
+    #[test]
+    fn subscribe_reads_events_until_hangup() {
+        let mut conn = fake_daemon(concat!(
+            "\"subscribed\"\n",
+            "{\"timestamp_millis\":1000,\"event\":\"started\",\"id\":1,\"name\":\"tea\"}\n",
+            "\"keepalive\"\n",
+            "{\"timestamp_millis\":2000,\"event\":\"elapsed\",\"id\":1}\n",
+        ));
+        let mut events = Vec::new();
+        subscribe(&mut conn, |entry| {
+            events.push((entry.timestamp_millis, entry.event, entry.id, entry.name));
+            Ok(())
+        }).unwrap();
+        assert_eq!(sent(&conn), "\"subscribe\"\n");
+        assert_eq!(events, [
+            (1000, LifecycleEvent::Started, TimerId(1), Some("tea".to_string())),
+            (2000, LifecycleEvent::Elapsed, TimerId(1), None),
+        ]);
+    }
+
+    #[test]
+    fn all_means_every_timer() {
+        let mut conn = fake_daemon(concat!(
+            "{\"ok\":{\"timers\":[",
+            "{\"id\":2,\"state\":\"Paused\",\"remaining_millis\":1000},",
+            "{\"id\":1,\"state\":\"Running\",\"remaining_millis\":500}",
+            "]}}\n",
+        ));
+        let ids = resolve_timer_ids(&mut conn, &["3".into(), "all".into()]).unwrap();
+        assert_eq!(sent(&conn), "\"list\"\n");
+        assert_eq!(ids, [TimerId(1), TimerId(2)]);
+
+        let mut conn = fake_daemon("");
+        let ids = resolve_timer_ids(&mut conn, &["1,3-4".into()]).unwrap();
+        assert_eq!(sent(&conn), "");
+        assert_eq!(ids, [TimerId(1), TimerId(3), TimerId(4)]);
+    }
+
+    #[test]
+    fn request_reconnects_when_the_daemon_goes_away() {
+        let path = std::env::temp_dir().join(format!("sand-test-{}-reconnect.sock", process::id()));
+        let _ = std::fs::remove_file(&path);
+        let listener = std::os::unix::net::UnixListener::bind(&path).unwrap();
+        let daemon = std::thread::spawn(move || {
+            // Hang up on the first connection, as a daemon restarting would.
+            drop(listener.accept().unwrap());
+            let (stream, _) = listener.accept().unwrap();
+            let mut line = String::new();
+            BufReader::new(&stream).read_line(&mut line).unwrap();
+            writeln!(&stream, "{{\"ok\":{{\"timers\":[]}}}}").unwrap();
+            line
+        });
+
+        let mut conn = DaemonConnection::new(DaemonAddr::Unix(path.clone()))
+            .unwrap()
+            .with_reconnect(DaemonAddr::Unix(path.clone()));
+        let resp = conn.request::<ListResponse>(Command::List);
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(resp.unwrap(), ListResponse::ok(Vec::new()));
+        assert_eq!(daemon.join().unwrap(), "\"list\"\n");
+    }
+
+    #[test]
+    fn empty_ls_mentions_the_state_filter() {
+        let args = |state| LsArgs {
+            duration_format: DurationFormat::default(),
+            ids: false,
+            json: false,
+            sort: Default::default(),
+            state,
+            name_contains: None,
+            min_remaining: None,
+            max_remaining: None,
+            no_summary: false,
+            due: None,
+            oneline: false,
+        };
+        let out = Printer::new(false);
+        let labels = UnitLabels::default();
+        assert_eq!(display_timer_info(&[], &args(None), &labels, &out), "No timers running.");
+        assert_eq!(display_timer_info(&[], &args(Some(TimerState::Paused)), &labels, &out), "No paused timers.");
+        assert_eq!(display_timer_info(&[], &args(Some(TimerState::Running)), &labels, &out), "No running timers.");
+        let named = LsArgs { name_contains: Some("tea".into()), ..args(Some(TimerState::Running)) };
+        assert_eq!(display_timer_info(&[], &named, &labels, &out), "No matching timers.");
+    }
+
+    #[test]
+    fn recv_skips_keepalives() {
+        let mut conn = fake_daemon("\"keepalive\"\n\"keepalive\"\n\"elapsed\"\n");
+        assert_eq!(conn.recv::<WaitResponse>().unwrap(), WaitResponse::Elapsed);
+    }
+
+    #[test]
+    fn list_on_the_wire() {
+        let mut conn = fake_daemon(concat!(
+            "{\"ok\":{\"timers\":[",
+            "{\"id\":2,\"state\":\"Paused\",\"remaining_millis\":1000,\"name\":\"tea\"},",
+            "{\"id\":1,\"state\":\"Running\",\"remaining_millis\":500}",
+            "]}}\n",
+        ));
+        conn.send(Command::List).unwrap();
+        assert_eq!(sent(&conn), "\"list\"\n");
+        let ListResponse::Ok { timers } = conn.recv::<ListResponse>().unwrap();
+        let summary: Vec<_> = timers.iter()
+            .map(|timer| (timer.id(), timer.remaining_millis(), timer.name()))
+            .collect();
+        assert_eq!(summary, [(TimerId(2), 1000, Some("tea")), (TimerId(1), 500, None)]);
+    }
+
+    #[test]
+    fn summary_counts_timers() {
+        let timers: Vec<TimerInfoForClient> = serde_json::from_str(concat!(
+            "[{\"id\":1,\"state\":\"Running\",\"remaining_millis\":90000},",
+            "{\"id\":2,\"state\":\"Paused\",\"remaining_millis\":1000},",
+            "{\"id\":3,\"state\":\"Running\",\"remaining_millis\":30000}]",
+        )).unwrap();
+        let labels = UnitLabels::default();
+        assert_eq!(summary_line(&timers, DurationFormat::Compact, &labels), "2 running, 1 paused, next due in 30s");
+        assert_eq!(summary_line(&timers[1..2], DurationFormat::Compact, &labels), "0 running, 1 paused");
+    }
+
+    #[test]
+    fn daemon_hanging_up_is_connection_lost() {
+        let mut conn = fake_daemon("\"keepalive\"\n");
+        let err = ClientError::from(conn.recv::<WaitResponse>().unwrap_err());
+        assert!(matches!(err, ClientError::ConnectionLost(_)), "{err:?}");
+
+        let refused = io::Error::from(io::ErrorKind::ConnectionRefused);
+        assert!(matches!(ClientError::from(refused), ClientError::DaemonUnavailable(_)));
+    }
+
+    #[test]
+    fn verbose_flag_sets_log_level() {
+        assert_eq!(log_level(1), log::LevelFilter::Debug);
+        assert_eq!(log_level(2), log::LevelFilter::Trace);
+        assert_eq!(log_level(5), log::LevelFilter::Trace);
+    }
+}
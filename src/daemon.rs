@@ -1,22 +1,33 @@
 mod handle_client;
-mod ctx;
 
+use std::future::Future;
 use std::io;
-use std::mem;
 use std::os::fd::FromRawFd;
 use std::os::fd::RawFd;
 use std::os::unix;
-use async_scoped;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
 use async_scoped::TokioScope;
-use rodio::OutputStream;
-use tokio;
-use tokio::net::UnixListener;
+use tokio::net::{TcpListener, UnixListener, UnixStream};
 use tokio::runtime::Runtime;
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::watch;
+
+use sand::audio::{self, ElapsedSoundPlayer};
+use sand::message::AddTimerResponse;
+use sand::clock;
+use sand::config::{self, Config};
+use sand::duration::DurationExt;
+use sand::socket;
+use sand::state;
+use sand::ctx;
+use sand::DaemonCtx;
 
 use crate::cli;
-use crate::sand;
-use handle_client::handle_client;
-use ctx::DaemonCtx;
+use crate::client;
+use crate::logger;
+use crate::output::Printer;
+use handle_client::{handle_client, spawn_client};
 
 const SYSTEMD_SOCKFD: RawFd = 3;
 
@@ -24,34 +35,137 @@ fn env_fd() -> Option<u32> {
     let str_fd = std::env::var("SAND_SOCKFD").ok()?;
     let fd = str_fd
         .parse::<u32>()
-        .expect("Error: Found SAND_SOCKFD but couldn't parse it as a string")
-        .into();
+        .expect("Error: Found SAND_SOCKFD but couldn't parse it as a string");
     Some(fd)
 }
 
-fn get_fd() -> RawFd {
+fn max_timers() -> usize {
+    let Ok(str_max) = std::env::var("SAND_MAX_TIMERS") else {
+        return ctx::DEFAULT_MAX_TIMERS;
+    };
+    str_max
+        .parse::<usize>()
+        .expect("Error: Found SAND_MAX_TIMERS but couldn't parse it as a number")
+}
+
+fn max_command_bytes() -> usize {
+    let Ok(str_max) = std::env::var("SAND_MAX_COMMAND_BYTES") else {
+        return ctx::DEFAULT_MAX_COMMAND_BYTES;
+    };
+    str_max
+        .parse::<usize>()
+        .expect("Error: Found SAND_MAX_COMMAND_BYTES but couldn't parse it as a number")
+}
+
+/// Whether an environment variable which turns something on is set to
+/// `1` or `true`, or to `0` or `false`. Anything else is a mistake.
+fn parse_env_flag(value: &str) -> Option<bool> {
+    match value.to_ascii_lowercase().as_str() {
+        "1" | "true" => Some(true),
+        "0" | "false" | "" => Some(false),
+        _ => None,
+    }
+}
+
+fn ignore_suspend() -> bool {
+    let Ok(value) = std::env::var("SAND_IGNORE_SUSPEND") else {
+        return false;
+    };
+    parse_env_flag(&value).unwrap_or_else(|| {
+        eprintln!("Error: SAND_IGNORE_SUSPEND should be 1 or true, or 0 or false, got {value:?}");
+        std::process::exit(1)
+    })
+}
+
+/// The fd of a listening socket we were given, either through
+/// `SAND_SOCKFD`, or by systemd socket activation.
+fn get_fd() -> Option<RawFd> {
     match env_fd() {
-        None => {
-            eprintln!("SAND_SOCKFD not found, falling back on default.");
-            SYSTEMD_SOCKFD
+        None if std::env::var_os("LISTEN_FDS").is_some() => {
+            eprintln!("SAND_SOCKFD not found, using the socket from systemd.");
+            Some(SYSTEMD_SOCKFD)
         }
+        None => None,
         Some(fd) => {
             eprintln!("Found SAND_SOCKFD.");
-            fd.try_into()
-                .expect("Error: SAND_SOCKFD is too large to be a file descriptor.")
+            Some(fd.try_into()
+                .expect("Error: SAND_SOCKFD is too large to be a file descriptor."))
         }
     }
 }
 
+/// Bind the socket ourselves, when we weren't given one. A socket file left
+/// behind by a daemon which has since died is replaced, but we refuse to
+/// take over from one that's still running.
+///
+/// Only we can connect to the socket, regardless of the umask, since
+/// anyone who can connect can control our timers. It's created that way,
+/// rather than having its permissions narrowed afterwards, so there's no
+/// moment when someone else could connect.
+fn bind_socket(path: &Path) -> io::Result<unix::net::UnixListener> {
+    if unix::net::UnixStream::connect(path).is_ok() {
+        return Err(io::Error::new(
+            io::ErrorKind::AddrInUse,
+            format!("a daemon is already listening on {}", path.display()),
+        ));
+    }
+    match std::fs::remove_file(path) {
+        Ok(()) => eprintln!("Removed stale socket {}", path.display()),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+        Err(e) => return Err(e),
+    }
+    eprintln!("No socket provided, binding {}", path.display());
+    // SAFETY: umask can't fail. It's process wide, but nothing else creates
+    // files while the daemon is starting up.
+    let umask = unsafe { libc::umask(0o177) };
+    let listener = unix::net::UnixListener::bind(path);
+    // SAFETY: as above.
+    unsafe { libc::umask(umask) };
+    listener
+}
+
+/// What to tell the user when we couldn't bind the socket at `path` for a
+/// reason they can do something about, rather than just the OS error.
+fn bind_error_hint(path: &Path, err: &io::Error) -> Option<String> {
+    let dir = path.parent().unwrap_or(Path::new("/"));
+    match err.kind() {
+        io::ErrorKind::PermissionDenied => Some(format!(
+            "Error: can't create the socket {}: we don't have permission to write to {} ({err}). \
+             Set SAND_SOCK_PATH to a path in a directory you own.",
+            path.display(),
+            dir.display(),
+        )),
+        io::ErrorKind::AddrInUse => Some(format!(
+            "Error: can't create the socket {}: {err}. \
+             Stop the other daemon, or set SAND_SOCK_PATH to run this one elsewhere.",
+            path.display(),
+        )),
+        _ => None,
+    }
+}
+
 async fn accept_loop(listener: UnixListener, state: &DaemonCtx) {
+    // Todo can we get rid of this clone? maybe if we use scoped threads?
+    accept_clients(listener, |stream| handle_client(stream, state.clone()), io::stderr).await
+}
+
+/// Serve each client that connects to `listener` with `handle`, in a task
+/// of its own. A client's handler panicking is logged to `log()`.
+async fn accept_clients<H, F, L, W>(listener: UnixListener, handle: H, log: L)
+where
+    H: Fn(UnixStream) -> F,
+    F: Future<Output = ()> + Send + 'static,
+    L: Fn() -> W,
+    W: io::Write + Send + 'static,
+{
     eprintln!("starting accept loop");
+    let mut clients = 0u64;
     loop {
         match listener.accept().await {
             Ok((stream, _addr)) => {
-                eprintln!("got client");
-
-                // Todo can we get rid of this clone? maybe if we use scoped threads?
-                let _jh = tokio::spawn(handle_client(stream, state.clone()));
+                clients += 1;
+                eprintln!("got client {clients}");
+                spawn_client(format!("client {clients}"), handle(stream), log());
             }
             Err(e) => {
                 eprintln!("Error: failed to accept client: {}", e);
@@ -61,32 +175,444 @@ async fn accept_loop(listener: UnixListener, state: &DaemonCtx) {
     }
 }
 
-async fn daemon() -> io::Result<()> {
-    eprintln!("Starting sand daemon {}", sand::VERSION);
+/// The address to listen for TCP connections on, from
+/// `SAND_LISTEN=tcp://host:port`, in addition to the Unix socket.
+fn tcp_listen_addr() -> Option<String> {
+    let addr = std::env::var("SAND_LISTEN").ok()?;
+    match addr.strip_prefix("tcp://") {
+        Some(host_port) => Some(host_port.to_string()),
+        None => {
+            eprintln!("Error: SAND_LISTEN should look like tcp://127.0.0.1:7654, got {addr:?}");
+            std::process::exit(1)
+        }
+    }
+}
 
-    let fd = get_fd();
+/// Like [`accept_loop`], for TCP. There's no authentication, so anyone who
+/// can reach the port can control the daemon's timers.
+async fn tcp_accept_loop(listener: TcpListener, state: &DaemonCtx) {
+    loop {
+        match listener.accept().await {
+            Ok((stream, addr)) => {
+                eprintln!("got TCP client from {addr}");
+                spawn_client(format!("TCP client {addr}"), handle_client(stream, state.clone()), io::stderr());
+            }
+            Err(e) => {
+                eprintln!("Error: failed to accept TCP client: {}", e);
+                continue;
+            }
+        };
+    }
+}
+
+const CONFIG_POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+fn modified_time(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+fn load_config(path: &Path) -> Option<Config> {
+    Config::load(path)
+        .inspect_err(|e| eprintln!("Error loading config from {}: {e}", path.display()))
+        .ok()
+}
+
+/// The user's timer sound file, and when it was last modified.
+fn sound_file(state: &DaemonCtx) -> (Option<PathBuf>, Option<SystemTime>) {
+    let path = audio::user_sound_path(state.config().sound_path.as_deref());
+    let modified = path.as_deref().and_then(modified_time);
+    (path, modified)
+}
 
-    let o_handle = match OutputStream::try_default() {
-        Ok((stream, handle)) => {
-            mem::forget(stream);
-            Some(handle)
+/// Poll the config file for changes, reloading it whenever it's modified.
+/// The user's timer sound is watched too, and reloaded if it changes.
+async fn watch_config(path: PathBuf, state: &DaemonCtx) {
+    let mut last_modified = modified_time(&path);
+    let mut last_sound = sound_file(state);
+    loop {
+        tokio::time::sleep(CONFIG_POLL_INTERVAL).await;
+        let modified = modified_time(&path);
+        if modified != last_modified {
+            eprintln!("config file changed, reloading.");
+            last_modified = modified;
+            if let Some(config) = load_config(&path) {
+                state.set_config(config);
+            }
+        }
+        // A different file means the config changed, and it's already
+        // been loaded.
+        let sound = sound_file(state);
+        if sound.0 == last_sound.0 && sound.1 != last_sound.1 {
+            eprintln!("timer sound changed, reloading.");
+            state.reload_sound();
+        }
+        last_sound = sound;
+    }
+}
+
+/// Reload the config file, if there is one, and the timer sound whenever
+/// the daemon gets SIGHUP, without waiting for the file watcher to notice.
+async fn reload_on_hangup(config_path: Option<&Path>, state: &DaemonCtx) {
+    let mut hangups = match signal(SignalKind::hangup()) {
+        Ok(hangups) => hangups,
+        Err(e) => {
+            eprintln!("Error installing SIGHUP handler, reloading on SIGHUP is disabled: {e}");
+            return;
         }
-        Err(_) => None
     };
+    while hangups.recv().await.is_some() {
+        eprintln!("got SIGHUP, reloading config and sound.");
+        if let Some(config) = config_path.and_then(load_config) {
+            state.set_config(config);
+        }
+        state.reload_sound();
+    }
+}
+
+/// Below this, a difference in time suspended is just measurement noise.
+const SUSPEND_THRESHOLD: Duration = Duration::from_millis(100);
+const SUSPEND_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How far the monotonic clock can be off from the poll interval before
+/// it's checked against the wall clock for having jumped.
+const MONOTONIC_JUMP_TOLERANCE: Duration = Duration::from_secs(2);
+
+/// Poll for the system having been suspended, and let the timers catch up
+/// on the time they missed. Also watch for the monotonic clock jumping, as
+/// after a container is migrated to another host.
+///
+/// If `ignore_suspend` is set, suspensions are still noticed but timers are
+/// left alone. Since the monotonic clock stops during suspend, that means
+/// they only count down while the system is awake.
+async fn watch_for_suspend(state: &DaemonCtx, ignore_suspend: bool) {
+    let mut suspended = clock::time_suspended();
+    let mut last_poll = (Instant::now(), SystemTime::now());
+    loop {
+        tokio::time::sleep(SUSPEND_POLL_INTERVAL).await;
+        let poll = (Instant::now(), SystemTime::now());
+        let jump = clock::monotonic_jump(last_poll, poll, SUSPEND_POLL_INTERVAL, MONOTONIC_JUMP_TOLERANCE);
+        last_poll = poll;
+        if let Some(jump) = jump {
+            // The boot clock likely jumped along with the monotonic one, so
+            // start measuring suspensions afresh.
+            suspended = clock::time_suspended();
+            state.monotonic_jumped(poll.0, jump);
+            continue;
+        }
+        let now_suspended = clock::time_suspended();
+        let slept = now_suspended.saturating_sub(suspended);
+        if slept > SUSPEND_THRESHOLD {
+            suspended = now_suspended;
+            if ignore_suspend {
+                eprintln!("System was asleep for {}, leaving timers as they are", slept.format_colon_separated());
+            } else {
+                state.awaken(poll.0, slept);
+            }
+        } else if now_suspended < suspended {
+            suspended = now_suspended;
+        }
+    }
+}
+
+/// How long `--until-empty` waits with no timers before exiting, so a
+/// client has time to start one.
+const UNTIL_EMPTY_GRACE: Duration = Duration::from_secs(10);
+
+/// Return once there have been no timers for [`UNTIL_EMPTY_GRACE`].
+async fn wait_until_empty(state: &DaemonCtx) {
+    let mut events = state.subscribe();
+    loop {
+        if state.is_empty() {
+            tokio::select! {
+                _ = tokio::time::sleep(UNTIL_EMPTY_GRACE) => {
+                    // A timer started during the grace period which hasn't
+                    // finished yet doesn't send an event, so check again.
+                    if state.is_empty() {
+                        return;
+                    }
+                }
+                // A timer came and went, so start the grace period again.
+                _ = events.recv() => {}
+            }
+        } else {
+            // Lagging just means we missed some events, so check again.
+            let _ = events.recv().await;
+        }
+    }
+}
 
-    let state = DaemonCtx::new(o_handle);
-    let std_listener: unix::net::UnixListener = unsafe { unix::net::UnixListener::from_raw_fd(fd) };
+/// Run `fut` until it finishes, or until `shutdown` becomes true.
+async fn until_shutdown(fut: impl Future<Output = ()>, mut shutdown: watch::Receiver<bool>) {
+    tokio::select! {
+        () = fut => {}
+        _ = shutdown.wait_for(|&shutdown| shutdown) => {}
+    }
+}
+
+/// Ask the notification server what it supports, and report whether that
+/// includes actions.
+async fn notification_actions_supported() -> bool {
+    let capabilities = tokio::task::spawn_blocking(notify_rust::get_capabilities).await;
+    match capabilities {
+        Ok(Ok(capabilities)) => {
+            eprintln!("notification server capabilities: {}", capabilities.join(", "));
+            capabilities.iter().any(|c| c == "actions")
+        }
+        Ok(Err(e)) => {
+            eprintln!("Error getting notification server capabilities: {e}");
+            false
+        }
+        Err(e) => {
+            eprintln!("Error getting notification server capabilities: {e}");
+            false
+        }
+    }
+}
+
+async fn daemon(args: cli::DaemonArgs) -> io::Result<()> {
+    eprintln!("Starting sand daemon {}", sand::VERSION);
+
+    let o_handle = if args.no_sound || std::env::var_os("SAND_NO_SOUND").is_some() {
+        eprintln!("sound disabled.");
+        None
+    } else {
+        audio::open_default_output()
+            .inspect_err(|e| eprintln!("Error opening audio output: {e}"))
+            .ok()
+    };
+
+    let config_path = config::config_path();
+    let config = match config_path {
+        Some(ref path) => load_config(path).unwrap_or_default(),
+        None => {
+            eprintln!("config directory not found, using default config.");
+            Config::default()
+        }
+    };
+    let state = DaemonCtx::new(o_handle)
+        .with_max_timers(max_timers())
+        .with_max_command_bytes(max_command_bytes())
+        .with_config(config)
+        .with_notification_actions(notification_actions_supported().await);
+    let last_started_path = if args.no_state { None } else { state::last_started_path() };
+    let state = match last_started_path {
+        Some(path) => state.with_last_started_path(path),
+        None => state,
+    };
+    let (std_listener, bound_ourselves) = match get_fd() {
+        Some(fd) => (unsafe { unix::net::UnixListener::from_raw_fd(fd) }, false),
+        None => {
+            let path = socket::sock_path()?;
+            match bind_socket(&path) {
+                Ok(listener) => (listener, true),
+                Err(e) => match bind_error_hint(&path, &e) {
+                    Some(hint) => {
+                        eprintln!("{hint}");
+                        std::process::exit(1)
+                    }
+                    None => return Err(e),
+                },
+            }
+        }
+    };
     std_listener.set_nonblocking(true)?;
+    let socket_path = std_listener.local_addr()?.as_pathname().map(Path::to_path_buf);
+    let state = state
+        .with_socket_path(socket_path.clone())
+        .with_config_path(config_path.clone());
     let listener: UnixListener = UnixListener::from_std(std_listener)?;
+    let tcp_listener = match tcp_listen_addr() {
+        Some(addr) => {
+            let tcp_listener = TcpListener::bind(&addr).await?;
+            eprintln!("also listening on tcp://{}", tcp_listener.local_addr()?);
+            Some(tcp_listener)
+        }
+        None => None,
+    };
+
+    let ignore_suspend = ignore_suspend();
+    if ignore_suspend {
+        eprintln!("not adjusting timers for time spent suspended.");
+    }
 
     eprintln!("daemon started.");
+    let (shutdown_tx, shutdown) = watch::channel(false);
     TokioScope::scope_and_block(|scope| {
-        scope.spawn(accept_loop(listener, &state));
+        scope.spawn(until_shutdown(accept_loop(listener, &state), shutdown.clone()));
+        if let Some(tcp_listener) = tcp_listener {
+            scope.spawn(until_shutdown(tcp_accept_loop(tcp_listener, &state), shutdown.clone()));
+        }
+        scope.spawn(until_shutdown(watch_for_suspend(&state, ignore_suspend), shutdown.clone()));
+        scope.spawn(until_shutdown(reload_on_hangup(config_path.as_deref(), &state), shutdown.clone()));
+        if let Some(path) = config_path.clone() {
+            scope.spawn(until_shutdown(watch_config(path, &state), shutdown.clone()));
+        }
+        if args.until_empty {
+            scope.spawn(async {
+                wait_until_empty(&state).await;
+                eprintln!("no timers left, exiting.");
+                shutdown_tx.send_replace(true);
+            });
+        }
     });
 
+    // Don't leave a stale socket behind. One we were given belongs to
+    // whoever gave it to us.
+    if bound_ourselves {
+        if let Some(path) = socket_path {
+            std::fs::remove_file(&path)
+                .unwrap_or_else(|e| eprintln!("Error removing socket {}: {e}", path.display()));
+        }
+    }
+
+    Ok(())
+}
+
+/// `sand start --no-daemon`: run the timer here, with a context of our own,
+/// and play the sound to the end before exiting.
+async fn in_process(args: cli::StartArgs, out: &Printer) -> io::Result<()> {
+    let config = config::config_path().and_then(|path| load_config(&path)).unwrap_or_default();
+    let dur = args.duration_or(config.default_duration);
+    let player = if std::env::var_os("SAND_NO_SOUND").is_some() {
+        None
+    } else {
+        audio::open_default_output()
+            .inspect_err(|e| eprintln!("Error opening audio output: {e}"))
+            .ok()
+            .and_then(|handle| {
+                ElapsedSoundPlayer::new(handle, config.sound_path.as_deref())
+                    .inspect_err(|e| eprintln!("No timer sound could be loaded: {e}"))
+                    .ok()
+            })
+    };
+    let volume = config.volume;
+    // The context doesn't play the sound itself, since it wouldn't wait for
+    // it to finish.
+    let state = DaemonCtx::new(None).with_config(config);
+
+    let spec = client::timer_spec(dur, args.options);
+    let name = spec.name.clone();
+    let AddTimerResponse::Ok { id } = state.add_timer(Instant::now(), spec) else {
+        unreachable!("a new context has no timers");
+    };
+    out.timer_created(id, dur, name.as_deref());
+    state.wait(id).await;
+    out.elapsed(id);
+    if let Some(player) = player {
+        tokio::task::spawn_blocking(move || player.play_to_end(volume))
+            .await?
+            .unwrap_or_else(|e| eprintln!("Error playing timer elapsed sound: {e}"));
+    }
     Ok(())
 }
 
-pub fn main(_args: cli::DaemonArgs) -> io::Result<()> {
-    Runtime::new()?.block_on(daemon())
+pub fn run_in_process(args: cli::StartArgs, porcelain: bool) -> io::Result<()> {
+    let then = args.then.clone();
+    Runtime::new()?.block_on(in_process(args, &Printer::new(porcelain)))?;
+    match then {
+        Some(command) => client::run_then(&command),
+        None => Ok(()),
+    }
+}
+
+pub fn main(args: cli::DaemonArgs) -> io::Result<()> {
+    logger::init(logger::level_from_env("RUST_LOG"));
+    Runtime::new()?.block_on(daemon(args))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::fs::PermissionsExt;
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::sync::{Arc, Mutex};
+
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    use super::*;
+
+    /// Collects what's logged to it, to be checked on.
+    #[derive(Clone, Default)]
+    struct CapturedLog(Arc<Mutex<Vec<u8>>>);
+
+    impl CapturedLog {
+        fn contents(&self) -> String {
+            String::from_utf8_lossy(&self.0.lock().unwrap()).into_owned()
+        }
+    }
+
+    impl io::Write for CapturedLog {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    async fn read_all(path: &Path) -> Vec<u8> {
+        let mut stream = UnixStream::connect(path).await.unwrap();
+        let mut read = Vec::new();
+        stream.read_to_end(&mut read).await.unwrap();
+        read
+    }
+
+    #[test]
+    fn socket_is_only_accessible_to_us() {
+        let path = std::env::temp_dir().join(format!("sand-test-{}-bind.sock", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let _listener = bind_socket(&path).unwrap();
+        let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn env_flags() {
+        assert_eq!(parse_env_flag("1"), Some(true));
+        assert_eq!(parse_env_flag("True"), Some(true));
+        assert_eq!(parse_env_flag("0"), Some(false));
+        assert_eq!(parse_env_flag("false"), Some(false));
+        assert_eq!(parse_env_flag(""), Some(false));
+        assert_eq!(parse_env_flag("yes please"), None);
+    }
+
+    #[tokio::test]
+    async fn panicking_client_doesnt_stop_others() {
+        let path = std::env::temp_dir().join(format!("sand-test-{}-accept.sock", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let listener = UnixListener::bind(&path).unwrap();
+        let log = CapturedLog::default();
+
+        let connected = Arc::new(AtomicU64::new(0));
+        let handle = move |mut stream: UnixStream| {
+            let client = connected.fetch_add(1, Ordering::SeqCst) + 1;
+            async move {
+                if client == 1 {
+                    panic!("forced for testing");
+                }
+                stream.write_all(b"served").await.unwrap();
+            }
+        };
+        let log_for_loop = log.clone();
+        let accepting = tokio::spawn(accept_clients(listener, handle, move || log_for_loop.clone()));
+
+        // The first client's connection is closed when its handler panics,
+        // and the next client is still served.
+        assert_eq!(read_all(&path).await, b"");
+        assert_eq!(read_all(&path).await, b"served");
+
+        let logged = async {
+            while !log.contents().contains("Error: handling client 1 panicked: forced for testing") {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        };
+        tokio::time::timeout(Duration::from_secs(5), logged).await
+            .unwrap_or_else(|_| panic!("panic wasn't logged, got {:?}", log.contents()));
+        assert!(!accepting.is_finished());
+
+        accepting.abort();
+        std::fs::remove_file(&path).unwrap();
+    }
 }
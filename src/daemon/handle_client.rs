@@ -1,104 +1,276 @@
-
-use std::time::Duration;
-use std::time::Instant;
-
 use serde_json::Error;
+use std::any::Any;
+use std::future::Future;
+use std::io;
 use tokio::io::AsyncBufReadExt;
 use tokio::io::BufReader;
-use tokio::net::UnixStream;
-use tokio::io::AsyncWriteExt;
-use tokio_stream::wrappers::LinesStream;
-use tokio_stream::StreamExt;
-use crate::sand::message::AddTimerResponse;
-use crate::sand::message::CancelTimerResponse;
-use crate::sand::message::ListResponse;
-use crate::sand::message::PauseTimerResponse;
-use crate::sand::message::ResumeTimerResponse;
-use crate::sand::message::{Command, Response};
-use crate::sand::timer::TimerId;
-
-use super::ctx::DaemonCtx;
-
-struct CmdHandlerCtx {
-    now: Instant,
-    state: DaemonCtx,
-}
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::sync::{broadcast, mpsc};
+use tokio::time::{self, Duration, Instant};
+use tokio::task::JoinHandle;
+use sand::event_log::EventLogEntry;
+use sand::message::{Command, Response, SubscribeResponse, KEEPALIVE_FRAME};
+use sand::DaemonCtx;
 
-impl CmdHandlerCtx {
-    fn new(state: DaemonCtx) -> Self {
-        let now = Instant::now();
-        Self { now, state }
-    }
+const KEEPALIVE_INTERVAL: Duration = Duration::from_secs(30);
 
-    fn list(&self) -> ListResponse {
-        ListResponse::ok(self.state.get_timerinfo_for_client(self.now))
-    }
-
-
-    fn add_timer(&self, duration: u64) -> AddTimerResponse {
-        let duration = Duration::from_millis(duration);
-        let id = self.state.add_timer(self.now, duration);
-        AddTimerResponse::ok(id)
-    }
-    
-    fn pause_timer(&self, id: TimerId) -> PauseTimerResponse {
-        self.state.pause_timer(id, self.now)
-    }
-    
-    fn resume_timer(&self, id: TimerId) -> ResumeTimerResponse {
-        self.state.resume_timer(id, self.now)
-    }
-    
-    fn cancel_timer(&self, id: TimerId) -> CancelTimerResponse {
-        self.state.cancel_timer(id)
-    }
+/// What to send back for a command.
+enum Reply {
+    Response(JoinHandle<Response>),
+    /// The client subscribed, so send it events until it goes away.
+    Events(broadcast::Receiver<EventLogEntry>),
 }
 
+/// Spawn `handler`, a task serving `client`, so that if it panics, the
+/// panic is logged to `log` along with which client it was. The accept
+/// loop and other clients carry on, and the client's connection is closed
+/// as the task's state is dropped.
+pub fn spawn_client<F, W>(client: String, handler: F, mut log: W) -> JoinHandle<()>
+where
+    F: Future<Output = ()> + Send + 'static,
+    W: io::Write + Send + 'static,
+{
+    let handler = tokio::spawn(handler);
+    tokio::spawn(async move {
+        if let Err(e) = handler.await {
+            // There's nowhere else to report failing to log.
+            let _ = if e.is_panic() {
+                writeln!(log, "Error: handling {client} panicked: {}", panic_message(&*e.into_panic()))
+            } else {
+                writeln!(log, "Error: handling {client} was cancelled")
+            };
+        }
+    })
+}
 
-fn handle_command(cmd: Command, state: &DaemonCtx) -> Response {
-    let ctx = CmdHandlerCtx::new(state.clone());
-    match cmd {
-        Command::List => ctx.list().into(),
-        Command::AddTimer { duration } => ctx.add_timer(duration).into(),
-        Command::PauseTimer(id) => ctx.pause_timer(id).into(),
-        Command::ResumeTimer(id) => ctx.resume_timer(id).into(),
-        Command::CancelTimer(id) => ctx.cancel_timer(id).into(),
+/// The message a panic was raised with, if it was a string, as it is for
+/// `panic!` and `expect`.
+fn panic_message(payload: &(dyn Any + Send)) -> &str {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message
+    } else {
+        "(no message)"
     }
 }
 
-
-pub async fn handle_client(mut stream: UnixStream, state: DaemonCtx) {
+/// Serve a single client connection.
+///
+/// Clients may pipeline commands, sending several without waiting for the
+/// responses. Each command is applied as soon as it's read, in the order it
+/// was sent, so a pending `wait` doesn't hold up the commands after it.
+/// Responses are always written in the same order as the commands they
+/// answer, so the response to a command sent after a `wait` is only written
+/// once that `wait` has resolved. While waiting for a response, we send
+/// [`KEEPALIVE_FRAME`]s, to find out if the client has gone away.
+///
+/// A command longer than [`DaemonCtx::max_command_bytes`], or which isn't
+/// valid UTF-8, gets an error response, and the client can carry on with
+/// the next line. No more commands are read after [`Command::Subscribe`],
+/// since the events never stop.
+pub async fn handle_client<S>(stream: S, state: DaemonCtx)
+where
+    S: AsyncRead + AsyncWrite,
+{
     eprintln!("DEBUG: handling client.");
 
-    let (read_half, mut write_half) = stream.split();
+    let (read_half, mut write_half) = tokio::io::split(stream);
+    let (tx, mut rx) = mpsc::unbounded_channel::<Reply>();
 
-    let br = BufReader::new(read_half);
+    let max_command_bytes = state.max_command_bytes();
+    let read_commands = async move {
+        let mut br = BufReader::new(read_half);
+        let mut line = Vec::new();
 
-    let mut lines = LinesStream::new(br.lines());
+        loop {
+            line.clear();
+            // Read at most one byte more than allowed, so a command which
+            // is too long doesn't get buffered in its entirety.
+            let limit = max_command_bytes as u64 + 1;
+            match (&mut br).take(limit).read_until(b'\n', &mut line).await {
+                Ok(0) => break,
+                Ok(_) => {}
+                Err(e) => {
+                    eprintln!("Error reading line from client: {e}");
+                    break;
+                }
+            }
+            let command = line.strip_suffix(b"\n").unwrap_or(&line);
+            let command = if command.len() > max_command_bytes {
+                let err_msg = format!("Error: command is longer than the limit of {max_command_bytes} bytes");
+                eprintln!("{err_msg}, skipping it");
+                if let Err(e) = skip_line(&mut br, limit).await {
+                    eprintln!("Error reading line from client: {e}");
+                    break;
+                }
+                Err(err_msg)
+            } else {
+                std::str::from_utf8(command).map_err(|e| {
+                    let err_msg = format!("Error: command is not valid UTF-8: {e}");
+                    eprintln!("{err_msg}");
+                    err_msg
+                })
+            };
+            let command = match command {
+                Ok(command) => command,
+                Err(err_msg) => {
+                    if tx.send(Reply::Response(tokio::spawn(async move { Response::Error(err_msg) }))).is_err() {
+                        break;
+                    }
+                    continue;
+                }
+            };
+            let rcmd: Result<Command, Error> = serde_json::from_str(command.trim());
 
-    while let Some(rline) = lines.next().await {
-        let line: String = match rline {
-            Ok(line) => line,
-            Err(e) => {
-                eprintln!("Error reading line from client: {e}");
-                continue;
-            },
-        };
-        let line: &str = line.trim();
-        let rcmd: Result<Command, Error> = serde_json::from_str(&line);
+            let reply = match rcmd {
+                Ok(Command::Subscribe) => {
+                    // Subscribe now, so no event after the commands before
+                    // this one is missed.
+                    let _ = tx.send(Reply::Events(state.subscribe_lifecycle()));
+                    break;
+                }
+                Ok(cmd) => Reply::Response(tokio::spawn(state.handle_command(cmd))),
+                Err(e) => {
+                    let err_msg: String = format!("Error: failed to parse client message as Command: {e}"); 
+                    eprintln!("{err_msg}");
+                    Reply::Response(tokio::spawn(async move { Response::Error(err_msg) }))
+                }
+            };
+            if tx.send(reply).is_err() {
+                // The writer gave up, so nobody will see any more responses.
+                break;
+            }
+        }
+    };
 
-        let resp: Response = match rcmd {
-            Ok(cmd) => handle_command(cmd, &state),
-            Err(e) => {
-                let err_msg: String = format!("Error: failed to parse client message as Command: {e}"); 
+    let write_responses = async {
+        'responses: while let Some(reply) = rx.recv().await {
+            let mut resp = match reply {
+                Reply::Response(resp) => resp,
+                Reply::Events(events) => {
+                    if let Err(e) = write_events(&mut write_half, events).await {
+                        eprintln!("Error writing event to client: {e}");
+                    }
+                    break;
+                }
+            };
+            // While a response is pending, like a `wait`, send keepalives.
+            // Otherwise we'd only notice the client was gone once the
+            // response was ready, which could be hours away.
+            let mut keepalive = time::interval_at(Instant::now() + KEEPALIVE_INTERVAL, KEEPALIVE_INTERVAL);
+            let resp = loop {
+                tokio::select! {
+                    resp = &mut resp => break resp,
+                    _ = keepalive.tick() => {
+                        let frame = format!("{KEEPALIVE_FRAME}\n");
+                        if let Err(e) = write_half.write_all(frame.as_bytes()).await {
+                            eprintln!("Error writing keepalive to client: {e}");
+                            resp.abort();
+                            break 'responses;
+                        }
+                    }
+                }
+            };
+            let resp: Response = resp.unwrap_or_else(|e| {
+                let err_msg = format!("Error: failed to handle command: {e}");
                 eprintln!("{err_msg}");
                 Response::Error(err_msg)
+            });
+            let mut resp_str: String = serde_json::to_string(&resp).unwrap();
+            resp_str.push('\n');
+            if let Err(e) = write_half.write_all(resp_str.as_bytes()).await {
+                eprintln!("Error writing response to client: {e}");
+                break;
+            }
+        }
+        // Don't leave waits running for a client that's gone.
+        rx.close();
+        while let Ok(reply) = rx.try_recv() {
+            if let Reply::Response(resp) = reply {
+                resp.abort();
             }
+        }
+    };
+
+    tokio::join!(read_commands, write_responses);
+
+    eprintln!("Client disconnected");
+}
+
+/// Acknowledge a subscription, then send each event as a line of JSON,
+/// with keepalives while it's quiet, until writing fails.
+/// Read and throw away the rest of a line which was too long, `chunk`
+/// bytes at a time.
+async fn skip_line<R>(read: &mut R, chunk: u64) -> io::Result<()>
+where
+    R: AsyncBufReadExt + Unpin,
+{
+    let mut skipped = Vec::new();
+    loop {
+        skipped.clear();
+        let read = read.take(chunk).read_until(b'\n', &mut skipped).await?;
+        if read == 0 || skipped.ends_with(b"\n") {
+            return Ok(());
+        }
+    }
+}
+
+async fn write_events<W>(write_half: &mut W, mut events: broadcast::Receiver<EventLogEntry>) -> io::Result<()>
+where
+    W: AsyncWrite + Unpin,
+{
+    let ack = Response::from(SubscribeResponse::Subscribed);
+    let mut line = serde_json::to_string(&ack).unwrap();
+    line.push('\n');
+    write_half.write_all(line.as_bytes()).await?;
+
+    let mut keepalive = time::interval_at(Instant::now() + KEEPALIVE_INTERVAL, KEEPALIVE_INTERVAL);
+    loop {
+        let mut line = tokio::select! {
+            event = events.recv() => match event {
+                Ok(entry) => serde_json::to_string(&entry).unwrap(),
+                Err(broadcast::error::RecvError::Lagged(missed)) => {
+                    eprintln!("Subscribed client fell behind, dropped {missed} events");
+                    continue;
+                }
+                Err(broadcast::error::RecvError::Closed) => return Ok(()),
+            },
+            _ = keepalive.tick() => KEEPALIVE_FRAME.to_string(),
         };
-        let mut resp_str: String = serde_json::to_string(&resp).unwrap();
-        resp_str.push('\n');
-        write_half.write_all(resp_str.as_bytes()).await.unwrap();
+        line.push('\n');
+        write_half.write_all(line.as_bytes()).await?;
     }
+}
 
-    eprintln!("Client disconnected");
-}
\ No newline at end of file
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn bad_lines_get_errors_and_reading_carries_on() {
+        let state = DaemonCtx::new(None).with_max_command_bytes(16);
+        let (client, server) = tokio::io::duplex(1024);
+        tokio::spawn(handle_client(server, state));
+        let (read, mut write) = tokio::io::split(client);
+        // Reading stops after 17 bytes, in the middle of the é, so neither
+        // what was read nor what's left of the line is valid UTF-8.
+        let mut lines = "\"list\" and then é \"list\"\n".as_bytes().to_vec();
+        lines.extend(b"\xff\xfe\n\"list\"\n");
+        write.write_all(&lines).await.unwrap();
+
+        let mut read = BufReader::new(read).lines();
+        let too_long = read.next_line().await.unwrap().unwrap();
+        assert!(too_long.contains("longer than the limit of 16 bytes"), "{too_long}");
+        let not_utf8 = read.next_line().await.unwrap().unwrap();
+        assert!(not_utf8.contains("not valid UTF-8"), "{not_utf8}");
+        assert_eq!(read.next_line().await.unwrap().unwrap(), r#"{"ok":{"timers":[]}}"#);
+    }
+
+    #[test]
+    fn panic_messages() {
+        assert_eq!(panic_message(&"static"), "static");
+        assert_eq!(panic_message(&String::from("formatted")), "formatted");
+        assert_eq!(panic_message(&3), "(no message)");
+    }
+}
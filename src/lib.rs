@@ -0,0 +1,28 @@
+//! The timer engine behind the `sand` command line tool.
+//!
+//! The `sand` binary is a thin layer over this crate: it parses the command
+//! line, and shuttles [`Command`]s and [`Response`]s over a unix socket.
+//! Embedders can skip the socket and drive a [`DaemonCtx`] directly:
+//!
+//! ```no_run
+//! use std::time::Duration;
+//! use sand::{Command, DaemonCtx, Response, StartSpec};
+//!
+//! # #[tokio::main] async fn main() {
+//! let ctx = DaemonCtx::new(None);
+//! let spec = StartSpec::new(Duration::from_secs(5));
+//! let resp: Response = ctx.handle_command(Command::AddTimer(spec)).await;
+//! # }
+//! ```
+//!
+//! Timers count down on tokio tasks, so a [`DaemonCtx`] must be used from
+//! within a tokio runtime.
+
+mod sand;
+
+pub use crate::sand::{audio, clock, config, ctx, duration, event_log, message, notifier, socket, state, timer, timers, VERSION};
+
+pub use crate::sand::ctx::DaemonCtx;
+pub use crate::sand::message::{Command, Response};
+pub use crate::sand::timer::{StartSpec, Timer, TimerId, TimerInfoForClient};
+pub use crate::sand::timers::Timers;
@@ -0,0 +1,55 @@
+//! The client, and the daemon's dependencies, log through the `log` crate.
+//! This passes those messages on to stderr, where the rest of the daemon's
+//! output, and the client's errors, go.
+
+use log::{Level, LevelFilter, Log, Metadata, Record};
+
+struct StderrLogger;
+
+static LOGGER: StderrLogger = StderrLogger;
+
+/// The level a message is actually shown at. When probing a sound file,
+/// symphonia logs every candidate format marker it finds, along with the
+/// bytes around it, at debug level. That's only of interest when debugging
+/// symphonia itself, so anything below a warning from it is demoted to
+/// trace.
+fn effective_level(metadata: &Metadata) -> Level {
+    if metadata.target().starts_with("symphonia") && metadata.level() > Level::Warn {
+        Level::Trace
+    } else {
+        metadata.level()
+    }
+}
+
+impl Log for StderrLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        effective_level(metadata) <= log::max_level()
+    }
+
+    fn log(&self, record: &Record) {
+        if self.enabled(record.metadata()) {
+            eprintln!("{}: [{}] {}", record.level(), record.target(), record.args());
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+/// The most verbose level to show according to the environment variable
+/// `var`, eg `RUST_LOG=debug`. By default only warnings and errors are shown.
+pub fn level_from_env(var: &str) -> LevelFilter {
+    match std::env::var(var) {
+        Ok(level) => level.parse().unwrap_or_else(|_| {
+            eprintln!("Couldn't parse {var}={level:?} as a log level, using \"warn\"");
+            LevelFilter::Warn
+        }),
+        Err(_) => LevelFilter::Warn,
+    }
+}
+
+/// Start passing on log messages, up to `max_level`.
+pub fn init(max_level: LevelFilter) {
+    if log::set_logger(&LOGGER).is_ok() {
+        log::set_max_level(max_level);
+    }
+}
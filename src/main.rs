@@ -3,15 +3,16 @@
 use std::io;
 
 use clap::Parser;
-use sand::cli::CliCommand;
-use sand::cli;
+use cli::CliCommand;
 
+mod cli;
 mod client;
 mod daemon;
-mod sand;
+mod logger;
+mod output;
 
 fn main() -> io::Result<()> {
-    let cli = cli::Cli::parse();
+    let cli = cli::Cli::parse().check().unwrap_or_else(|e| e.exit());
 
     match cli.command {
         CliCommand::Version => {
@@ -19,8 +20,14 @@ fn main() -> io::Result<()> {
             Ok(())
         }
         CliCommand::Daemon(args) => daemon::main(args),
+        CliCommand::Start(args) if args.no_daemon => daemon::run_in_process(args, cli.porcelain),
+        CliCommand::EditSound { open } => client::edit_sound(open),
+        CliCommand::ParseDuration(args) => {
+            println!("{}", args.total().as_millis());
+            Ok(())
+        }
         _ => {
-            client::main(cli.command)
+            client::main(cli.command, cli.porcelain, cli.verbose)
         }
     }
 }
@@ -0,0 +1,332 @@
+//! What the client prints about timers. Normally that's meant for people,
+//! and may change between releases. With `--porcelain`, it's lines of tab
+//! separated fields in a format which won't change, for scripts. Each line
+//! starts with what happened, followed by the timer id, and for some events
+//! more fields:
+//!
+//! - `created`, id, duration, name
+//! - `adjusted`, id, time remaining
+//! - `snoozed`, id, duration
+//! - `resumescheduled`, id, when it will resume in milliseconds since the
+//!   unix epoch
+//! - `paused`, `resumed`, `cancelled`, `elapsed`, `alreadypaused`,
+//!   `alreadyrunning`, `exists` or `notfound`, id
+//!
+//! `sand stats` prints a line for `running` timers and one for `paused`
+//! ones: the state, how many there are, and their time remaining added up.
+//!
+//! `sand ls` prints a line per timer, with no header or summary: the id,
+//! state (`running` or `paused`), time remaining, when it was started in
+//! milliseconds since the unix epoch, and name.
+//!
+//! `sand sound-info` prints `sound`, the sound's path, and its length, which
+//! is `unknown` if the daemon can't tell.
+//!
+//! Durations are in milliseconds. A name is always the last field, and is
+//! left out if the timer has none. Tabs and newlines in names are printed
+//! as spaces.
+
+use std::io::IsTerminal;
+use std::path::Path;
+use std::time::{Duration, SystemTime};
+
+use sand::clock;
+use sand::duration::DurationExt;
+use sand::timer::{TimerId, TimerInfoForClient, TimerState};
+
+pub struct Printer {
+    porcelain: bool,
+}
+
+impl Printer {
+    pub fn new(porcelain: bool) -> Self {
+        Self { porcelain }
+    }
+
+    pub fn porcelain(&self) -> bool {
+        self.porcelain
+    }
+
+    /// Print a porcelain line, made of `fields` separated by tabs.
+    fn line(&self, fields: &[&str]) {
+        println!("{}", fields.join("\t"));
+    }
+
+    pub fn timer_created(&self, id: TimerId, dur: Duration, name: Option<&str>) {
+        if self.porcelain {
+            let mut fields = vec!["created".to_string(), id_field(id), dur.as_millis().to_string()];
+            fields.extend(name.map(name_field));
+            println!("{}", fields.join("\t"));
+            return;
+        }
+        let dur_string = dur.format_colon_separated();
+        match name {
+            Some(name) => println!("Timer {id} ({name}) created for {dur_string}."),
+            None => println!("Timer {id} created for {dur_string}."),
+        }
+    }
+
+    /// Just the id, for `start --print-id`, whether or not output is
+    /// porcelain.
+    pub fn id(&self, id: TimerId) {
+        println!("{}", id_field(id));
+    }
+
+    pub fn paused(&self, id: TimerId) {
+        self.event(id, "paused", format!("Paused timer {id}."));
+    }
+
+    pub fn resumed(&self, id: TimerId) {
+        self.event(id, "resumed", format!("Resumed timer {id}."));
+    }
+
+    pub fn resume_scheduled(&self, id: TimerId, at: SystemTime) {
+        if self.porcelain {
+            self.line(&["resumescheduled", &id_field(id), &clock::unix_millis(at).to_string()]);
+        } else {
+            println!("Timer {id} will resume at {}.", clock::format_local(at));
+        }
+    }
+
+    pub fn cancelled(&self, id: TimerId) {
+        self.event(id, "cancelled", format!("Cancelled timer {id}."));
+    }
+
+    /// Waiting for the timer ended with it being cancelled.
+    pub fn wait_cancelled(&self, id: TimerId) {
+        self.event(id, "cancelled", format!("Timer {id} was cancelled."));
+    }
+
+    /// Only printed in porcelain mode, since the command returning is
+    /// enough for people.
+    pub fn elapsed(&self, id: TimerId) {
+        if self.porcelain {
+            self.line(&["elapsed", &id_field(id)]);
+        }
+    }
+
+    /// A timer started with `--unique` matched this one, which is running.
+    pub fn already_exists(&self, id: TimerId) {
+        self.event(id, "exists", format!("Timer {id} is already running."));
+    }
+
+    pub fn already_paused(&self, id: TimerId) {
+        self.event(id, "alreadypaused", format!("Timer {id} is already paused."));
+    }
+
+    pub fn already_running(&self, id: TimerId) {
+        self.event(id, "alreadyrunning", format!("Timer {id} is already running."));
+    }
+
+    /// Returns false, for multi-id commands to record that one failed.
+    pub fn not_found(&self, id: TimerId) -> bool {
+        self.event(id, "notfound", format!("Timer {id} not found."));
+        false
+    }
+
+    pub fn adjusted(&self, id: TimerId, remaining_millis: u64) {
+        if self.porcelain {
+            self.line(&["adjusted", &id_field(id), &remaining_millis.to_string()]);
+        } else {
+            let remaining = Duration::from_millis(remaining_millis).format_colon_separated();
+            println!("Timer {id} now has {remaining} remaining.");
+        }
+    }
+
+    pub fn snoozed(&self, id: TimerId, dur: Duration) {
+        if self.porcelain {
+            self.line(&["snoozed", &id_field(id), &dur.as_millis().to_string()]);
+        } else {
+            println!("Snoozed timer {id} for {}.", dur.format_colon_separated());
+        }
+    }
+
+    /// A new timer won't start until another one elapses. Porcelain output
+    /// leaves this out, since the command line says so.
+    pub fn waiting(&self, id: TimerId, after: TimerId) {
+        if !self.porcelain {
+            println!("Timer {id} will start when timer {after} elapses.");
+        }
+    }
+
+    /// How many timers are in each state, each as a count and the time
+    /// remaining on them added up.
+    pub fn stats(&self, running: (usize, Duration), paused: (usize, Duration)) {
+        for (state, (count, remaining)) in [("running", running), ("paused", paused)] {
+            if self.porcelain {
+                self.line(&[state, &count.to_string(), &remaining.as_millis().to_string()]);
+            } else {
+                println!("{count} {state}, {} remaining in total", remaining.format_colon_separated());
+            }
+        }
+    }
+
+    pub fn sound_info(&self, path: &Path, duration: Option<Duration>) {
+        let path = path.display().to_string();
+        if self.porcelain {
+            let millis = duration.map_or("unknown".into(), |duration| duration.as_millis().to_string());
+            self.line(&["sound", &path, &millis]);
+        } else {
+            let length = duration.map_or("unknown".into(), |duration| duration.format_colon_separated_millis());
+            println!("{path}\nlength: {length}");
+        }
+    }
+
+    /// `cancel --all` found nothing to cancel.
+    pub fn no_timers(&self) {
+        if !self.porcelain {
+            println!("No timers running.");
+        }
+    }
+
+    fn event(&self, id: TimerId, event: &str, human: String) {
+        if self.porcelain {
+            self.line(&[event, &id_field(id)]);
+        } else {
+            println!("{human}");
+        }
+    }
+}
+
+fn id_field(id: TimerId) -> String {
+    id.0.to_string()
+}
+
+fn name_field(name: &str) -> String {
+    name.replace(['\t', '\n', '\r'], " ")
+}
+
+/// The width of the terminal stdout is connected to, if it is one.
+pub fn terminal_width() -> Option<usize> {
+    if !std::io::stdout().is_terminal() {
+        return None;
+    }
+    let mut size = libc::winsize { ws_row: 0, ws_col: 0, ws_xpixel: 0, ws_ypixel: 0 };
+    // SAFETY: size is a valid winsize for the duration of the call.
+    let ret = unsafe { libc::ioctl(libc::STDOUT_FILENO, libc::TIOCGWINSZ, &mut size) };
+    (ret == 0 && size.ws_col > 0).then_some(size.ws_col.into())
+}
+
+/// Cut `line` short with an ellipsis if it's more than `width` characters,
+/// so it doesn't wrap.
+pub fn truncate_to_width(line: String, width: usize) -> String {
+    if line.chars().count() <= width {
+        return line;
+    }
+    let mut truncated: String = line.chars().take(width.saturating_sub(1)).collect();
+    truncated.push('…');
+    truncated
+}
+
+/// Timers' [`TimerInfoForClient::columns`] as lines, padded so the columns
+/// line up. Widths are measured from what's actually in each column, since
+/// ids and times remaining vary in length.
+pub fn align_columns(rows: Vec<(String, String, Option<String>)>) -> Vec<String> {
+    let width = |column: &str| column.chars().count();
+    let id_width = rows.iter().map(|(id, ..)| width(id)).max().unwrap_or(0);
+    let remaining_width = rows.iter().map(|(_, remaining, _)| width(remaining)).max().unwrap_or(0);
+    rows.into_iter()
+        .map(|(id, remaining, name)| match name {
+            Some(name) => format!("{id:<id_width$} | {remaining:<remaining_width$} | {name}"),
+            None => format!("{id:<id_width$} | {remaining}"),
+        })
+        .collect()
+}
+
+/// Whether `sand ls --oneline` should mark timers' states with plain `R` and
+/// `P` rather than `▶` and `⏸`: when `NO_COLOR` is set, or stdout isn't a
+/// terminal.
+pub fn plain_symbols() -> bool {
+    std::env::var_os("NO_COLOR").is_some_and(|no_color| !no_color.is_empty())
+        || !std::io::stdout().is_terminal()
+}
+
+/// A timer as an entry in `sand ls --oneline`, like `▶#1 00:04:12:000` or
+/// `⏸#2 00:01:00:000 tea`, with `remaining` as the time remaining. With
+/// `plain`, the state is `R` or `P` instead, like `R#1 00:04:12:000`.
+pub fn oneline_timer(timer: &TimerInfoForClient, remaining: String, plain: bool) -> String {
+    let symbol = match (timer.state(), plain) {
+        (TimerState::Running, false) => '▶',
+        (TimerState::Paused, false) => '⏸',
+        (TimerState::Running, true) => 'R',
+        (TimerState::Paused, true) => 'P',
+    };
+    let id = timer.id();
+    match timer.name() {
+        Some(name) => format!("{symbol}{id} {remaining} {}", name_field(name)),
+        None => format!("{symbol}{id} {remaining}"),
+    }
+}
+
+/// [`oneline_timer`] entries as the single line `sand ls --oneline` prints,
+/// like `▶#1 00:04:12:000 | ⏸#2 00:01:00:000`.
+pub fn oneline(entries: impl Iterator<Item = String>) -> String {
+    entries.intersperse(" | ".to_string()).collect()
+}
+
+/// A timer as a porcelain line for `sand ls`, without the trailing newline.
+pub fn porcelain_timer(timer: &TimerInfoForClient) -> String {
+    let state = match timer.state() {
+        TimerState::Running => "running",
+        TimerState::Paused => "paused",
+    };
+    let mut fields = vec![
+        id_field(timer.id()),
+        state.to_string(),
+        timer.remaining_millis().to_string(),
+        clock::unix_millis(timer.created_at()).to_string(),
+    ];
+    fields.extend(timer.name().map(name_field));
+    fields.join("\t")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn truncate_long_lines() {
+        assert_eq!(truncate_to_width("#1 | 00:05:00:000 | tea".into(), 80), "#1 | 00:05:00:000 | tea");
+        assert_eq!(truncate_to_width("#1 | 00:05:00:000 | a very long name".into(), 24), "#1 | 00:05:00:000 | a v…");
+    }
+
+    #[test]
+    fn columns_line_up() {
+        let timers: Vec<TimerInfoForClient> = serde_json::from_str(concat!(
+            "[{\"id\":9,\"state\":\"Running\",\"remaining_millis\":5000,\"name\":\"tea\"},",
+            "{\"id\":10,\"state\":\"Paused\",\"remaining_millis\":3600000,\"name\":\"eggs\"},",
+            "{\"id\":11,\"state\":\"Running\",\"remaining_millis\":90000}]",
+        )).unwrap();
+        let rows = timers.iter()
+            .map(|timer| timer.columns(Duration::from_millis(timer.remaining_millis()).format_compact()))
+            .collect();
+        assert_eq!(align_columns(rows), [
+            "#9  | 5s          | tea",
+            "#10 | 1h (PAUSED) | eggs",
+            "#11 | 1m 30s",
+        ]);
+    }
+
+    #[test]
+    fn oneline_entries() {
+        let timers: Vec<TimerInfoForClient> = serde_json::from_str(concat!(
+            "[{\"id\":1,\"state\":\"Running\",\"remaining_millis\":252000},",
+            "{\"id\":2,\"state\":\"Paused\",\"remaining_millis\":60000,\"name\":\"tea\\nbreak\"}]",
+        )).unwrap();
+        let line = |plain| oneline(timers.iter()
+            .map(|timer| oneline_timer(timer, Duration::from_millis(timer.remaining_millis()).format_compact(), plain)));
+        assert_eq!(line(false), "▶#1 4m 12s | ⏸#2 1m tea break");
+        assert_eq!(line(true), "R#1 4m 12s | P#2 1m tea break");
+    }
+
+    #[test]
+    fn porcelain_ls_line() {
+        let timers: Vec<TimerInfoForClient> = serde_json::from_str(concat!(
+            "[{\"id\":3,\"state\":\"Running\",\"remaining_millis\":240000,",
+            "\"created_at_millis\":1722556800000,\"name\":\"tea\\tbreak\"},",
+            "{\"id\":4,\"state\":\"Paused\",\"remaining_millis\":5000,\"created_at_millis\":1}]",
+        )).unwrap();
+        assert_eq!(porcelain_timer(&timers[0]), "3\trunning\t240000\t1722556800000\ttea break");
+        assert_eq!(porcelain_timer(&timers[1]), "4\tpaused\t5000\t1");
+    }
+}
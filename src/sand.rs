@@ -1,8 +1,14 @@
-pub mod cli;
+pub mod audio;
+pub mod clock;
+pub mod config;
+pub mod ctx;
 pub mod duration;
+pub mod event_log;
 pub mod message;
+pub mod notifier;
+pub mod socket;
+pub mod state;
 pub mod timer;
 pub mod timers;
-pub mod audio;
 
 pub const VERSION: &str = "Sand v0.3.0: rewrite it in Rust";
\ No newline at end of file
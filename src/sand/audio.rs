@@ -2,11 +2,71 @@ use std::convert::AsRef;
 use std::fmt::Debug;
 use std::io::{self, Read};
 use std::path::{Path, PathBuf};
-use std::sync::Arc;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
 
-use rodio::OutputStreamHandle;
+use rodio::cpal::traits::HostTrait;
+use rodio::decoder::DecoderError;
+use rodio::DeviceTrait;
+use rodio::{OutputStream, OutputStreamHandle, Sink, StreamError};
 use rodio::Source;
 
+#[derive(Debug)]
+pub enum SoundLoadError {
+    Io(io::Error),
+    /// The file was read, but isn't audio we know how to play.
+    DecoderError(DecoderError),
+}
+
+impl std::fmt::Display for SoundLoadError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SoundLoadError::Io(e) => write!(f, "failed to read sound file: {}", e),
+            SoundLoadError::DecoderError(e) => write!(f, "failed to decode sound file: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for SoundLoadError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            SoundLoadError::Io(e) => Some(e),
+            SoundLoadError::DecoderError(e) => Some(e),
+        }
+    }
+}
+
+/// Why no timer sound could be loaded: every place it was looked for, and
+/// what went wrong with each.
+#[derive(Debug)]
+pub struct SoundSearchError {
+    pub attempts: Vec<(PathBuf, SoundLoadError)>,
+}
+
+impl std::fmt::Display for SoundSearchError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "tried ")?;
+        for (i, (path, e)) in self.attempts.iter().enumerate() {
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+            match e {
+                SoundLoadError::Io(e) if e.kind() == io::ErrorKind::NotFound => write!(f, "{} (not found)", path.display())?,
+                e => write!(f, "{} ({e})", path.display())?,
+            }
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for SoundSearchError {}
+
+impl From<io::Error> for SoundLoadError {
+    fn from(e: io::Error) -> Self {
+        SoundLoadError::Io(e)
+    }
+}
+
 #[derive(Debug, Clone)]
 #[repr(transparent)]
 pub struct Sound {
@@ -20,7 +80,9 @@ impl AsRef<[u8]> for Sound {
 }
 
 impl Sound {
-    pub fn load<P>(path: P) -> io::Result<Self>
+    /// Load a sound file, checking up front that it can be decoded, so that
+    /// a broken file is reported at startup rather than when a timer elapses.
+    pub fn load<P>(path: P) -> Result<Self, SoundLoadError>
     where
         P: AsRef<Path>,
     {
@@ -31,65 +93,414 @@ impl Sound {
         let mut buf = Vec::with_capacity(1_000_000);
         let mut file = File::open(path)?;
         file.read_to_end(&mut buf)?;
-        Ok(Self {
+        if buf.is_empty() {
+            return Err(SoundLoadError::DecoderError(DecoderError::UnrecognizedFormat));
+        }
+        let sound = Self {
             data: Arc::from(buf),
-        })
+        };
+        sound.decoder().map_err(SoundLoadError::DecoderError)?;
+        Ok(sound)
     }
 
     pub fn cursor(&self) -> io::Cursor<Self> {
         io::Cursor::new(self.clone())
     }
 
-    pub fn decoder(&self) -> rodio::Decoder<io::Cursor<Self>> {
-        rodio::Decoder::new(self.cursor()).expect("Failed to decode the sound")
+    pub fn decoder(&self) -> Result<rodio::Decoder<io::Cursor<Self>>, DecoderError> {
+        rodio::Decoder::new(self.cursor())
     }
 
-    pub fn play(&self, handle: &OutputStreamHandle) -> Result<(), rodio::PlayError> {
-        let decoder = self.decoder();
-        handle.play_raw(decoder.convert_samples())
+    /// How long the sound plays for, if the decoder can tell without
+    /// playing it. Some formats can't, when they're decoded as a stream.
+    pub fn total_duration(&self) -> Option<Duration> {
+        self.decoder().ok()?.total_duration()
+    }
+
+    /// The sound, played through once at `volume`. Each call decodes the
+    /// sound afresh, so the source ends when the sound does.
+    fn once(&self, volume: f32) -> Result<impl Source<Item = f32>, DecoderError> {
+        Ok(self.decoder()?.convert_samples().amplify(volume))
+    }
+
+    /// Start playing the sound at `volume`. It stops if the returned sink
+    /// is dropped.
+    pub fn play(&self, handle: &OutputStreamHandle, volume: f32) -> Result<Sink, rodio::PlayError> {
+        let source = self.once(volume).map_err(rodio::PlayError::DecoderError)?;
+        let sink = Sink::try_new(handle)?;
+        sink.append(source);
+        Ok(sink)
     }
 }
 
 const SOUND_FILENAME: &str = "timer_sound.flac";
 
-fn xdg_sand_data_dir() -> Option<PathBuf> {
+/// Each audio format rodio can decode, and whether the cargo feature for it
+/// was enabled.
+const FORMATS: &[(&str, bool)] = &[
+    ("flac", cfg!(feature = "flac")),
+    ("mp3", cfg!(feature = "mp3")),
+    ("ogg (vorbis)", cfg!(feature = "vorbis")),
+    ("wav", cfg!(feature = "wav")),
+];
+
+/// The audio formats the timer sound can be in, as built. The format is
+/// detected from the file's contents, so the sound file is still called
+/// `timer_sound.flac` whatever its format.
+pub fn supported_formats() -> impl Iterator<Item = &'static str> {
+    FORMATS.iter().filter(|(_, enabled)| *enabled).map(|(format, _)| *format)
+}
+
+/// Where users can put their own timer sound.
+pub fn sand_user_data_dir() -> Option<PathBuf> {
     Some(dirs::data_dir()?.join("sand"))
 }
 
-fn xdg_sound_path() -> Option<PathBuf> {
-    Some(xdg_sand_data_dir()?.join(SOUND_FILENAME))
+/// The path a user's own timer sound is loaded from.
+pub fn xdg_sound_path() -> Option<PathBuf> {
+    Some(sand_user_data_dir()?.join(SOUND_FILENAME))
 }
 
 fn usrshare_sound_path() -> PathBuf {
     Path::new("/usr/share/sand").join(SOUND_FILENAME)
 }
 
-fn load_elapsed_sound() -> io::Result<Sound> {
-    if let Some(ref xdg_path) = xdg_sound_path() {
-        let sound = Sound::load(xdg_path);
-        if sound.is_ok() {
-            return sound;
+/// The sound to fall back on when the user hasn't provided their own.
+/// `SAND_DEFAULT_SOUND` overrides the usual location, for systems which
+/// don't install it under `/usr/share`.
+fn default_sound_path() -> PathBuf {
+    match std::env::var_os("SAND_DEFAULT_SOUND") {
+        Some(path) => path.into(),
+        None => usrshare_sound_path(),
+    }
+}
+
+/// The name, without an extension, of the sound played when a timer starts.
+/// Unlike the elapsed sound, it's found whatever its extension is.
+const START_SOUND_STEM: &str = "start_sound";
+
+/// The file in `dir` named `start_sound`, with any extension. If there's
+/// more than one, the first by name is used.
+fn start_sound_in(dir: &Path) -> Option<PathBuf> {
+    let mut paths: Vec<PathBuf> = std::fs::read_dir(dir)
+        .ok()?
+        .filter_map(|entry| Some(entry.ok()?.path()))
+        .filter(|path| path.is_file() && path.file_stem().is_some_and(|stem| stem == START_SOUND_STEM))
+        .collect();
+    paths.sort();
+    paths.into_iter().next()
+}
+
+/// Load the sound played when a timer starts, from the data directory, or
+/// else `/usr/share/sand`. There's no default, so this is often `None`.
+fn load_start_sound() -> Option<Sound> {
+    let dirs = sand_user_data_dir().into_iter().chain([PathBuf::from("/usr/share/sand")]);
+    let path = dirs.filter_map(|dir| start_sound_in(&dir)).next()?;
+    match Sound::load(&path) {
+        Ok(sound) => {
+            eprintln!("Loaded start sound from {}", path.display());
+            Some(sound)
+        }
+        Err(e) => {
+            eprintln!("Error loading start sound {}: {e}", path.display());
+            None
         }
     }
-    Sound::load(usrshare_sound_path())
+}
+
+/// The user's own timer sound: `configured`, which is the config file's
+/// `sound_path`, or else the conventional file in the data directory.
+pub fn user_sound_path(configured: Option<&Path>) -> Option<PathBuf> {
+    configured.map(Path::to_path_buf).or_else(xdg_sound_path)
+}
+
+/// Load the sound at `configured`, falling back on the user's sound in the
+/// data directory, then the default sound. If none of them can be loaded,
+/// the error says what went wrong with each.
+fn load_elapsed_sound(configured: Option<&Path>) -> Result<(Sound, PathBuf), SoundSearchError> {
+    let mut attempts = Vec::new();
+    if let Some(path) = configured {
+        match Sound::load(path) {
+            Ok(sound) => {
+                eprintln!("Loaded timer sound from {}", path.display());
+                return Ok((sound, path.to_path_buf()));
+            }
+            Err(e) => {
+                eprintln!("Error loading configured sound {}: {e}", path.display());
+                attempts.push((path.to_path_buf(), e));
+            }
+        }
+    }
+    if let Some(xdg_path) = xdg_sound_path() {
+        match Sound::load(&xdg_path) {
+            Ok(sound) => {
+                eprintln!("Loaded timer sound from {}", xdg_path.display());
+                return Ok((sound, xdg_path));
+            }
+            Err(SoundLoadError::Io(e)) if e.kind() == io::ErrorKind::NotFound => {
+                attempts.push((xdg_path, SoundLoadError::Io(e)));
+            }
+            Err(e) => {
+                eprintln!("Error loading {}, using default sound: {e}", xdg_path.display());
+                attempts.push((xdg_path, e));
+            }
+        }
+    }
+    let path = default_sound_path();
+    match Sound::load(&path) {
+        Ok(sound) => {
+            eprintln!("Loaded timer sound from {}", path.display());
+            Ok((sound, path))
+        }
+        Err(e) => {
+            eprintln!("Error loading {}: {e}", path.display());
+            attempts.push((path, e));
+            Err(SoundSearchError { attempts })
+        }
+    }
+}
+
+/// Open a stream on the default output device, or failing that, on any
+/// device which works.
+///
+/// The stream itself is leaked, since it isn't `Send`, and has to outlive
+/// every handle to it. We only open a new one when the output device
+/// changes, so few are ever leaked.
+pub fn open_default_output() -> Result<OutputStreamHandle, StreamError> {
+    let (stream, handle) = OutputStream::try_default()?;
+    std::mem::forget(stream);
+    Ok(handle)
+}
+
+fn default_output_device_name() -> Option<String> {
+    rodio::cpal::default_host().default_output_device()?.name().ok()
+}
+
+/// The stream sounds are played on, and the name of the device it was
+/// opened on.
+struct Output {
+    device: Option<String>,
+    handle: OutputStreamHandle,
+}
+
+impl Output {
+    /// Reopen the stream on the current default device. If that fails, the
+    /// old stream is kept, in case it still works.
+    fn reopen(&mut self, device: Option<String>) {
+        let describe = |device: &Option<String>| device.clone().unwrap_or_else(|| "unknown device".into());
+        eprintln!("Switching audio output from {} to {}", describe(&self.device), describe(&device));
+        match open_default_output() {
+            Ok(handle) => *self = Output { device, handle },
+            Err(e) => eprintln!("Error opening audio output: {e}"),
+        }
+    }
+}
+
+/// A sound, and where it was loaded from.
+struct LoadedSound {
+    sound: Sound,
+    path: PathBuf,
 }
 
 #[derive(Clone)]
 pub struct ElapsedSoundPlayer {
-    sound: Sound,
-    handle: OutputStreamHandle,
+    loaded: Arc<RwLock<LoadedSound>>,
+    /// The sound played when a timer starts, if there is one.
+    start: Arc<RwLock<Option<Sound>>>,
+    output: Arc<Mutex<Output>>,
+    /// The sounds which have been started, some of which may have finished.
+    playing: Arc<Mutex<Vec<Sink>>>,
 }
 
 impl ElapsedSoundPlayer {
-    pub fn new(handle: OutputStreamHandle) -> io::Result<Self> {
-        let sound = load_elapsed_sound()?;
+    /// `handle` should be for a stream on the default output device, as
+    /// opened by [`open_default_output`]. `sound_path` is as for
+    /// [`ElapsedSoundPlayer::reload`].
+    pub fn new(handle: OutputStreamHandle, sound_path: Option<&Path>) -> Result<Self, SoundSearchError> {
+        let (sound, path) = load_elapsed_sound(sound_path)?;
+        let output = Output { device: default_output_device_name(), handle };
         Ok(Self {
-            sound,
-            handle,
+            loaded: Arc::new(RwLock::new(LoadedSound { sound, path })),
+            start: Arc::new(RwLock::new(load_start_sound())),
+            output: Arc::new(Mutex::new(output)),
+            playing: Default::default(),
         })
     }
 
-    pub fn play(&self) -> Result<(), rodio::PlayError> {
-        self.sound.play(&self.handle)
+    /// Load the sound again, from `sound_path` if it's given and can be
+    /// loaded, or else from the usual places. If no sound can be loaded, the
+    /// current one is kept. The start sound is reloaded too.
+    pub fn reload(&self, sound_path: Option<&Path>) {
+        *self.start.write().expect("another thread panicked while holding this lock.") = load_start_sound();
+        let (sound, path) = match load_elapsed_sound(sound_path) {
+            Ok(loaded) => loaded,
+            Err(e) => {
+                eprintln!("No timer sound could be loaded ({e}), keeping the current one.");
+                return;
+            }
+        };
+        *self.loaded.write().expect("another thread panicked while holding this lock.") = LoadedSound { sound, path };
+    }
+
+    /// Where the sound was loaded from.
+    pub fn path(&self) -> PathBuf {
+        self.loaded.read().expect("another thread panicked while holding this lock.").path.clone()
+    }
+
+    /// How long the sound plays for, as for [`Sound::total_duration`].
+    pub fn total_duration(&self) -> Option<Duration> {
+        self.loaded.read().expect("another thread panicked while holding this lock.").sound.total_duration()
+    }
+
+    /// Play the sound on the default output device. If that's changed since
+    /// the last time, e.g. because headphones were unplugged, the output is
+    /// reopened on the new device first, since the old one may be gone.
+    ///
+    /// If `max_playing` sounds are already playing, nothing more is played,
+    /// so that timers elapsing together don't drown each other out.
+    pub fn play(&self, volume: f32, max_playing: Option<usize>) -> Result<(), rodio::PlayError> {
+        let sound = self.loaded.read().expect("another thread panicked while holding this lock.").sound.clone();
+        self.play_sound(&sound, volume, max_playing)
+    }
+
+    /// Play the start sound, if one was found, like [`ElapsedSoundPlayer::play`].
+    pub fn play_start(&self, volume: f32, max_playing: Option<usize>) -> Result<(), rodio::PlayError> {
+        let start = self.start.read().expect("another thread panicked while holding this lock.").clone();
+        match start {
+            Some(sound) => self.play_sound(&sound, volume, max_playing),
+            None => Ok(()),
+        }
+    }
+
+    fn play_sound(&self, sound: &Sound, volume: f32, max_playing: Option<usize>) -> Result<(), rodio::PlayError> {
+        let mut playing = self.playing.lock().expect("another thread panicked while holding this lock.");
+        playing.retain(|sink| !sink.empty());
+        if max_playing.is_some_and(|max| playing.len() >= max) {
+            eprintln!("{} sounds already playing, not playing another", playing.len());
+            return Ok(());
+        }
+        let mut output = self.output.lock().expect("another thread panicked while holding this lock.");
+        let device = default_output_device_name();
+        if device.is_some() && device != output.device {
+            output.reopen(device);
+        }
+        let sink = sound.play(&output.handle, volume).or_else(|e| {
+            eprintln!("Error playing sound, reopening audio output: {e}");
+            output.reopen(default_output_device_name());
+            sound.play(&output.handle, volume)
+        })?;
+        playing.push(sink);
+        Ok(())
+    }
+
+    /// Play the sound, returning once it's finished, for when the process
+    /// is about to exit.
+    pub fn play_to_end(&self, volume: f32) -> Result<(), rodio::PlayError> {
+        let sound = self.loaded.read().expect("another thread panicked while holding this lock.").sound.clone();
+        let output = self.output.lock().expect("another thread panicked while holding this lock.");
+        let sink = Sink::try_new(&output.handle)?;
+        drop(output);
+        sink.append(sound.once(volume).map_err(rodio::PlayError::DecoderError)?);
+        sink.sleep_until_end();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn load_fixture(name: &str, contents: &[u8]) -> Result<Sound, SoundLoadError> {
+        let path = std::env::temp_dir().join(format!("sand-test-{}-{name}", std::process::id()));
+        std::fs::write(&path, contents).unwrap();
+        let sound = Sound::load(&path);
+        std::fs::remove_file(&path).unwrap();
+        sound
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn load_rejects_undecodable_files() {
+        let empty = load_fixture("empty.flac", b"");
+        assert!(matches!(empty, Err(SoundLoadError::DecoderError(_))), "{empty:?}");
+
+        let garbage = load_fixture("garbage.flac", b"this is not a flac file");
+        assert!(matches!(garbage, Err(SoundLoadError::DecoderError(_))), "{garbage:?}");
+    }
+
+    /// A silent 16 bit mono wav file, `samples` samples long.
+    fn silent_wav(samples: u32) -> Vec<u8> {
+        let data_len = samples * 2;
+        let mut wav = Vec::new();
+        wav.extend_from_slice(b"RIFF");
+        wav.extend_from_slice(&(36 + data_len).to_le_bytes());
+        wav.extend_from_slice(b"WAVEfmt ");
+        wav.extend_from_slice(&16u32.to_le_bytes());
+        wav.extend_from_slice(&1u16.to_le_bytes()); // PCM
+        wav.extend_from_slice(&1u16.to_le_bytes()); // channels
+        wav.extend_from_slice(&8000u32.to_le_bytes()); // sample rate
+        wav.extend_from_slice(&16000u32.to_le_bytes()); // byte rate
+        wav.extend_from_slice(&2u16.to_le_bytes()); // block align
+        wav.extend_from_slice(&16u16.to_le_bytes()); // bits per sample
+        wav.extend_from_slice(b"data");
+        wav.extend_from_slice(&data_len.to_le_bytes());
+        wav.resize(wav.len() + data_len as usize, 0);
+        wav
+    }
+
+    #[test]
+    fn one_shot_ends() {
+        let sound = load_fixture("silence.wav", &silent_wav(800)).unwrap();
+        assert_eq!(sound.once(1.0).unwrap().count(), 800);
+        // Playing again starts from the beginning.
+        assert_eq!(sound.once(1.0).unwrap().count(), 800);
+    }
+
+    #[test]
+    fn wav_knows_its_length() {
+        let sound = load_fixture("length.wav", &silent_wav(4000)).unwrap();
+        assert_eq!(sound.total_duration(), Some(Duration::from_millis(500)));
+    }
+
+    #[test]
+    fn configured_sound_comes_first() {
+        let path = std::env::temp_dir().join(format!("sand-test-{}-configured.wav", std::process::id()));
+        std::fs::write(&path, silent_wav(800)).unwrap();
+        let loaded = load_elapsed_sound(Some(&path));
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(loaded.unwrap().1, path);
+        assert_eq!(user_sound_path(Some(&path)), Some(path));
+    }
+
+    #[test]
+    fn start_sound_has_any_extension() {
+        let dir = std::env::temp_dir().join(format!("sand-test-{}-start-sound", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        assert_eq!(start_sound_in(&dir), None);
+        std::fs::write(dir.join("timer_sound.flac"), b"").unwrap();
+        std::fs::write(dir.join("start_sound.wav"), b"").unwrap();
+        std::fs::write(dir.join("start_sound.ogg"), b"").unwrap();
+        let found = start_sound_in(&dir);
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(found, Some(dir.join("start_sound.ogg")));
+    }
+
+    #[test]
+    fn search_error_says_what_was_tried() {
+        let missing = Sound::load("/nonexistent/sand/timer_sound.flac").unwrap_err();
+        let garbage = load_fixture("garbage.flac", b"this is not a flac file").unwrap_err();
+        let e = SoundSearchError {
+            attempts: vec![("/home/me/timer_sound.flac".into(), missing), ("/usr/share/sand/timer_sound.flac".into(), garbage)],
+        };
+        assert_eq!(
+            e.to_string(),
+            "tried /home/me/timer_sound.flac (not found), \
+             /usr/share/sand/timer_sound.flac (failed to decode sound file: Unrecognized format)",
+        );
+    }
+
+    #[test]
+    fn load_reports_missing_files() {
+        let missing = Sound::load("/nonexistent/sand/timer_sound.flac");
+        assert!(matches!(missing, Err(SoundLoadError::Io(_))), "{missing:?}");
+    }
+}
@@ -0,0 +1,401 @@
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+fn clock_gettime(clock: libc::clockid_t) -> Duration {
+    let mut ts = libc::timespec { tv_sec: 0, tv_nsec: 0 };
+    // SAFETY: ts is a valid timespec for the duration of the call.
+    let ret = unsafe { libc::clock_gettime(clock, &mut ts) };
+    assert_eq!(ret, 0, "clock_gettime failed: {}", std::io::Error::last_os_error());
+    Duration::new(ts.tv_sec as u64, ts.tv_nsec as u32)
+}
+
+/// The total time the system has spent suspended since boot.
+///
+/// [`std::time::Instant`] and tokio's timers use `CLOCK_MONOTONIC`, which
+/// stops while the system is suspended, whereas `CLOCK_BOOTTIME` keeps
+/// counting. The difference between them is the time spent asleep.
+pub fn time_suspended() -> Duration {
+    let monotonic = clock_gettime(libc::CLOCK_MONOTONIC);
+    let boottime = clock_gettime(libc::CLOCK_BOOTTIME);
+    boottime.saturating_sub(monotonic)
+}
+
+/// A jump of the monotonic clock, relative to the time that really passed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MonotonicJump {
+    /// The monotonic clock got ahead, so timers would go off early.
+    Forward(Duration),
+    /// The monotonic clock went back, so timers would go off late.
+    Backward(Duration),
+}
+
+/// Whether the monotonic clock jumped between `before` and `after`, which
+/// are readings of it and the wall clock taken `expected` apart. This can
+/// happen when a container or VM is checkpointed and restored on another
+/// host, whose monotonic clock started at a different time.
+///
+/// The wall clock is taken to show how much time really passed, but only
+/// when the monotonic clock is more than `tolerance` off `expected`, so the
+/// wall clock being changed, say by NTP, isn't mistaken for a jump.
+pub fn monotonic_jump(
+    before: (Instant, SystemTime),
+    after: (Instant, SystemTime),
+    expected: Duration,
+    tolerance: Duration,
+) -> Option<MonotonicJump> {
+    let real = after.1.duration_since(before.1).unwrap_or_default();
+    match after.0.checked_duration_since(before.0) {
+        Some(monotonic) if monotonic <= expected + tolerance => None,
+        // Either the clock jumped, or the whole process was frozen for a
+        // while, in which case the wall clock moved on just as much.
+        Some(monotonic) => {
+            let ahead = monotonic.saturating_sub(real);
+            (ahead > tolerance).then_some(MonotonicJump::Forward(ahead))
+        }
+        None => Some(MonotonicJump::Backward(before.0 - after.0 + real)),
+    }
+}
+
+/// Wait until the wall clock, as read by `now`, reaches `at`.
+///
+/// Tokio's timers use the monotonic clock, which stops while the system is
+/// suspended, so a single sleep until `at` would go off late by however long
+/// the system slept. Instead, this sleeps at most `check_every` at a time,
+/// checking the wall clock in between.
+pub async fn sleep_until_wall_time<F>(at: SystemTime, check_every: Duration, now: F)
+where
+    F: Fn() -> SystemTime,
+{
+    while let Ok(left) = at.duration_since(now()) {
+        if left.is_zero() {
+            break;
+        }
+        tokio::time::sleep(left.min(check_every)).await;
+    }
+}
+
+/// Milliseconds since the unix epoch, as used for timestamps in messages.
+pub fn unix_millis(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH).unwrap_or_default().as_millis() as u64
+}
+
+/// The inverse of [`unix_millis`].
+pub fn from_unix_millis(millis: u64) -> SystemTime {
+    UNIX_EPOCH + Duration::from_millis(millis)
+}
+
+/// A wall clock time, like `14:30`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimeOfDay {
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum ParseTimeOfDayError {
+    BadFormat,
+    OutOfRange,
+}
+
+impl std::fmt::Display for ParseTimeOfDayError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ParseTimeOfDayError::BadFormat => write!(f, "expected a 24 hour time like 14:30 or 14:30:15"),
+            ParseTimeOfDayError::OutOfRange => write!(f, "hour, minute or second out of range"),
+        }
+    }
+}
+
+impl std::error::Error for ParseTimeOfDayError {}
+
+impl std::str::FromStr for TimeOfDay {
+    type Err = ParseTimeOfDayError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parts = s.split(':')
+            .map(|part| part.parse::<u8>().map_err(|_| ParseTimeOfDayError::BadFormat))
+            .collect::<Result<Vec<u8>, _>>()?;
+        let (hour, minute, second) = match parts[..] {
+            [hour, minute] => (hour, minute, 0),
+            [hour, minute, second] => (hour, minute, second),
+            _ => return Err(ParseTimeOfDayError::BadFormat),
+        };
+        if hour > 23 || minute > 59 || second > 59 {
+            return Err(ParseTimeOfDayError::OutOfRange);
+        }
+        Ok(Self { hour, minute, second })
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum LocalTimeError {
+    /// The time is skipped over by a daylight saving transition.
+    Nonexistent,
+}
+
+impl std::fmt::Display for LocalTimeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LocalTimeError::Nonexistent => {
+                write!(f, "that time doesn't exist today, because of a daylight saving change")
+            }
+        }
+    }
+}
+
+impl std::error::Error for LocalTimeError {}
+
+/// Where wall clock time comes from, so that tests can use a timezone of
+/// their own instead of the process wide one.
+pub trait TimeZone {
+    /// The offset from UTC, in seconds east, at `secs` seconds after the
+    /// Unix epoch.
+    fn utc_offset(&self, secs: i64) -> i64;
+}
+
+/// The system's local timezone, from `TZ` or `/etc/localtime`.
+pub struct Local;
+
+extern "C" {
+    // Not bound by the libc crate.
+    fn tzset();
+}
+
+impl TimeZone for Local {
+    fn utc_offset(&self, secs: i64) -> i64 {
+        // SAFETY: tm is plain old data, and both pointers are valid for the call.
+        let tm = unsafe {
+            let mut tm: libc::tm = std::mem::zeroed();
+            // Pick up any change to the TZ environment variable.
+            tzset();
+            libc::localtime_r(&(secs as libc::time_t), &mut tm);
+            tm
+        };
+        tm.tm_gmtoff as i64
+    }
+}
+
+const SECS_PER_DAY: i64 = 24 * 60 * 60;
+
+/// A moment as seen on a wall clock: `local_secs` is the seconds since the
+/// epoch a clock in UTC would show at the same reading.
+struct WallClock {
+    local_secs: i64,
+    offset: i64,
+}
+
+impl WallClock {
+    fn at(zone: &impl TimeZone, secs: i64) -> Self {
+        let offset = zone.utc_offset(secs);
+        Self { local_secs: secs + offset, offset }
+    }
+
+    fn day(&self) -> i64 {
+        self.local_secs.div_euclid(SECS_PER_DAY)
+    }
+
+    fn hms(&self) -> (i64, i64, i64) {
+        let secs = self.local_secs.rem_euclid(SECS_PER_DAY);
+        (secs / 3600, secs / 60 % 60, secs % 60)
+    }
+}
+
+/// The year, month and day of the `days`th day after 1970-01-01, in the
+/// proleptic Gregorian calendar.
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    // Howard Hinnant's algorithm, counting from 0000-03-01 so that the leap
+    // day falls at the end of the year.
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days - era * 146_097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let march_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * march_month + 2) / 5 + 1;
+    let month = if march_month < 10 { march_month + 3 } else { march_month - 9 };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+/// The instant `time` occurs on the `day`th day after 1970-01-01 in `zone`.
+/// If `time` occurs twice that day because the clocks go back, the later one
+/// is chosen.
+fn resolve_local(zone: &impl TimeZone, day: i64, time: TimeOfDay) -> Result<i64, LocalTimeError> {
+    let local_secs = day * SECS_PER_DAY
+        + i64::from(time.hour) * 3600
+        + i64::from(time.minute) * 60
+        + i64::from(time.second);
+    // Offsets are under a day, so the offsets a day either side are the ones
+    // in force before and after any transition near this time. Keep
+    // whichever interpretations actually read back as the requested time.
+    [zone.utc_offset(local_secs - SECS_PER_DAY), zone.utc_offset(local_secs + SECS_PER_DAY)]
+        .into_iter()
+        .map(|offset| local_secs - offset)
+        .filter(|&secs| WallClock::at(zone, secs).local_secs == local_secs)
+        .max()
+        .ok_or(LocalTimeError::Nonexistent)
+}
+
+fn unix_secs(time: SystemTime) -> i64 {
+    time.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs() as i64
+}
+
+/// `time` on the local wall clock, like `14:30:05`.
+pub fn format_local(time: SystemTime) -> String {
+    format_local_in(&Local, time)
+}
+
+fn format_local_in(zone: &impl TimeZone, time: SystemTime) -> String {
+    let (hour, minute, second) = WallClock::at(zone, unix_secs(time)).hms();
+    format!("{hour:02}:{minute:02}:{second:02}")
+}
+
+/// `time` as an RFC 3339 timestamp in the local timezone, to the second,
+/// like `2024-08-02T14:30:05+10:00`.
+pub fn format_rfc3339(time: SystemTime) -> String {
+    format_rfc3339_in(&Local, time)
+}
+
+fn format_rfc3339_in(zone: &impl TimeZone, time: SystemTime) -> String {
+    let wall = WallClock::at(zone, unix_secs(time));
+    let (year, month, day) = civil_from_days(wall.day());
+    let (hour, minute, second) = wall.hms();
+    let offset_minutes = wall.offset / 60;
+    let sign = if offset_minutes < 0 { '-' } else { '+' };
+    let offset_minutes = offset_minutes.abs();
+    format!(
+        "{year:04}-{month:02}-{day:02}T{hour:02}:{minute:02}:{second:02}{sign}{:02}:{:02}",
+        offset_minutes / 60, offset_minutes % 60,
+    )
+}
+
+/// The next instant after `now` at which the local wall clock reads `time`.
+pub fn next_local_time(now: SystemTime, time: TimeOfDay) -> Result<SystemTime, LocalTimeError> {
+    next_local_time_in(&Local, now, time)
+}
+
+fn next_local_time_in(zone: &impl TimeZone, now: SystemTime, time: TimeOfDay) -> Result<SystemTime, LocalTimeError> {
+    let now_secs = now.duration_since(UNIX_EPOCH)
+        .expect("system clock is set before 1970")
+        .as_secs() as i64;
+    let today = WallClock::at(zone, now_secs).day();
+    let mut target = resolve_local(zone, today, time)?;
+    if target <= now_secs {
+        target = resolve_local(zone, today + 1, time)?;
+    }
+    Ok(UNIX_EPOCH + Duration::from_secs(target as u64))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn utc(secs: u64) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(secs)
+    }
+
+    fn at(s: &str) -> TimeOfDay {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn detect_monotonic_jumps() {
+        let second = Duration::from_secs(1);
+        let instant = Instant::now() + Duration::from_secs(3600);
+        let before = (instant, utc(1000));
+        let check = |after| monotonic_jump(before, after, second, second);
+
+        assert_eq!(check((instant + second, utc(1001))), None);
+        // The wall clock being changed isn't a jump.
+        assert_eq!(check((instant + second, utc(5000))), None);
+        // Nor is the process being frozen.
+        assert_eq!(check((instant + Duration::from_secs(60), utc(1060))), None);
+        assert_eq!(
+            check((instant + Duration::from_secs(600), utc(1010))),
+            Some(MonotonicJump::Forward(Duration::from_secs(590))),
+        );
+        assert_eq!(
+            check((instant - Duration::from_secs(600), utc(1010))),
+            Some(MonotonicJump::Backward(Duration::from_secs(610))),
+        );
+    }
+
+    #[tokio::test]
+    async fn sleep_until_wall_time_notices_the_clock_jumping() {
+        use std::sync::atomic::{AtomicU64, Ordering};
+        // The wall clock jumps forward an hour, as it does when the system
+        // wakes from suspend, while the monotonic clock barely moves.
+        let wall = AtomicU64::new(1000);
+        let wake = async {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            wall.store(4600, Ordering::SeqCst);
+        };
+        let sleep = sleep_until_wall_time(utc(4600), Duration::from_millis(10), || utc(wall.load(Ordering::SeqCst)));
+        let both = async { tokio::join!(sleep, wake) };
+        tokio::time::timeout(Duration::from_secs(5), both).await
+            .expect("still asleep after the wall clock passed the deadline");
+    }
+
+    #[test]
+    fn parse_time_of_day() {
+        assert_eq!(at("14:30"), TimeOfDay { hour: 14, minute: 30, second: 0 });
+        assert_eq!(at("7:05:09"), TimeOfDay { hour: 7, minute: 5, second: 9 });
+        assert_eq!("24:00".parse::<TimeOfDay>(), Err(ParseTimeOfDayError::OutOfRange));
+        assert_eq!("14".parse::<TimeOfDay>(), Err(ParseTimeOfDayError::BadFormat));
+        assert_eq!("2pm".parse::<TimeOfDay>(), Err(ParseTimeOfDayError::BadFormat));
+    }
+
+    /// US Eastern time in 2024, without needing the system's tz database.
+    struct NewYork2024;
+
+    impl TimeZone for NewYork2024 {
+        fn utc_offset(&self, secs: i64) -> i64 {
+            // 2024-03-10 07:00 UTC to 2024-11-03 06:00 UTC is daylight time.
+            if (1_710_054_000..1_730_613_600).contains(&secs) {
+                -4 * 3600
+            } else {
+                -5 * 3600
+            }
+        }
+    }
+
+    #[test]
+    fn local_time_around_dst() {
+        let next = |now, time| next_local_time_in(&NewYork2024, now, at(time));
+
+        // 2024-06-01 12:00 EDT
+        let noon = utc(1_717_257_600);
+        assert_eq!(next(noon, "13:00"), Ok(utc(1_717_261_200)));
+        // Already passed today, so tomorrow.
+        assert_eq!(next(noon, "11:00"), Ok(utc(1_717_340_400)));
+
+        // 2024-03-10: clocks go forward from 02:00 EST to 03:00 EDT.
+        // 2024-03-10 00:00 EST
+        let spring = utc(1_710_046_800);
+        assert_eq!(next(spring, "02:30"), Err(LocalTimeError::Nonexistent));
+        // 03:30 EDT is only 2.5 hours after midnight.
+        assert_eq!(next(spring, "03:30"), Ok(utc(1_710_055_800)));
+
+        // 2024-11-03: clocks go back from 02:00 EDT to 01:00 EST, so 01:30
+        // happens twice. The later one, in EST, is chosen.
+        // 2024-11-03 00:00 EDT
+        let autumn = utc(1_730_606_400);
+        assert_eq!(next(autumn, "01:30"), Ok(utc(1_730_615_400)));
+
+        assert_eq!(format_local_in(&NewYork2024, noon), "12:00:00");
+        assert_eq!(format_rfc3339_in(&NewYork2024, noon), "2024-06-01T12:00:00-04:00");
+        assert_eq!(
+            format_rfc3339_in(&NewYork2024, autumn + Duration::from_secs(3 * 3600)),
+            "2024-11-03T02:00:00-05:00",
+        );
+    }
+
+    #[test]
+    fn civil_dates() {
+        assert_eq!(civil_from_days(0), (1970, 1, 1));
+        assert_eq!(civil_from_days(-1), (1969, 12, 31));
+        // 2024-02-29, a leap day.
+        assert_eq!(civil_from_days(19_782), (2024, 2, 29));
+        assert_eq!(civil_from_days(19_783), (2024, 3, 1));
+    }
+}
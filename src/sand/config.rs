@@ -0,0 +1,208 @@
+use std::io;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::sand::duration::UnitLabels;
+use crate::sand::notifier::NotifierConfig;
+
+const CONFIG_FILENAME: &str = "config.json";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum Urgency {
+    Low,
+    Normal,
+    Critical,
+}
+
+impl From<Urgency> for notify_rust::Urgency {
+    fn from(urgency: Urgency) -> Self {
+        match urgency {
+            Urgency::Low => notify_rust::Urgency::Low,
+            Urgency::Normal => notify_rust::Urgency::Normal,
+            Urgency::Critical => notify_rust::Urgency::Critical,
+        }
+    }
+}
+
+/// Daemon settings which can be changed without a restart. Any field missing
+/// from the config file takes its default value.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Multiplier applied to the elapsed sound. 1.0 is unchanged.
+    pub volume: f32,
+    pub notification_summary: String,
+    pub notification_body: String,
+    pub urgency: Urgency,
+    /// Dismiss notifications after this many seconds. If unset, the
+    /// notification server decides, which for critical urgency usually
+    /// means they stay until dismissed.
+    pub notification_timeout: Option<u32>,
+    /// The application name notifications are sent under.
+    pub app_name: String,
+    /// The desktop entry notifications are associated with, which desktop
+    /// environments use to pick an icon and group notifications.
+    pub desktop_entry: String,
+    /// If set, a line of JSON is appended to this file whenever a timer is
+    /// started, paused, resumed, cancelled or elapses.
+    pub event_log_path: Option<PathBuf>,
+    /// How to tell the user a timer has elapsed.
+    pub notifier: NotifierConfig,
+    /// Mention in each notification how many other timers are still
+    /// running.
+    pub show_running_count: bool,
+    /// The timer sound to use, instead of `timer_sound.flac` in the data
+    /// directory.
+    pub sound_path: Option<PathBuf>,
+    /// Play a short sound when a timer starts, from a `start_sound` file in
+    /// the data directory with any audio extension.
+    pub start_sound: bool,
+    /// How long a timer lasts, in seconds, when `sand start` isn't given a
+    /// duration. If unset, a duration has to be given.
+    pub default_duration: Option<u64>,
+    /// What to call units of time in compact durations, as shown by
+    /// `ls --duration-format compact`.
+    pub unit_labels: UnitLabels,
+    /// The most sounds to play at once. When this many are already playing,
+    /// timers which elapse don't add another. If unset, there's no limit.
+    pub max_playing_sounds: Option<usize>,
+    /// A command to speak elapsed timers aloud with, like
+    /// `["espeak"]`, given the timer's name as its last argument.
+    pub tts_command: Option<Vec<String>>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            volume: 1.0,
+            notification_summary: "Time's up!".into(),
+            notification_body: "Your timer has elapsed".into(),
+            urgency: Urgency::Critical,
+            notification_timeout: None,
+            app_name: "sand".into(),
+            desktop_entry: "sand".into(),
+            event_log_path: None,
+            notifier: NotifierConfig::default(),
+            show_running_count: false,
+            sound_path: None,
+            start_sound: false,
+            default_duration: None,
+            unit_labels: UnitLabels::default(),
+            max_playing_sounds: None,
+            tts_command: None,
+        }
+    }
+}
+
+impl Config {
+    /// Load the config file at `path`. A missing file is not an error, it
+    /// just means the defaults are used.
+    pub fn load<P>(path: P) -> io::Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        let contents = match std::fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(e) => return Err(e),
+        };
+        serde_json::from_str(&contents).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    /// Human readable descriptions of each setting that differs between
+    /// `self` and `new`, for logging.
+    pub fn changes(&self, new: &Self) -> Vec<String> {
+        let mut changes = Vec::new();
+        if self.volume != new.volume {
+            changes.push(format!("volume: {} -> {}", self.volume, new.volume));
+        }
+        if self.notification_summary != new.notification_summary {
+            changes.push(format!(
+                "notification_summary: {:?} -> {:?}",
+                self.notification_summary, new.notification_summary
+            ));
+        }
+        if self.notification_body != new.notification_body {
+            changes.push(format!(
+                "notification_body: {:?} -> {:?}",
+                self.notification_body, new.notification_body
+            ));
+        }
+        if self.urgency != new.urgency {
+            changes.push(format!("urgency: {:?} -> {:?}", self.urgency, new.urgency));
+        }
+        if self.notification_timeout != new.notification_timeout {
+            changes.push(format!(
+                "notification_timeout: {:?} -> {:?}",
+                self.notification_timeout, new.notification_timeout
+            ));
+        }
+        if self.app_name != new.app_name {
+            changes.push(format!("app_name: {:?} -> {:?}", self.app_name, new.app_name));
+        }
+        if self.desktop_entry != new.desktop_entry {
+            changes.push(format!("desktop_entry: {:?} -> {:?}", self.desktop_entry, new.desktop_entry));
+        }
+        if self.event_log_path != new.event_log_path {
+            changes.push(format!("event_log_path: {:?} -> {:?}", self.event_log_path, new.event_log_path));
+        }
+        if self.notifier != new.notifier {
+            changes.push(format!("notifier: {:?} -> {:?}", self.notifier, new.notifier));
+        }
+        if self.show_running_count != new.show_running_count {
+            changes.push(format!(
+                "show_running_count: {} -> {}",
+                self.show_running_count, new.show_running_count
+            ));
+        }
+        if self.sound_path != new.sound_path {
+            changes.push(format!("sound_path: {:?} -> {:?}", self.sound_path, new.sound_path));
+        }
+        if self.start_sound != new.start_sound {
+            changes.push(format!("start_sound: {} -> {}", self.start_sound, new.start_sound));
+        }
+        if self.default_duration != new.default_duration {
+            changes.push(format!("default_duration: {:?} -> {:?}", self.default_duration, new.default_duration));
+        }
+        if self.unit_labels != new.unit_labels {
+            changes.push(format!("unit_labels: {:?} -> {:?}", self.unit_labels, new.unit_labels));
+        }
+        if self.max_playing_sounds != new.max_playing_sounds {
+            changes.push(format!("max_playing_sounds: {:?} -> {:?}", self.max_playing_sounds, new.max_playing_sounds));
+        }
+        if self.tts_command != new.tts_command {
+            changes.push(format!("tts_command: {:?} -> {:?}", self.tts_command, new.tts_command));
+        }
+        changes
+    }
+
+    pub fn notification_timeout(&self) -> Option<notify_rust::Timeout> {
+        let secs = self.notification_timeout?;
+        Some(notify_rust::Timeout::Milliseconds(secs.saturating_mul(1000)))
+    }
+}
+
+pub fn config_path() -> Option<PathBuf> {
+    Some(dirs::config_dir()?.join("sand").join(CONFIG_FILENAME))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn partial_config_uses_defaults() {
+        let config: Config = serde_json::from_str(r#"{"volume": 0.5}"#).unwrap();
+        assert_eq!(config, Config { volume: 0.5, ..Config::default() });
+    }
+
+    #[test]
+    fn changes_lists_differences() {
+        let old = Config::default();
+        let new = Config { urgency: Urgency::Low, ..Config::default() };
+        assert_eq!(old.changes(&new), vec!["urgency: Critical -> Low".to_string()]);
+        assert!(old.changes(&old).is_empty());
+    }
+}
@@ -0,0 +1,1276 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::sync::RwLock;
+use std::time::Duration;
+use std::time::Instant;
+use std::time::SystemTime;
+
+use rodio::OutputStreamHandle;
+use tokio::sync::broadcast;
+use tokio::sync::Notify;
+use tokio::task::JoinHandle;
+
+use crate::sand::audio::{self, ElapsedSoundPlayer};
+use crate::sand::clock::{self, MonotonicJump};
+use crate::sand::config::Config;
+use crate::sand::duration::DurationExt;
+use crate::sand::event_log::{self, EventLogEntry, LifecycleEvent};
+use crate::sand::message::AddTimerResponse;
+use crate::sand::message::AdjustTimerResponse;
+use crate::sand::message::AgainResponse;
+use crate::sand::message::CancelTimerResponse;
+use crate::sand::message::DebugDump;
+use crate::sand::message::DebugDumpResponse;
+use crate::sand::message::ExportResponse;
+use crate::sand::message::ExportedTimer;
+use crate::sand::message::FormatsResponse;
+use crate::sand::message::FsckResponse;
+use crate::sand::message::GetConfigResponse;
+use crate::sand::message::ListArgs;
+use crate::sand::message::ListResponse;
+use crate::sand::message::NopResponse;
+use crate::sand::message::PauseTimerResponse;
+use crate::sand::message::ResumeTimerResponse;
+use crate::sand::message::SoundInfoResponse;
+use crate::sand::message::SubscribeResponse;
+use crate::sand::message::SnoozeResponse;
+use crate::sand::message::StatsResponse;
+use crate::sand::message::TimerFilter;
+use crate::sand::message::ToggleTimerResponse;
+use crate::sand::message::WaitResponse;
+use crate::sand::message::{Command, Response};
+use crate::sand::notifier::{self, ElapsedTimer, NotificationAction};
+use crate::sand::message;
+use crate::sand::state;
+use crate::sand::timer;
+use crate::sand::timer::RunState;
+use crate::sand::timer::SortKey;
+use crate::sand::timer::StartSpec;
+use crate::sand::timer::Timer;
+use crate::sand::timer::TimerId;
+use crate::sand::timer::TimerInfoForClient;
+use crate::sand::timer::TimerState;
+use crate::sand::timers::{Elapsed, Timers};
+use crate::sand::VERSION;
+
+/// The timer engine: owns the set of timers, and the means of notifying the
+/// user when one elapses. Cheap to clone; clones share the same timers.
+#[derive(Clone)]
+pub struct DaemonCtx {
+    timers: Arc<Timers>,
+    player: Option<ElapsedSoundPlayer>,
+    /// Why there's no player, if there isn't one.
+    no_sound_reason: Option<String>,
+    max_timers: usize,
+    max_command_bytes: usize,
+    config: Arc<RwLock<Config>>,
+    last_started: Arc<Mutex<Option<StartSpec>>>,
+    last_started_path: Option<PathBuf>,
+    /// Held while saving the last timer started, so saves don't overlap.
+    saving_last_started: Arc<Mutex<()>>,
+    /// The specs of timers which have elapsed, by id, for snoozing. An
+    /// entry is dropped once its id is used by another timer.
+    elapsed: Arc<Mutex<HashMap<TimerId, StartSpec>>>,
+    events: broadcast::Sender<TimerEvent>,
+    lifecycle: broadcast::Sender<EventLogEntry>,
+    socket_path: Option<PathBuf>,
+    config_path: Option<PathBuf>,
+    notification_actions: bool,
+}
+
+/// How long the "Snooze" notification action sets a new timer for.
+const SNOOZE_DURATION: Duration = Duration::from_secs(5 * 60);
+
+/// Something that happened to a timer, as seen by [`DaemonCtx::subscribe`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimerEvent {
+    Elapsed(TimerId),
+    Cancelled(TimerId),
+}
+
+const EVENT_CHANNEL_CAPACITY: usize = 64;
+
+/// The number of timers a [`DaemonCtx`] allows by default. Paused timers
+/// count towards the limit.
+pub const DEFAULT_MAX_TIMERS: usize = 256;
+
+/// The longest command a client may send by default, in bytes, not counting
+/// the newline.
+pub const DEFAULT_MAX_COMMAND_BYTES: usize = 64 * 1024;
+
+/// How often a timer waiting to resume at a set time checks the wall clock,
+/// so it isn't held up by time spent suspended. See
+/// [`clock::sleep_until_wall_time`].
+const RESUME_CHECK_INTERVAL: Duration = Duration::from_secs(15);
+
+impl DaemonCtx {
+    pub fn new(stream_handle: Option<OutputStreamHandle>) -> Self {
+        let (player, no_sound_reason) = match stream_handle.map(|handle| ElapsedSoundPlayer::new(handle, None)) {
+            Some(Ok(player)) => (Some(player), None),
+            Some(Err(e)) => {
+                eprintln!("No timer sound could be loaded, sounds are disabled: {e}");
+                (None, Some(format!("no timer sound could be loaded: {e}")))
+            }
+            None => (None, Some("sound is disabled, or there's no audio output".into())),
+        };
+        Self {
+            timers: Default::default(),
+            player,
+            no_sound_reason,
+            max_timers: DEFAULT_MAX_TIMERS,
+            max_command_bytes: DEFAULT_MAX_COMMAND_BYTES,
+            config: Default::default(),
+            last_started: Default::default(),
+            last_started_path: None,
+            saving_last_started: Default::default(),
+            elapsed: Default::default(),
+            events: broadcast::channel(EVENT_CHANNEL_CAPACITY).0,
+            lifecycle: broadcast::channel(EVENT_CHANNEL_CAPACITY).0,
+            socket_path: None,
+            config_path: None,
+            notification_actions: false,
+        }
+    }
+
+    /// Use `config`, loading the timer sound it names if it has one.
+    pub fn with_config(self, config: Config) -> Self {
+        if let (Some(player), Some(path)) = (&self.player, &config.sound_path) {
+            player.reload(Some(path));
+        }
+        Self { config: Arc::new(RwLock::new(config)), ..self }
+    }
+
+    /// Whether the notification server supports actions. If it does, elapsed
+    /// notifications get buttons to restart or snooze the timer.
+    pub fn with_notification_actions(self, notification_actions: bool) -> Self {
+        Self { notification_actions, ..self }
+    }
+
+    /// Remember the last timer started in the file at `path`, so `again`
+    /// works across restarts. Whatever is already there is loaded.
+    pub fn with_last_started_path(self, path: PathBuf) -> Self {
+        let last_started = state::load_last_started(&path).unwrap_or_else(|e| {
+            eprintln!("Error loading last started timer from {}: {e}", path.display());
+            None
+        });
+        Self {
+            last_started: Arc::new(Mutex::new(last_started)),
+            last_started_path: Some(path),
+            ..self
+        }
+    }
+
+    /// Record where the config was loaded from, for [`DaemonCtx::debug_dump`].
+    pub fn with_config_path(self, config_path: Option<PathBuf>) -> Self {
+        Self { config_path, ..self }
+    }
+
+    /// Record the socket clients connect on, for [`DaemonCtx::debug_dump`].
+    pub fn with_socket_path(self, socket_path: Option<PathBuf>) -> Self {
+        Self { socket_path, ..self }
+    }
+
+    pub fn debug_dump(&self, now: Instant) -> DebugDump {
+        DebugDump {
+            version: VERSION.to_string(),
+            timers: self.timers.debug_info(now),
+            max_timers: self.max_timers,
+            time_suspended_millis: clock::time_suspended().as_millis() as u64,
+            socket_path: self.socket_path.clone(),
+            sound_path: self.player.as_ref().map(ElapsedSoundPlayer::path),
+            config_path: self.config_path.clone(),
+            config: self.config(),
+        }
+    }
+
+    pub fn stats(&self, now: Instant) -> StatsResponse {
+        self.timers.stats(now)
+    }
+
+    pub fn sound_info(&self) -> SoundInfoResponse {
+        match self.player {
+            Some(ref player) => SoundInfoResponse::Ok {
+                path: player.path(),
+                duration_millis: player.total_duration().map(|duration| duration.as_millis() as u64),
+            },
+            None => SoundInfoResponse::NoSound {
+                reason: self.no_sound_reason.clone().unwrap_or_default(),
+            },
+        }
+    }
+
+    pub fn config(&self) -> Config {
+        self.config.read().expect("another thread panicked while holding this lock.").clone()
+    }
+
+    /// Replace the current config, logging what changed. If anything did,
+    /// the elapsed sound is played so the new settings can be heard.
+    pub fn set_config(&self, new: Config) {
+        let mut config = self.config.write().expect("another thread panicked while holding this lock.");
+        let changes = config.changes(&new);
+        if changes.is_empty() {
+            return;
+        }
+        for change in changes {
+            eprintln!("config changed: {change}");
+        }
+        let sound_changed = config.sound_path != new.sound_path;
+        *config = new;
+        let current = config.clone();
+        drop(config);
+        if sound_changed {
+            self.reload_sound();
+        }
+        self.play_elapsed_sound(&current);
+    }
+
+    /// Load the timer sound again, as after the file has changed.
+    pub fn reload_sound(&self) {
+        if let Some(ref player) = self.player {
+            player.reload(self.config().sound_path.as_deref());
+        }
+    }
+
+    /// Play the start sound, if the config asks for it.
+    fn play_start_sound(&self) {
+        let config = self.config();
+        if !config.start_sound {
+            return;
+        }
+        if let Some(ref player) = self.player {
+            if let Err(e) = player.play_start(config.volume, config.max_playing_sounds) {
+                eprintln!("Error playing timer start sound: {e}");
+            }
+        }
+    }
+
+    fn play_elapsed_sound(&self, config: &Config) {
+        if let Some(ref player) = self.player {
+            eprintln!("playing sound");
+            if let Err(e) = player.play(config.volume, config.max_playing_sounds) {
+                eprintln!("Error playing timer elapsed sound: {e}");
+            }
+        } else {
+            eprintln!("not playing sound");
+        }
+    }
+
+    pub fn with_max_timers(self, max_timers: usize) -> Self {
+        Self { max_timers, ..self }
+    }
+
+    /// Set the longest command clients may send. Longer ones are rejected,
+    /// and the client disconnected.
+    pub fn with_max_command_bytes(self, max_command_bytes: usize) -> Self {
+        Self { max_command_bytes, ..self }
+    }
+
+    pub fn max_command_bytes(&self) -> usize {
+        self.max_command_bytes
+    }
+
+    /// Receive an event each time a timer elapses or is cancelled.
+    pub fn subscribe(&self) -> broadcast::Receiver<TimerEvent> {
+        self.events.subscribe()
+    }
+
+    /// Receive an entry each time a timer is started, paused, resumed,
+    /// cancelled or elapses, as written to the event log.
+    pub fn subscribe_lifecycle(&self) -> broadcast::Receiver<EventLogEntry> {
+        self.lifecycle.subscribe()
+    }
+
+    fn emit(&self, event: TimerEvent) {
+        // An error just means nobody is subscribed.
+        let _ = self.events.send(event);
+    }
+
+    /// Send the event to lifecycle subscribers, and record it in the event
+    /// log if one is configured.
+    fn log_event(&self, event: LifecycleEvent, id: TimerId, name: Option<String>) {
+        let entry = EventLogEntry::new(SystemTime::now(), event, id, name);
+        if let Some(path) = self.config().event_log_path {
+            if let Err(e) = event_log::append(&path, &entry) {
+                eprintln!("Error writing to event log {}: {e}", path.display());
+            }
+        }
+        // An error just means nobody is subscribed.
+        let _ = self.lifecycle.send(entry);
+    }
+
+    fn timer_name(&self, id: TimerId) -> Option<String> {
+        self.timers.spec(id)?.name
+    }
+
+    /// Carry out a client command, returning a future of the response to
+    /// send back.
+    ///
+    /// The command takes effect before this returns, so commands handled in
+    /// sequence are applied in that order even if their responses are
+    /// awaited later. Most responses are ready immediately, but
+    /// [`Command::Wait`] doesn't resolve until its timer is gone.
+    pub fn handle_command(&self, cmd: Command) -> impl Future<Output = Response> + Send + 'static {
+        let ctx = CmdHandlerCtx::new(self.clone());
+        let outcome = match cmd {
+            Command::List => Outcome::Done(ctx.list(ListArgs::default()).into()),
+            Command::ListWith(args) => Outcome::Done(ctx.list(args).into()),
+            Command::Query { filter, sort_by } => Outcome::Done(ctx.query(filter, sort_by).into()),
+            Command::AddTimer(spec) => Outcome::Done(ctx.add_timer(spec).into()),
+            Command::Again { name } => Outcome::Done(ctx.again(name).into()),
+            Command::PauseTimer(id) => Outcome::Done(ctx.pause_timer(id).into()),
+            Command::ResumeTimer(id) => Outcome::Done(ctx.resume_timer(id).into()),
+            Command::ResumeAt { id, at_millis } => Outcome::Done(self.schedule_resume(id, clock::from_unix_millis(at_millis)).into()),
+            Command::ToggleTimer(id) => Outcome::Done(ctx.toggle_timer(id).into()),
+            Command::CancelTimer(id) => Outcome::Done(ctx.cancel_timer(id).into()),
+            Command::ExtendTimer { id, millis } => Outcome::Done(ctx.extend_timer(id, millis).into()),
+            Command::SetTimer { id, millis } => Outcome::Done(ctx.set_timer(id, millis).into()),
+            Command::Snooze { id, millis } => Outcome::Done(self.snooze_timer(id, ctx.now, millis).into()),
+            Command::Wait(id) => Outcome::Waiting(self.wait(id)),
+            Command::GetConfig => Outcome::Done(GetConfigResponse::Ok(Box::new(self.config())).into()),
+            Command::Formats => Outcome::Done(FormatsResponse::Ok {
+                formats: audio::supported_formats().map(str::to_string).collect(),
+            }.into()),
+            Command::SoundInfo => Outcome::Done(self.sound_info().into()),
+            Command::Export => Outcome::Done(ExportResponse::Ok { timers: self.timers.export(ctx.now) }.into()),
+            Command::ImportTimer(timer) => Outcome::Done(self.import_timer(ctx.now, timer).into()),
+            Command::DebugDump => Outcome::Done(DebugDumpResponse::Ok(Box::new(self.debug_dump(ctx.now))).into()),
+            Command::Fsck => Outcome::Done(FsckResponse::Ok { findings: self.timers.fsck(ctx.now) }.into()),
+            Command::Stats => Outcome::Done(self.stats(ctx.now).into()),
+            // A stream of events doesn't fit in a single response. The
+            // daemon handles this itself, using `subscribe_lifecycle`.
+            Command::Subscribe => Outcome::Done(SubscribeResponse::Unsupported.into()),
+            Command::Nop => Outcome::Done(NopResponse::Ok.into()),
+        };
+        async move {
+            match outcome {
+                Outcome::Done(resp) => resp,
+                Outcome::Waiting(wait) => wait.await.into(),
+            }
+        }
+    }
+
+    pub fn get_timerinfo_for_client(&self, now: Instant) -> Vec<TimerInfoForClient> {
+        self.timers.get_timerinfo_for_client(now)
+    }
+
+    /// The timers matching `filter`, as of `now`, in order of id.
+    pub fn query(&self, now: Instant, filter: &TimerFilter) -> Vec<TimerInfoForClient> {
+        self.timers.query(now, |timer| filter.matches(timer))
+    }
+
+    /// The number of running timers, other than `id`.
+    fn running_count_except(&self, id: TimerId) -> usize {
+        self.get_timerinfo_for_client(Instant::now())
+            .iter()
+            .filter(|timer| timer.id() != id && timer.state() == timer::TimerState::Running)
+            .count()
+    }
+
+    /// Whether there are no timers, running or paused.
+    pub fn is_empty(&self) -> bool {
+        self.timers.is_empty()
+    }
+
+    async fn countdown(
+        self,
+        id: TimerId,
+        due: Instant,
+        rx_added: Arc<Notify>,
+        elapsed_while_asleep: bool,
+    ) {
+        tokio::time::sleep_until(due.into()).await;
+        rx_added.notified().await;
+
+        let mut notify_added = Vec::new();
+        let elapsed = self.timers.elapse(
+            id,
+            due,
+            Instant::now(),
+            |spec| spec.repeats_after(SystemTime::now()),
+            |id, due| {
+                let (join_handle, notify) = self.spawn_countdown(id, due, false);
+                notify_added.push(notify);
+                join_handle
+            },
+        );
+        let Some(Elapsed { spec, repeated, started }) = elapsed else {
+            eprintln!("Timer {id} was cancelled or changed as its countdown finished, not going off");
+            return;
+        };
+        eprintln!("Timer {id} completed");
+        for notify in notify_added {
+            notify.notify_one();
+        }
+
+        if !repeated {
+            self.remember_elapsed(id, spec.clone());
+        }
+        let config = self.config();
+        let spec_name = spec.name.clone();
+        self.do_notification(&config, id, spec, elapsed_while_asleep);
+        self.play_elapsed_sound(&config);
+        self.log_event(LifecycleEvent::Elapsed, id, spec_name.clone());
+        if repeated {
+            self.log_event(LifecycleEvent::Started, id, spec_name);
+        }
+        for started_id in started {
+            eprintln!("Timer {started_id} started after timer {id}");
+            self.log_event(LifecycleEvent::Started, started_id, self.timer_name(started_id));
+        }
+        self.emit(TimerEvent::Elapsed(id));
+    }
+
+    /// Tell the user the timer has elapsed, through the configured notifier,
+    /// unless the timer was started without notifications.
+    fn do_notification(&self, config: &Config, id: TimerId, spec: StartSpec, elapsed_while_asleep: bool) {
+        if spec.no_notify {
+            eprintln!("Timer {id} elapsed, not notifying");
+            return;
+        }
+        let mut body = spec.name.clone().unwrap_or_else(|| config.notification_body.clone());
+        if let Some(ref command) = config.tts_command {
+            notifier::speak(command, &body);
+        }
+        if elapsed_while_asleep {
+            body.push_str(" (fired while the system was asleep)");
+        }
+        if config.show_running_count {
+            match self.running_count_except(id) {
+                0 => {}
+                1 => body.push_str("\n1 timer still running"),
+                n => body.push_str(&format!("\n{n} timers still running")),
+            }
+        }
+        let elapsed = ElapsedTimer {
+            id,
+            name: spec.name.clone(),
+            summary: config.notification_summary.clone(),
+            body,
+            urgency: spec.urgency.unwrap_or(config.urgency),
+            icon: spec.icon.clone(),
+            elapsed_while_asleep,
+        };
+        let notifier = config.notifier.notifier(config, self.notification_actions);
+        let ctx = self.clone();
+        notifier.notify(&elapsed, Box::new(move |action| ctx.notification_action(action, spec)));
+    }
+
+    fn notification_action(&self, action: NotificationAction, spec: StartSpec) {
+        let spec = match action {
+            NotificationAction::Restart => spec,
+            NotificationAction::Snooze => StartSpec { duration: SNOOZE_DURATION.as_millis() as u64, ..spec },
+        };
+        eprintln!("notification action: {action:?}");
+        if let AddTimerResponse::LimitReached { max } = self.add_timer(Instant::now(), spec) {
+            eprintln!("Couldn't start timer for {action:?}: limit of {max} reached");
+        }
+    }
+
+    fn spawn_countdown(
+        &self,
+        id: TimerId,
+        due: Instant,
+        elapsed_while_asleep: bool,
+    ) -> (JoinHandle<()>, Arc<Notify>) {
+        // once the countdown has elapsed, it removes its associated timer from
+        // the Timers map. For short durations (eg 0), We need to synchronize to
+        // ensure it doesn't do this til after it's been added
+        let notify_added = Arc::new(Notify::new());
+        let rx_added = notify_added.clone();
+        let join_handle = tokio::spawn(
+            self.clone().countdown(id, due, rx_added, elapsed_while_asleep)
+        );
+        (join_handle, notify_added)
+    }
+
+    /// Called after the system wakes from having been asleep for `slept`.
+    /// Timers which were due during that time elapse immediately, and the
+    /// rest are brought forward by `slept`.
+    pub fn awaken(&self, now: Instant, slept: Duration) {
+        eprintln!("System was asleep for {}, rescheduling timers", slept.format_colon_separated());
+        self.respawn_running(|respawn| self.timers.reschedule_running(now, slept, respawn));
+    }
+
+    /// Called when the monotonic clock, which timers count down by, has
+    /// jumped, so they go off when they would have without the jump. Timers
+    /// which already went off early because of a forward jump can't be
+    /// taken back.
+    pub fn monotonic_jumped(&self, now: Instant, jump: MonotonicJump) {
+        match jump {
+            MonotonicJump::Forward(by) => {
+                eprintln!("Monotonic clock jumped forward by {}, delaying timers", by.format_colon_separated());
+                self.respawn_running(|respawn| self.timers.delay_running(now, by, respawn));
+            }
+            MonotonicJump::Backward(by) => {
+                eprintln!("Monotonic clock jumped back by {}, bringing timers forward", by.format_colon_separated());
+                self.respawn_running(|respawn| self.timers.reschedule_running(now, by, respawn));
+            }
+        }
+    }
+
+    /// Run `reschedule`, giving it a function to start each running timer's
+    /// new countdown.
+    fn respawn_running<R>(&self, reschedule: R)
+    where
+        R: FnOnce(&mut dyn FnMut(TimerId, Instant, bool) -> JoinHandle<()>),
+    {
+        let mut notify_added = Vec::new();
+        reschedule(&mut |id, due, due_while_asleep| {
+            let (join_handle, notify) = self.spawn_countdown(id, due, due_while_asleep);
+            notify_added.push(notify);
+            join_handle
+        });
+        for notify in notify_added {
+            notify.notify_one();
+        }
+    }
+
+    /// Start a new timer. If the spec says to start it after another timer,
+    /// it's added paused, and starts when that one elapses. If it says the
+    /// timer is unique, and a running timer has the same duration and name,
+    /// or a timer with them is already waiting for the same one, that
+    /// timer's id is returned instead.
+    pub fn add_timer(&self, now: Instant, mut spec: StartSpec) -> AddTimerResponse {
+        let unique = std::mem::take(&mut spec.unique);
+        let duration = spec.duration();
+        let Some(due) = spec.due(now) else {
+            eprintln!("Refusing to add timer: invalid duration of {}ms", spec.duration);
+            return AddTimerResponse::InvalidDuration { duration: spec.duration };
+        };
+
+        if let Some(after) = spec.after.take() {
+            let state = RunState::Paused { remaining: duration, after: Some(after), resume_at: None };
+            let timer = Timer { spec: spec.clone(), state, created_at: SystemTime::now() };
+            let resp = self.timers.try_add_after(self.max_timers, after, unique, timer);
+            match resp {
+                AddTimerResponse::Ok { id } => {
+                    self.elapsed_lock().remove(&id);
+                    self.play_start_sound();
+                    self.remember_last_started(spec);
+                }
+                AddTimerResponse::AlreadyExists { id } => eprintln!("Not adding timer: timer {id} is the same"),
+                _ => {}
+            }
+            return resp;
+        }
+
+        let id = match self.insert_running(&spec, due, SystemTime::now(), unique) {
+            AddTimerResponse::Ok { id } => id,
+            AddTimerResponse::AlreadyExists { id } => {
+                eprintln!("Not adding timer: timer {id} is the same");
+                return AddTimerResponse::AlreadyExists { id };
+            }
+            resp => {
+                eprintln!("Refusing to add timer: limit of {} reached", self.max_timers);
+                return resp;
+            }
+        };
+        self.elapsed_lock().remove(&id);
+        self.log_event(LifecycleEvent::Started, id, spec.name.clone());
+        self.play_start_sound();
+        self.remember_last_started(spec);
+        AddTimerResponse::ok(id)
+    }
+
+    /// Add a timer which is due at `due`, and start counting down to it,
+    /// unless there are already as many timers as allowed. If it's `unique`,
+    /// it isn't added if there's a running timer with the same duration and
+    /// name.
+    fn insert_running(&self, spec: &StartSpec, due: Instant, created_at: SystemTime, unique: bool) -> AddTimerResponse {
+        let mut notify_added = None;
+        let make_timer = |id| {
+            let (join_handle, notify) = self.spawn_countdown(id, due, false);
+            notify_added = Some(notify);
+            let state = RunState::Running { due, countdown: join_handle };
+            Timer { spec: spec.clone(), state, created_at }
+        };
+        let resp = if unique {
+            self.timers.try_add_unique(self.max_timers, spec, make_timer)
+        } else {
+            match self.timers.try_add_new(self.max_timers, make_timer) {
+                Some(id) => AddTimerResponse::ok(id),
+                None => AddTimerResponse::LimitReached { max: self.max_timers },
+            }
+        };
+        if let Some(notify_added) = notify_added {
+            notify_added.notify_one();
+        }
+        resp
+    }
+
+    /// Add a timer exported by [`Timers::export`], as it was then. Unlike
+    /// starting a new timer, this doesn't play the start sound, or change
+    /// what `again` starts.
+    pub fn import_timer(&self, now: Instant, timer: ExportedTimer) -> AddTimerResponse {
+        let ExportedTimer { spec, state, remaining_millis, created_at_millis } = timer;
+        let spec = StartSpec { after: None, ..spec };
+        let remaining = Duration::from_millis(remaining_millis);
+        let Some(due) = now.checked_add(remaining) else {
+            eprintln!("Refusing to import timer: invalid time remaining of {remaining_millis}ms");
+            return AddTimerResponse::InvalidDuration { duration: remaining_millis };
+        };
+        let created_at = clock::from_unix_millis(created_at_millis);
+        let resp = match state {
+            TimerState::Running => self.insert_running(&spec, due, created_at, false),
+            TimerState::Paused => {
+                let id = self.timers.try_add_new(self.max_timers, |_| {
+                    let state = RunState::Paused { remaining, after: None, resume_at: None };
+                    Timer { spec: spec.clone(), state, created_at }
+                });
+                id.map_or(AddTimerResponse::LimitReached { max: self.max_timers }, AddTimerResponse::ok)
+            }
+        };
+        let AddTimerResponse::Ok { id } = resp else {
+            eprintln!("Refusing to import timer: limit of {} reached", self.max_timers);
+            return resp;
+        };
+        self.elapsed_lock().remove(&id);
+        self.log_event(LifecycleEvent::Started, id, spec.name);
+        AddTimerResponse::ok(id)
+    }
+
+    fn elapsed_lock(&self) -> std::sync::MutexGuard<'_, HashMap<TimerId, StartSpec>> {
+        self.elapsed.lock().expect("another thread panicked while holding this lock.")
+    }
+
+    /// Keep the spec of the timer `id` which just elapsed, so it can be
+    /// snoozed. The timer was removed before this is called, so another one
+    /// may have been added under its id in the meantime. If so, the spec
+    /// isn't kept, or snoozing the new timer's id once it's gone would bring
+    /// back the old one. New timers forget the spec of their id after
+    /// they're added, so holding the lock while checking means one or the
+    /// other always wins.
+    fn remember_elapsed(&self, id: TimerId, spec: StartSpec) {
+        let mut elapsed = self.elapsed_lock();
+        if self.timers.spec(id).is_some() {
+            eprintln!("Timer {id} was reused as it elapsed, it can't be snoozed");
+            return;
+        }
+        elapsed.insert(id, spec);
+    }
+
+    /// Start the elapsed timer `id` again for `millis`, under the same id,
+    /// with the rest of its spec as it was.
+    pub fn snooze_timer(&self, id: TimerId, now: Instant, millis: u64) -> SnoozeResponse {
+        let mut elapsed = self.elapsed_lock();
+        let Some(spec) = elapsed.get(&id) else {
+            return match self.timers.spec(id) {
+                Some(_) => SnoozeResponse::NotElapsed,
+                None => SnoozeResponse::TimerNotFound,
+            };
+        };
+        let spec = StartSpec { duration: millis, ..spec.clone() };
+        let Some(due) = spec.due(now) else {
+            return SnoozeResponse::InvalidDuration { duration: millis };
+        };
+        let mut notify_added = None;
+        let resp = self.timers.try_add_at(self.max_timers, id, || {
+            let (join_handle, notify) = self.spawn_countdown(id, due, false);
+            notify_added = Some(notify);
+            let state = RunState::Running { due, countdown: join_handle };
+            Timer { spec: spec.clone(), state, created_at: SystemTime::now() }
+        });
+        if resp != SnoozeResponse::Ok {
+            return resp;
+        }
+        elapsed.remove(&id);
+        drop(elapsed);
+        if let Some(notify_added) = notify_added {
+            notify_added.notify_one();
+        }
+        self.log_event(LifecycleEvent::Started, id, spec.name);
+        resp
+    }
+
+    /// Record `spec` for `again`, and save it in the background, if there's
+    /// somewhere to save it.
+    fn remember_last_started(&self, spec: StartSpec) {
+        *self.last_started.lock().expect("another thread panicked while holding this lock.") = Some(spec.clone());
+        let Some(path) = self.last_started_path.clone() else {
+            return;
+        };
+        let ctx = self.clone();
+        tokio::task::spawn_blocking(move || {
+            let _saving = ctx.saving_last_started.lock().expect("another thread panicked while holding this lock.");
+            // If another timer has been started since, its save will be the
+            // one that counts, whichever order they run in.
+            let latest = ctx.last_started.lock().expect("another thread panicked while holding this lock.").clone();
+            if latest.as_ref() != Some(&spec) {
+                return;
+            }
+            if let Err(e) = state::save_last_started(&path, &spec) {
+                eprintln!("Error saving last started timer to {}: {e}", path.display());
+            }
+        });
+    }
+
+    /// Start a new timer with the same spec as the last one started, but
+    /// named `name` instead, if given.
+    pub fn again(&self, now: Instant, name: Option<String>) -> AgainResponse {
+        let last_started = self.last_started.lock()
+            .expect("another thread panicked while holding this lock.")
+            .clone();
+        let Some(mut spec) = last_started else {
+            return AgainResponse::NonePrevious;
+        };
+        if name.is_some() {
+            spec.name = name;
+        }
+        let StartSpec { duration, ref name, .. } = spec;
+        let name = name.clone();
+        match self.add_timer(now, spec) {
+            AddTimerResponse::Ok { id } => AgainResponse::Ok { id, duration, name },
+            AddTimerResponse::LimitReached { max } => AgainResponse::LimitReached { max },
+            AddTimerResponse::InvalidDuration { duration } => AgainResponse::InvalidStoredDuration { duration },
+            AddTimerResponse::AfterNotFound { .. } | AddTimerResponse::AlreadyExists { .. } => {
+                unreachable!("BUG: the last timer started was remembered with what it started after, or as unique")
+            }
+        }
+    }
+
+    pub fn pause_timer(&self, id: TimerId, now: Instant) -> PauseTimerResponse {
+        let resp = self.timers.pause(id, now);
+        if resp == PauseTimerResponse::Ok {
+            self.log_event(LifecycleEvent::Paused, id, self.timer_name(id));
+        }
+        resp
+    }
+    
+    pub fn resume_timer(&self, id: TimerId, now: Instant) -> message::ResumeTimerResponse {
+        let mut notify_added = None;
+        let resp = self.timers.resume(id, now, |due| {
+            let (join_handle, notify) = self.spawn_countdown(id, due, false);
+            notify_added = Some(notify);
+            join_handle
+        });
+        if let Some(notify_added) = notify_added {
+            notify_added.notify_one();
+        }
+        if resp == message::ResumeTimerResponse::Ok {
+            self.log_event(LifecycleEvent::Resumed, id, self.timer_name(id));
+        }
+        resp
+    }
+
+    /// Have the paused timer `id` resume by itself at `at`. If it's resumed
+    /// or paused again in the meantime, that's forgotten.
+    pub fn schedule_resume(&self, id: TimerId, at: SystemTime) -> ResumeTimerResponse {
+        let resp = self.timers.schedule_resume(id, at);
+        if resp != ResumeTimerResponse::Ok {
+            return resp;
+        }
+        eprintln!("Timer {id} will resume at {}", clock::format_local(at));
+        let ctx = self.clone();
+        tokio::spawn(async move {
+            clock::sleep_until_wall_time(at, RESUME_CHECK_INTERVAL, SystemTime::now).await;
+            if ctx.timers.resume_at(id) == Some(at) {
+                ctx.resume_timer(id, Instant::now());
+            }
+        });
+        resp
+    }
+
+    pub fn toggle_timer(&self, id: TimerId, now: Instant) -> ToggleTimerResponse {
+        let mut notify_added = None;
+        let resp = self.timers.toggle(id, now, |due| {
+            let (join_handle, notify) = self.spawn_countdown(id, due, false);
+            notify_added = Some(notify);
+            join_handle
+        });
+        if let Some(notify_added) = notify_added {
+            notify_added.notify_one();
+        }
+        match resp {
+            ToggleTimerResponse::Paused => self.log_event(LifecycleEvent::Paused, id, self.timer_name(id)),
+            ToggleTimerResponse::Resumed => self.log_event(LifecycleEvent::Resumed, id, self.timer_name(id)),
+            ToggleTimerResponse::TimerNotFound => {}
+        }
+        resp
+    }
+    
+    /// Add `millis` to the time remaining on a timer, or subtract it if
+    /// negative, stopping at zero.
+    pub fn extend_timer(&self, id: TimerId, millis: i64, now: Instant) -> AdjustTimerResponse {
+        let amount = Duration::from_millis(millis.unsigned_abs());
+        self.adjust_timer(id, now, |remaining| {
+            if millis >= 0 {
+                remaining.saturating_add(amount)
+            } else {
+                remaining.saturating_sub(amount)
+            }
+        })
+    }
+
+    /// Like starting a timer, the new duration can't be zero, or longer
+    /// than [`timer::MAX_DURATION`].
+    pub fn set_timer(&self, id: TimerId, millis: u64, now: Instant) -> AdjustTimerResponse {
+        if millis == 0 {
+            return AdjustTimerResponse::InvalidDuration { duration: millis };
+        }
+        self.adjust_timer(id, now, |_| Duration::from_millis(millis))
+    }
+
+    fn adjust_timer<A>(&self, id: TimerId, now: Instant, adjust: A) -> AdjustTimerResponse
+    where
+        A: FnOnce(Duration) -> Duration,
+    {
+        let mut notify_added = None;
+        let resp = self.timers.adjust(id, now, adjust, |due| {
+            let (join_handle, notify) = self.spawn_countdown(id, due, false);
+            notify_added = Some(notify);
+            join_handle
+        });
+        if let Some(notify_added) = notify_added {
+            notify_added.notify_one();
+        }
+        resp
+    }
+
+    pub fn cancel_timer(&self, id: TimerId) -> message::CancelTimerResponse {
+        let name = self.timer_name(id);
+        let resp = self.timers.cancel(id);
+        if resp == message::CancelTimerResponse::Ok {
+            self.log_event(LifecycleEvent::Cancelled, id, name);
+            self.emit(TimerEvent::Cancelled(id));
+        }
+        resp
+    }
+
+    /// Wait for the timer to either elapse or be cancelled.
+    ///
+    /// We start listening for the timer's events immediately rather than when
+    /// the future is first polled, so an event following this call can't be
+    /// missed.
+    pub fn wait(&self, id: TimerId) -> impl Future<Output = WaitResponse> + Send + 'static {
+        // Subscribe before checking the timer exists, so we can't miss its
+        // event in between.
+        let mut events = self.subscribe();
+        let exists = self.timers.spec(id).is_some();
+        let ctx = self.clone();
+        async move {
+            if !exists {
+                return WaitResponse::TimerNotFound;
+            }
+            loop {
+                match events.recv().await {
+                    Ok(TimerEvent::Elapsed(event_id)) if event_id == id => return WaitResponse::Elapsed,
+                    Ok(TimerEvent::Cancelled(event_id)) if event_id == id => return WaitResponse::Cancelled,
+                    Ok(_) => continue,
+                    Err(broadcast::error::RecvError::Lagged(_)) => {
+                        // We may have missed the event we're waiting for.
+                        if ctx.timers.spec(id).is_none() {
+                            return WaitResponse::TimerNotFound;
+                        }
+                    }
+                    Err(broadcast::error::RecvError::Closed) => {
+                        unreachable!("BUG: DaemonCtx holds the event sender")
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// What handling a command produced: either the response, or a future which
+/// will produce it.
+enum Outcome<W> {
+    Done(Response),
+    Waiting(W),
+}
+
+struct CmdHandlerCtx {
+    now: Instant,
+    state: DaemonCtx,
+}
+
+impl CmdHandlerCtx {
+    fn new(state: DaemonCtx) -> Self {
+        let now = Instant::now();
+        Self { now, state }
+    }
+
+    fn list(&self, args: ListArgs) -> ListResponse {
+        self.query(TimerFilter { state: args.state, ..Default::default() }, args.sort_by)
+    }
+
+    fn query(&self, filter: TimerFilter, sort_by: SortKey) -> ListResponse {
+        let mut timers = self.state.query(self.now, &filter);
+        timer::sort_timers(&mut timers, sort_by);
+        ListResponse::ok(timers)
+    }
+
+
+    fn add_timer(&self, spec: StartSpec) -> AddTimerResponse {
+        self.state.add_timer(self.now, spec)
+    }
+
+    fn again(&self, name: Option<String>) -> AgainResponse {
+        self.state.again(self.now, name)
+    }
+    
+    fn pause_timer(&self, id: TimerId) -> PauseTimerResponse {
+        self.state.pause_timer(id, self.now)
+    }
+    
+    fn resume_timer(&self, id: TimerId) -> ResumeTimerResponse {
+        self.state.resume_timer(id, self.now)
+    }
+    
+    fn toggle_timer(&self, id: TimerId) -> ToggleTimerResponse {
+        self.state.toggle_timer(id, self.now)
+    }
+    
+    fn extend_timer(&self, id: TimerId, millis: i64) -> AdjustTimerResponse {
+        self.state.extend_timer(id, millis, self.now)
+    }
+
+    fn set_timer(&self, id: TimerId, millis: u64) -> AdjustTimerResponse {
+        self.state.set_timer(id, millis, self.now)
+    }
+
+    fn cancel_timer(&self, id: TimerId) -> CancelTimerResponse {
+        self.state.cancel_timer(id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sand::timer::MAX_DURATION;
+
+    #[tokio::test]
+    async fn snooze_restarts_elapsed_timers() {
+        let ctx = DaemonCtx::new(None);
+        let minute = Duration::from_secs(60).as_millis() as u64;
+        assert_eq!(ctx.snooze_timer(TimerId(1), Instant::now(), minute), SnoozeResponse::TimerNotFound);
+
+        let spec = StartSpec { name: Some("tea".into()), ..StartSpec::new(Duration::from_millis(1)) };
+        let AddTimerResponse::Ok { id } = ctx.add_timer(Instant::now(), spec) else {
+            panic!("failed to add timer");
+        };
+        assert_eq!(ctx.wait(id).await, WaitResponse::Elapsed);
+        assert_eq!(ctx.snooze_timer(id, Instant::now(), minute), SnoozeResponse::Ok);
+        assert_eq!(ctx.timer_name(id).as_deref(), Some("tea"));
+        assert_eq!(ctx.snooze_timer(id, Instant::now(), minute), SnoozeResponse::NotElapsed);
+
+        // Once the id goes to another timer, the old one can't be snoozed.
+        ctx.cancel_timer(id);
+        let AddTimerResponse::Ok { id } = ctx.add_timer(Instant::now(), StartSpec::new(Duration::from_millis(1))) else {
+            panic!("failed to add timer");
+        };
+        assert_eq!(ctx.wait(id).await, WaitResponse::Elapsed);
+        assert_eq!(ctx.snooze_timer(id, Instant::now(), minute), SnoozeResponse::Ok);
+        assert_eq!(ctx.timer_name(id), None);
+    }
+
+    #[tokio::test]
+    async fn reused_id_is_not_snoozed_as_the_old_timer() {
+        let ctx = DaemonCtx::new(None);
+        let minute = Duration::from_secs(60).as_millis() as u64;
+        let old = StartSpec { name: Some("old".into()), ..StartSpec::new(Duration::ZERO) };
+        let AddTimerResponse::Ok { id } = ctx.add_timer(Instant::now(), StartSpec::new(Duration::from_secs(60))) else {
+            panic!("failed to add timer");
+        };
+        // As if an old timer with this id elapsed just before the new one
+        // took the id, but its countdown only got round to remembering it now.
+        ctx.remember_elapsed(id, old);
+        ctx.cancel_timer(id);
+        assert_eq!(ctx.snooze_timer(id, Instant::now(), minute), SnoozeResponse::TimerNotFound);
+    }
+
+    /// Every command, through the same dispatch as commands from clients.
+    #[tokio::test]
+    async fn commands_get_their_responses() {
+        let ctx = DaemonCtx::new(None);
+        let minute = 60_000;
+        let (one, two, missing) = (TimerId(1), TimerId(2), TimerId(9));
+        let spec = StartSpec { name: Some("tea".into()), ..StartSpec::new(Duration::from_secs(60)) };
+
+        assert_eq!(ctx.handle_command(Command::AddTimer(spec)).await, AddTimerResponse::ok(one).into());
+        assert_eq!(
+            ctx.handle_command(Command::Again { name: None }).await,
+            AgainResponse::Ok { id: two, duration: minute, name: Some("tea".into()) }.into(),
+        );
+        let Response::List(ListResponse::Ok { timers }) = ctx.handle_command(Command::List).await else {
+            panic!("expected a list");
+        };
+        assert_eq!(timers.len(), 2);
+
+        assert_eq!(ctx.handle_command(Command::PauseTimer(one)).await, PauseTimerResponse::Ok.into());
+        assert_eq!(ctx.handle_command(Command::PauseTimer(one)).await, PauseTimerResponse::AlreadyPaused.into());
+        assert_eq!(ctx.handle_command(Command::PauseTimer(missing)).await, PauseTimerResponse::TimerNotFound.into());
+        let paused = ListArgs { state: Some(TimerState::Paused), ..Default::default() };
+        let Response::List(ListResponse::Ok { timers }) = ctx.handle_command(Command::ListWith(paused)).await else {
+            panic!("expected a list");
+        };
+        assert_eq!(timers.iter().map(TimerInfoForClient::id).collect::<Vec<_>>(), [one]);
+        let filter = TimerFilter { name_contains: Some("TEA".into()), ..Default::default() };
+        let Response::List(ListResponse::Ok { timers }) = ctx.handle_command(Command::Query { filter, sort_by: SortKey::Id }).await else {
+            panic!("expected a list");
+        };
+        assert_eq!(timers.iter().map(TimerInfoForClient::id).collect::<Vec<_>>(), [one, two]);
+        assert_eq!(ctx.handle_command(Command::ResumeTimer(one)).await, ResumeTimerResponse::Ok.into());
+        assert_eq!(ctx.handle_command(Command::ResumeTimer(one)).await, ResumeTimerResponse::AlreadyRunning.into());
+        assert_eq!(ctx.handle_command(Command::ResumeTimer(missing)).await, ResumeTimerResponse::TimerNotFound.into());
+        assert_eq!(ctx.handle_command(Command::ToggleTimer(one)).await, ToggleTimerResponse::Paused.into());
+        assert_eq!(ctx.handle_command(Command::ToggleTimer(one)).await, ToggleTimerResponse::Resumed.into());
+        assert_eq!(ctx.handle_command(Command::ToggleTimer(missing)).await, ToggleTimerResponse::TimerNotFound.into());
+
+        let extended = ctx.handle_command(Command::ExtendTimer { id: one, millis: minute as i64 }).await;
+        assert!(matches!(extended, Response::AdjustTimer(AdjustTimerResponse::Ok { remaining_millis }) if remaining_millis > minute), "{extended:?}");
+        let set = ctx.handle_command(Command::SetTimer { id: two, millis: 2 * minute }).await;
+        assert!(matches!(set, Response::AdjustTimer(AdjustTimerResponse::Ok { remaining_millis }) if remaining_millis > minute), "{set:?}");
+        assert_eq!(ctx.handle_command(Command::SetTimer { id: missing, millis: minute }).await, AdjustTimerResponse::TimerNotFound.into());
+        let too_long = MAX_DURATION.as_millis() as u64 + 1;
+        assert_eq!(ctx.handle_command(Command::SetTimer { id: two, millis: too_long }).await, AdjustTimerResponse::InvalidDuration { duration: too_long }.into());
+        assert_eq!(ctx.handle_command(Command::SetTimer { id: two, millis: 0 }).await, AdjustTimerResponse::InvalidDuration { duration: 0 }.into());
+        let extended = ctx.handle_command(Command::ExtendTimer { id: one, millis: too_long as i64 }).await;
+        assert!(matches!(extended, Response::AdjustTimer(AdjustTimerResponse::InvalidDuration { .. })), "{extended:?}");
+        assert_eq!(ctx.handle_command(Command::Snooze { id: one, millis: minute }).await, SnoozeResponse::NotElapsed.into());
+        assert_eq!(ctx.handle_command(Command::Snooze { id: missing, millis: minute }).await, SnoozeResponse::TimerNotFound.into());
+
+        let stats = ctx.handle_command(Command::Stats).await;
+        assert!(matches!(stats, Response::Stats(StatsResponse::Ok { running: 2, paused: 0, .. })), "{stats:?}");
+        assert_eq!(ctx.handle_command(Command::Fsck).await, FsckResponse::Ok { findings: vec![] }.into());
+        assert_eq!(ctx.handle_command(Command::GetConfig).await, GetConfigResponse::Ok(Box::default()).into());
+        let formats = ctx.handle_command(Command::Formats).await;
+        assert!(matches!(formats, Response::Formats(FormatsResponse::Ok { ref formats }) if !formats.is_empty()), "{formats:?}");
+        assert_eq!(ctx.handle_command(Command::SoundInfo).await, SoundInfoResponse::NoSound { reason: "sound is disabled, or there's no audio output".into() }.into());
+        let dump = ctx.handle_command(Command::DebugDump).await;
+        assert!(matches!(dump, Response::DebugDump(DebugDumpResponse::Ok(ref dump)) if dump.timers.len() == 2), "{dump:?}");
+        assert_eq!(ctx.handle_command(Command::Subscribe).await, SubscribeResponse::Unsupported.into());
+        assert_eq!(ctx.handle_command(Command::Nop).await, NopResponse::Ok.into());
+
+        assert_eq!(ctx.handle_command(Command::CancelTimer(one)).await, CancelTimerResponse::Ok.into());
+        assert_eq!(ctx.handle_command(Command::CancelTimer(one)).await, CancelTimerResponse::TimerNotFound.into());
+        assert_eq!(ctx.handle_command(Command::Wait(one)).await, WaitResponse::TimerNotFound.into());
+        assert_eq!(ctx.handle_command(Command::CancelTimer(two)).await, CancelTimerResponse::Ok.into());
+    }
+
+    #[tokio::test]
+    async fn paused_timer_resumes_when_scheduled() {
+        let ctx = DaemonCtx::new(None);
+        let AddTimerResponse::Ok { id } = ctx.add_timer(Instant::now(), StartSpec::new(Duration::from_secs(60))) else {
+            panic!("failed to add timer");
+        };
+        let soon = || SystemTime::now() + Duration::from_millis(50);
+        assert_eq!(ctx.schedule_resume(id, soon()), ResumeTimerResponse::AlreadyRunning);
+        assert_eq!(ctx.schedule_resume(TimerId(9), soon()), ResumeTimerResponse::TimerNotFound);
+
+        ctx.pause_timer(id, Instant::now());
+        let at = soon();
+        assert_eq!(ctx.schedule_resume(id, at), ResumeTimerResponse::Ok);
+        assert_eq!(ctx.timers.resume_at(id), Some(at));
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        assert_eq!(ctx.pause_timer(id, Instant::now()), PauseTimerResponse::Ok);
+
+        // Resuming and pausing by hand in the meantime drops the schedule.
+        ctx.schedule_resume(id, soon());
+        ctx.resume_timer(id, Instant::now());
+        ctx.pause_timer(id, Instant::now());
+        assert_eq!(ctx.timers.resume_at(id), None);
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        assert_eq!(ctx.pause_timer(id, Instant::now()), PauseTimerResponse::AlreadyPaused);
+        ctx.cancel_timer(id);
+    }
+
+    #[tokio::test]
+    async fn unique_timer_reuses_running_duplicate() {
+        let ctx = DaemonCtx::new(None);
+        let now = Instant::now();
+        let tea = StartSpec { name: Some("tea".into()), ..StartSpec::new(Duration::from_secs(60)) };
+        let unique = StartSpec { unique: true, ..tea.clone() };
+        let AddTimerResponse::Ok { id } = ctx.add_timer(now, unique.clone()) else {
+            panic!("failed to add timer");
+        };
+        assert_eq!(ctx.timers.spec(id), Some(tea.clone()));
+        assert_eq!(ctx.add_timer(now, unique.clone()), AddTimerResponse::AlreadyExists { id });
+        assert_eq!(ctx.add_timer(now, tea), AddTimerResponse::ok(TimerId(2)));
+        let coffee = StartSpec { name: Some("coffee".into()), ..unique.clone() };
+        assert_eq!(ctx.add_timer(now, coffee), AddTimerResponse::ok(TimerId(3)));
+
+        // Paused timers don't count.
+        ctx.pause_timer(id, now);
+        ctx.pause_timer(TimerId(2), now);
+        assert_eq!(ctx.add_timer(now, unique), AddTimerResponse::ok(TimerId(4)));
+        for id in 1..=4 {
+            ctx.cancel_timer(TimerId(id));
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn concurrent_unique_starts_add_one_timer() {
+        let ctx = DaemonCtx::new(None);
+        let unique = StartSpec { unique: true, ..StartSpec::new(Duration::from_secs(60)) };
+        let barrier = Arc::new(tokio::sync::Barrier::new(8));
+        let starts: Vec<_> = (0..8).map(|_| {
+            let (ctx, unique, barrier) = (ctx.clone(), unique.clone(), barrier.clone());
+            tokio::spawn(async move {
+                barrier.wait().await;
+                ctx.handle_command(Command::AddTimer(unique)).await
+            })
+        }).collect();
+        let mut added = 0;
+        for start in starts {
+            match start.await.unwrap() {
+                Response::AddTimer(AddTimerResponse::Ok { .. }) => added += 1,
+                Response::AddTimer(AddTimerResponse::AlreadyExists { id }) => assert_eq!(id, TimerId(1)),
+                resp => panic!("unexpected response {resp:?}"),
+            }
+        }
+        assert_eq!(added, 1);
+        assert_eq!(ctx.timers.len(), 1);
+        ctx.cancel_timer(TimerId(1));
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn concurrent_unique_starts_after_add_one_timer() {
+        let ctx = DaemonCtx::new(None);
+        ctx.add_timer(Instant::now(), StartSpec::new(Duration::from_secs(600)));
+        let unique = StartSpec { unique: true, after: Some(TimerId(1)), ..StartSpec::new(Duration::from_secs(60)) };
+        let barrier = Arc::new(tokio::sync::Barrier::new(8));
+        let starts: Vec<_> = (0..8).map(|_| {
+            let (ctx, unique, barrier) = (ctx.clone(), unique.clone(), barrier.clone());
+            tokio::spawn(async move {
+                barrier.wait().await;
+                ctx.handle_command(Command::AddTimer(unique)).await
+            })
+        }).collect();
+        let mut added = 0;
+        for start in starts {
+            match start.await.unwrap() {
+                Response::AddTimer(AddTimerResponse::Ok { .. }) => added += 1,
+                Response::AddTimer(AddTimerResponse::AlreadyExists { id }) => assert_eq!(id, TimerId(2)),
+                resp => panic!("unexpected response {resp:?}"),
+            }
+        }
+        assert_eq!(added, 1);
+        assert_eq!(ctx.timers.len(), 2);
+        ctx.cancel_timer(TimerId(2));
+        ctx.cancel_timer(TimerId(1));
+    }
+
+    #[tokio::test]
+    async fn exported_timers_import_as_they_were() {
+        let ctx = DaemonCtx::new(None);
+        let now = Instant::now();
+        let spec = StartSpec { name: Some("tea".into()), repeat: true, ..StartSpec::new(Duration::from_secs(60)) };
+        ctx.add_timer(now, spec.clone());
+        let AddTimerResponse::Ok { id } = ctx.add_timer(now, StartSpec::new(Duration::from_secs(600))) else {
+            panic!("failed to add timer");
+        };
+        ctx.pause_timer(id, now + Duration::from_secs(100));
+        let exported = ctx.timers.export(now + Duration::from_secs(10));
+        assert_eq!(exported.len(), 2);
+        assert_eq!((&exported[0].spec, exported[0].state, exported[0].remaining_millis), (&spec, TimerState::Running, 50_000));
+        assert_eq!((exported[1].state, exported[1].remaining_millis), (TimerState::Paused, 500_000));
+
+        let other = DaemonCtx::new(None);
+        other.add_timer(now, StartSpec::new(Duration::from_secs(5)));
+        let later = Instant::now();
+        for timer in exported.clone() {
+            assert!(matches!(other.import_timer(later, timer), AddTimerResponse::Ok { .. }));
+        }
+        let reimported = other.timers.export(later);
+        assert_eq!(reimported[1..], exported[..]);
+        for ctx in [ctx, other] {
+            for timer in ctx.get_timerinfo_for_client(Instant::now()) {
+                ctx.cancel_timer(timer.id());
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn wait_reports_how_timer_ended() {
+        let ctx = DaemonCtx::new(None);
+        let now = Instant::now();
+        assert_eq!(ctx.wait(TimerId(1)).await, WaitResponse::TimerNotFound);
+
+        let AddTimerResponse::Ok { id } = ctx.add_timer(now, StartSpec::new(Duration::from_secs(60))) else {
+            panic!("failed to add timer");
+        };
+        let wait = tokio::spawn({
+            let ctx = ctx.clone();
+            async move { ctx.wait(id).await }
+        });
+        tokio::task::yield_now().await;
+        ctx.cancel_timer(id);
+        assert_eq!(wait.await.unwrap(), WaitResponse::Cancelled);
+
+        let AddTimerResponse::Ok { id } = ctx.add_timer(now, StartSpec::new(Duration::from_millis(1))) else {
+            panic!("failed to add timer");
+        };
+        assert_eq!(ctx.wait(id).await, WaitResponse::Elapsed);
+    }
+
+    #[tokio::test]
+    async fn zero_and_overlong_durations_are_rejected() {
+        let ctx = DaemonCtx::new(None);
+        let now = Instant::now();
+        let too_long = MAX_DURATION + Duration::from_millis(1);
+        for duration in [Duration::ZERO, too_long] {
+            let millis = duration.as_millis() as u64;
+            let spec = StartSpec::new(duration);
+            assert_eq!(ctx.add_timer(now, spec.clone()), AddTimerResponse::InvalidDuration { duration: millis });
+            *ctx.last_started.lock().unwrap() = Some(spec);
+            assert_eq!(ctx.again(now, None), AgainResponse::InvalidStoredDuration { duration: millis });
+        }
+        assert!(ctx.timers.is_empty());
+
+        let AddTimerResponse::Ok { id } = ctx.add_timer(now, StartSpec::new(MAX_DURATION)) else {
+            panic!("failed to add timer");
+        };
+        ctx.cancel_timer(id);
+    }
+
+    #[tokio::test]
+    async fn short_timer_elapses_exactly_once() {
+        let ctx = DaemonCtx::new(None);
+        let mut events = ctx.subscribe();
+        let AddTimerResponse::Ok { id } = ctx.add_timer(Instant::now(), StartSpec::new(Duration::from_millis(50))) else {
+            panic!("failed to add timer");
+        };
+        let event = tokio::time::timeout(Duration::from_secs(5), events.recv()).await;
+        assert!(matches!(event, Ok(Ok(TimerEvent::Elapsed(elapsed))) if elapsed == id), "{event:?}");
+        assert!(ctx.timers.is_empty());
+
+        let again = tokio::time::timeout(Duration::from_millis(200), events.recv()).await;
+        assert!(again.is_err(), "unexpected second event: {again:?}");
+    }
+
+    #[tokio::test]
+    async fn repeating_timer_starts_again() {
+        let ctx = DaemonCtx::new(None);
+        let mut events = ctx.subscribe();
+        let spec = StartSpec { repeat: true, ..StartSpec::new(Duration::from_millis(50)) };
+        let AddTimerResponse::Ok { id } = ctx.add_timer(Instant::now(), spec) else {
+            panic!("failed to add timer");
+        };
+        for _ in 0..2 {
+            let event = tokio::time::timeout(Duration::from_secs(5), events.recv()).await;
+            assert!(matches!(event, Ok(Ok(TimerEvent::Elapsed(elapsed))) if elapsed == id), "{event:?}");
+            assert!(!ctx.timers.is_empty());
+        }
+        ctx.cancel_timer(id);
+        assert!(ctx.timers.is_empty());
+    }
+
+    #[tokio::test]
+    async fn lifecycle_subscribers_see_events() {
+        let ctx = DaemonCtx::new(None);
+        let mut lifecycle = ctx.subscribe_lifecycle();
+        let spec = StartSpec { name: Some("tea".into()), ..StartSpec::new(Duration::from_secs(60)) };
+        let AddTimerResponse::Ok { id } = ctx.add_timer(Instant::now(), spec) else {
+            panic!("failed to add timer");
+        };
+        ctx.cancel_timer(id);
+
+        let started = lifecycle.try_recv().unwrap();
+        assert_eq!((started.event, started.id, started.name.as_deref()), (LifecycleEvent::Started, id, Some("tea")));
+        let cancelled = lifecycle.try_recv().unwrap();
+        assert_eq!((cancelled.event, cancelled.id), (LifecycleEvent::Cancelled, id));
+    }
+}
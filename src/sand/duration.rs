@@ -1,20 +1,182 @@
+use std::fmt;
 use std::num::ParseIntError;
 use std::time::Duration;
 use std::str::FromStr;
 
+use serde::{Deserialize, Serialize};
+
 pub trait DurationExt {
     fn format_colon_separated(&self) -> String;
+    fn format_colon_separated_millis(&self) -> String;
+    fn format_compact(&self) -> String;
+    /// Like [`DurationExt::format_compact`], with units named by `labels`.
+    fn format_compact_with(&self, labels: &UnitLabels) -> String;
+    fn format_seconds(&self) -> String;
 }
 
 impl DurationExt for Duration {
     fn format_colon_separated(&self) -> String {
+        ColonSeparated(*self).to_string()
+    }
+
+    /// Like a stopwatch, eg `0:03.450`, with hours only when there are any.
+    fn format_colon_separated_millis(&self) -> String {
         let total_seconds = self.as_secs();
         let hours = total_seconds / 3600;
         let minutes = (total_seconds % 3600) / 60;
         let seconds = total_seconds % 60;
         let millis = self.subsec_millis();
 
-        format!("{:02}:{:02}:{:02}:{:03}", hours, minutes, seconds, millis)
+        if hours > 0 {
+            format!("{}:{:02}:{:02}.{:03}", hours, minutes, seconds, millis)
+        } else {
+            format!("{}:{:02}.{:03}", minutes, seconds, millis)
+        }
+    }
+
+    /// Only the nonzero components, largest first, eg `1h 30s`.
+    fn format_compact(&self) -> String {
+        self.format_compact_with(&UnitLabels::default())
+    }
+
+    fn format_compact_with(&self, labels: &UnitLabels) -> String {
+        let total_seconds = self.as_secs();
+        let components = [
+            (total_seconds / 3600, &labels.hours),
+            ((total_seconds % 3600) / 60, &labels.minutes),
+            (total_seconds % 60, &labels.seconds),
+            (self.subsec_millis() as u64, &labels.milliseconds),
+        ];
+        let compact: Vec<String> = components.iter()
+            .filter(|(count, _)| *count != 0)
+            .map(|(count, label)| labels.component(*count, label))
+            .collect();
+        if compact.is_empty() {
+            labels.component(0, &labels.seconds)
+        } else {
+            compact.join(" ")
+        }
+    }
+
+    /// Whole seconds, rounded down.
+    fn format_seconds(&self) -> String {
+        self.as_secs().to_string()
+    }
+}
+
+/// What to call a unit of time in compact durations, depending on whether
+/// there's one of it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UnitLabel {
+    pub singular: String,
+    pub plural: String,
+}
+
+impl UnitLabel {
+    fn new(singular: &str, plural: &str) -> Self {
+        Self { singular: singular.into(), plural: plural.into() }
+    }
+}
+
+/// The names of the units in [`DurationFormat::Compact`], so they can be
+/// translated, or spelled out. The default is the letters `h`, `m`, `s` and
+/// `ms`, right after the number.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct UnitLabels {
+    pub hours: UnitLabel,
+    pub minutes: UnitLabel,
+    pub seconds: UnitLabel,
+    pub milliseconds: UnitLabel,
+    /// Goes between each number and its unit.
+    pub separator: String,
+}
+
+impl Default for UnitLabels {
+    fn default() -> Self {
+        Self {
+            hours: UnitLabel::new("h", "h"),
+            minutes: UnitLabel::new("m", "m"),
+            seconds: UnitLabel::new("s", "s"),
+            milliseconds: UnitLabel::new("ms", "ms"),
+            separator: String::new(),
+        }
+    }
+}
+
+impl UnitLabels {
+    /// Units as English words, like `1 hour 30 seconds`.
+    pub fn words() -> Self {
+        Self {
+            hours: UnitLabel::new("hour", "hours"),
+            minutes: UnitLabel::new("minute", "minutes"),
+            seconds: UnitLabel::new("second", "seconds"),
+            milliseconds: UnitLabel::new("millisecond", "milliseconds"),
+            separator: " ".into(),
+        }
+    }
+
+    fn component(&self, count: u64, label: &UnitLabel) -> String {
+        let unit = if count == 1 { &label.singular } else { &label.plural };
+        format!("{count}{}{unit}", self.separator)
+    }
+}
+
+/// A duration in the colon separated format `ls` shows by default,
+/// `hh:mm:ss:mmm`, eg `01:02:03:004`, truncated to milliseconds. It parses
+/// back from the same string, as well as the shorter `mm:ss` and `hh:mm:ss`
+/// which can be given to `start`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ColonSeparated(pub Duration);
+
+impl fmt::Display for ColonSeparated {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let total_seconds = self.0.as_secs();
+        let hours = total_seconds / 3600;
+        let minutes = (total_seconds % 3600) / 60;
+        let seconds = total_seconds % 60;
+        let millis = self.0.subsec_millis();
+
+        write!(f, "{:02}:{:02}:{:02}:{:03}", hours, minutes, seconds, millis)
+    }
+}
+
+impl FromStr for ColonSeparated {
+    type Err = ParseDurationComponentError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        parse_colon_separated(s).map(Self)
+    }
+}
+
+/// How to display a duration to the user.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum DurationFormat {
+    /// 01:02:03:004
+    #[default]
+    Colon,
+    /// 1:02:03.004, or 2:03.004 under an hour
+    Stopwatch,
+    /// 1h 2m 3s 4ms
+    Compact,
+    /// 3723
+    Seconds,
+}
+
+impl DurationFormat {
+    pub fn format(self, duration: Duration) -> String {
+        self.format_with(duration, &UnitLabels::default())
+    }
+
+    /// Like [`DurationFormat::format`], with `labels` for the units of
+    /// [`DurationFormat::Compact`]. The other formats don't name units.
+    pub fn format_with(self, duration: Duration, labels: &UnitLabels) -> String {
+        match self {
+            Self::Colon => duration.format_colon_separated(),
+            Self::Stopwatch => duration.format_colon_separated_millis(),
+            Self::Compact => duration.format_compact_with(labels),
+            Self::Seconds => duration.format_seconds(),
+        }
     }
 }
 
@@ -38,27 +200,46 @@ impl TimeUnit {
         }
     }
 
-    fn to_duration(&self, count: u64) -> Duration {
+    /// `count` of this unit, or `None` if that's too long to represent.
+    fn to_duration(&self, count: u64) -> Option<Duration> {
         match self {
-            Self::Hours => Duration::from_secs(count * 3600),
-            Self::Minutes => Duration::from_secs(count * 60),
-            Self::Seconds => Duration::from_secs(count),
-            Self::Milliseconds => Duration::from_millis(count),
+            Self::Hours => count.checked_mul(3600).map(Duration::from_secs),
+            Self::Minutes => count.checked_mul(60).map(Duration::from_secs),
+            Self::Seconds => Some(Duration::from_secs(count)),
+            Self::Milliseconds => Some(Duration::from_millis(count)),
         }
     }
 }
 
 #[derive(Debug, PartialEq)]
 pub enum ParseDurationComponentError {
+    MissingCount,
     BadCount(ParseIntError),
-    BadUnit,
+    BadUnit(String),
+    /// A colon separated duration without two to four fields, or with
+    /// minutes or seconds of 60 or more after the first field, or with a
+    /// fourth field which isn't three digits of milliseconds.
+    BadColonFormat(String),
+    /// Too long to represent as a [`Duration`].
+    TooLong,
 }
 
 impl std::fmt::Display for ParseDurationComponentError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
+            ParseDurationComponentError::MissingCount => write!(f, "expected a number, eg 5m"),
             ParseDurationComponentError::BadCount(e) => write!(f, "failed to parse count: {}", e),
-            ParseDurationComponentError::BadUnit => write!(f, "invalid unit"),
+            ParseDurationComponentError::BadUnit(unit) => write!(
+                f,
+                "invalid unit \"{}\" (expected one of h, m, s, ms, or nothing for seconds)",
+                unit
+            ),
+            ParseDurationComponentError::BadColonFormat(component) => write!(
+                f,
+                "invalid duration \"{}\" (expected mm:ss or hh:mm:ss, eg 1:30:00)",
+                component
+            ),
+            ParseDurationComponentError::TooLong => write!(f, "duration too long"),
         }
     }
 }
@@ -67,24 +248,95 @@ impl std::error::Error for ParseDurationComponentError {
     fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
         match self {
             ParseDurationComponentError::BadCount(e) => Some(e),
-            ParseDurationComponentError::BadUnit => None,
+            ParseDurationComponentError::MissingCount
+            | ParseDurationComponentError::BadUnit(_)
+            | ParseDurationComponentError::BadColonFormat(_)
+            | ParseDurationComponentError::TooLong => None,
         }
     }
 }
 
+/// Parse a single duration component: a count followed by an optional
+/// unit, like `90`, `5m` or `500ms`. A missing unit means seconds. Colon
+/// separated durations like `1:30` (mm:ss) or `1:30:00` (hh:mm:ss) are also
+/// accepted, as is the `01:30:00:000` that `ls` prints; see
+/// [`ColonSeparated`].
 pub fn parse_duration_component(component: &str) -> Result<Duration, ParseDurationComponentError> {
     use ParseDurationComponentError::*;
-    let split_point = component.find(|c: char| !c.is_digit(10)).unwrap_or(component.len());
+    if component.contains(':') {
+        return parse_colon_separated(component);
+    }
+    let split_point = component.find(|c: char| !c.is_ascii_digit()).unwrap_or(component.len());
     let (count_str, unit_str) = component.split_at(split_point);
-    let count = u64::from_str(count_str).map_err(|e| BadCount(e))?;
-    let unit = TimeUnit::parse(unit_str).ok_or(BadUnit)?;
-    Ok(unit.to_duration(count))
+    if count_str.is_empty() {
+        return Err(MissingCount);
+    }
+    let count = u64::from_str(count_str).map_err(BadCount)?;
+    let unit = TimeUnit::parse(unit_str).ok_or_else(|| BadUnit(unit_str.to_string()))?;
+    unit.to_duration(count).ok_or(TooLong)
+}
+
+fn parse_colon_separated(component: &str) -> Result<Duration, ParseDurationComponentError> {
+    use ParseDurationComponentError::*;
+    let fields = component.split(':')
+        .map(|field| {
+            if field.is_empty() {
+                return Err(MissingCount);
+            }
+            u64::from_str(field).map_err(BadCount)
+        })
+        .collect::<Result<Vec<u64>, _>>()?;
+    // Milliseconds are always printed with three digits, and requiring that
+    // keeps `1:2:3:4` from being read as four milliseconds.
+    let millis_field_ok = || component.rsplit(':').next().is_some_and(|millis| millis.len() == 3);
+    let (hours, minutes, seconds, millis) = match fields[..] {
+        [minutes, seconds] => (0, minutes, seconds, 0),
+        [hours, minutes, seconds] if minutes < 60 => (hours, minutes, seconds, 0),
+        [hours, minutes, seconds, millis] if minutes < 60 && millis_field_ok() => (hours, minutes, seconds, millis),
+        _ => return Err(BadColonFormat(component.to_string())),
+    };
+    if seconds >= 60 {
+        return Err(BadColonFormat(component.to_string()));
+    }
+    hours.checked_mul(3600)
+        .and_then(|secs| secs.checked_add(minutes.checked_mul(60)?))
+        .and_then(|secs| secs.checked_add(seconds))
+        .and_then(|secs| Duration::from_secs(secs).checked_add(Duration::from_millis(millis)))
+        .ok_or(TooLong)
+}
+
+/// Add up duration components, failing if the total is too long to
+/// represent.
+pub fn checked_sum<'a>(durations: impl IntoIterator<Item = &'a Duration>) -> Result<Duration, ParseDurationComponentError> {
+    durations.into_iter()
+        .try_fold(Duration::ZERO, |total, duration| total.checked_add(*duration))
+        .ok_or(ParseDurationComponentError::TooLong)
+}
+
+/// Parse a duration component with an optional `+` or `-` sign, like `+30s`
+/// or `-1m`, into a signed number of milliseconds. A missing sign means `+`.
+pub fn parse_duration_adjustment(adjustment: &str) -> Result<i64, ParseDurationComponentError> {
+    let (negative, component) = match adjustment.as_bytes().first() {
+        Some(b'-') => (true, &adjustment[1..]),
+        Some(b'+') => (false, &adjustment[1..]),
+        _ => (false, adjustment),
+    };
+    let millis = i64::try_from(parse_duration_component(component)?.as_millis()).unwrap_or(i64::MAX);
+    Ok(if negative { -millis } else { millis })
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_parse_duration_adjustment() {
+        assert_eq!(parse_duration_adjustment("+30s"), Ok(30_000));
+        assert_eq!(parse_duration_adjustment("-1m"), Ok(-60_000));
+        assert_eq!(parse_duration_adjustment("500ms"), Ok(500));
+        assert_eq!(parse_duration_adjustment("-"), Err(ParseDurationComponentError::MissingCount));
+    }
+
     #[test]
     fn test_parse_duration() {
         let cases = vec![
@@ -104,6 +356,11 @@ mod tests {
             ("15m".to_string(), Ok(Duration::from_secs(15 * 60))),
             ("15mins".to_string(), Ok(Duration::from_secs(15 * 60))),
             ("2h".to_string(), Ok(Duration::from_secs(2 * 3600))),
+            ("m".to_string(), Err(ParseDurationComponentError::MissingCount)),
+            ("5x".to_string(), Err(ParseDurationComponentError::BadUnit("x".into()))),
+            ("9999999999999999h".to_string(), Err(ParseDurationComponentError::TooLong)),
+            ("999999999999999999m".to_string(), Err(ParseDurationComponentError::TooLong)),
+            ("5124095576030431h".to_string(), Ok(Duration::from_secs(5_124_095_576_030_431 * 3600))),
         ];
 
         for (input, expected) in cases {
@@ -111,4 +368,86 @@ mod tests {
             assert_eq!(actual, expected, "Failed for input: {:?}", input);
         }
     }
+
+    #[test]
+    fn sums_too_long_are_rejected() {
+        let hours = parse_duration_component("5000000000000000h").unwrap();
+        assert_eq!(checked_sum(&[hours, Duration::from_secs(1)]), Ok(hours + Duration::from_secs(1)));
+        assert_eq!(checked_sum(&[hours, hours]), Err(ParseDurationComponentError::TooLong));
+        assert_eq!(checked_sum(&[]), Ok(Duration::ZERO));
+    }
+
+    #[test]
+    fn test_parse_colon_separated() {
+        assert_eq!(parse_duration_component("1:30:00"), Ok(Duration::from_secs(5400)));
+        assert_eq!(parse_duration_component("1:30"), Ok(Duration::from_secs(90)));
+        assert_eq!(parse_duration_component("90:00"), Ok(Duration::from_secs(5400)));
+        let bad = |s: &str| Err(ParseDurationComponentError::BadColonFormat(s.to_string()));
+        assert_eq!(parse_duration_component("1:2:3:4"), bad("1:2:3:4"));
+        assert_eq!(parse_duration_component("1:60"), bad("1:60"));
+        assert_eq!(parse_duration_component("1:60:00"), bad("1:60:00"));
+        assert_eq!(parse_duration_component("01:02:03:004"), Ok(Duration::from_millis(3_723_004)));
+        assert_eq!(parse_duration_component("1:02:03:04"), bad("1:02:03:04"));
+        assert_eq!(parse_duration_component("1:"), Err(ParseDurationComponentError::MissingCount));
+        assert_eq!(parse_duration_component("5124095576030432:00:00"), Err(ParseDurationComponentError::TooLong));
+        assert_eq!(parse_duration_component("307445734561825861:00"), Err(ParseDurationComponentError::TooLong));
+        assert_eq!(parse_duration_component("5124095576030431:00:00"), Ok(Duration::from_secs(5_124_095_576_030_431 * 3600)));
+        assert_eq!(
+            parse_duration_component("1:2:3:4").unwrap_err().to_string(),
+            "invalid duration \"1:2:3:4\" (expected mm:ss or hh:mm:ss, eg 1:30:00)"
+        );
+    }
+
+    #[test]
+    fn colon_separated_round_trips() {
+        // A fixed xorshift sequence, so failures are reproducible.
+        let mut state: u64 = 0x2545_f491_4f6c_dd1d;
+        let mut next = || {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state
+        };
+        let bound_millis = 1000 * 3600 * 1000;
+        for _ in 0..10_000 {
+            let duration = Duration::from_millis(next() % bound_millis);
+            let formatted = ColonSeparated(duration).to_string();
+            assert_eq!(formatted.parse(), Ok(ColonSeparated(duration)), "{formatted}");
+        }
+        // Anything finer than a millisecond is dropped.
+        let duration = Duration::from_nanos(1_500_000_001);
+        assert_eq!(ColonSeparated(duration).to_string().parse(), Ok(ColonSeparated(Duration::from_millis(1500))));
+    }
+
+    #[test]
+    fn compact_with_other_labels() {
+        let words = UnitLabels::words();
+        assert_eq!(Duration::from_secs(3630).format_compact_with(&words), "1 hour 30 seconds");
+        assert_eq!(Duration::from_millis(120_001).format_compact_with(&words), "2 minutes 1 millisecond");
+        assert_eq!(Duration::ZERO.format_compact_with(&words), "0 seconds");
+
+        let german: UnitLabels = serde_json::from_str(r#"{
+            "hours": {"singular": "Std.", "plural": "Std."},
+            "minutes": {"singular": "Min.", "plural": "Min."},
+            "separator": " "
+        }"#).unwrap();
+        assert_eq!(DurationFormat::Compact.format_with(Duration::from_secs(3723), &german), "1 Std. 2 Min. 3 s");
+    }
+
+    #[test]
+    fn test_duration_formats() {
+        let cases = vec![
+            (Duration::ZERO, "00:00:00:000", "0:00.000", "0s", "0"),
+            (Duration::from_millis(3450), "00:00:03:450", "0:03.450", "3s 450ms", "3"),
+            (Duration::from_secs(3630), "01:00:30:000", "1:00:30.000", "1h 30s", "3630"),
+            (Duration::from_millis(3_723_004), "01:02:03:004", "1:02:03.004", "1h 2m 3s 4ms", "3723"),
+        ];
+
+        for (duration, colon, stopwatch, compact, seconds) in cases {
+            assert_eq!(DurationFormat::Colon.format(duration), colon);
+            assert_eq!(DurationFormat::Stopwatch.format(duration), stopwatch);
+            assert_eq!(DurationFormat::Compact.format(duration), compact);
+            assert_eq!(DurationFormat::Seconds.format(duration), seconds);
+        }
+    }
 }
@@ -0,0 +1,76 @@
+//! An optional log of timer lifecycle events, for time tracking tools. Each
+//! event is appended to the file as a line of JSON, like
+//!
+//! ```json
+//! {"timestamp_millis":1722556800000,"event":"started","id":1,"name":"tea"}
+//! ```
+
+use std::fs::OpenOptions;
+use std::io::{self, Write};
+use std::path::Path;
+use std::time::SystemTime;
+
+use serde::{Deserialize, Serialize};
+
+use crate::sand::clock;
+use crate::sand::timer::TimerId;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LifecycleEvent {
+    Started,
+    Paused,
+    Resumed,
+    Cancelled,
+    Elapsed,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct EventLogEntry {
+    /// When the event happened, in milliseconds since the unix epoch.
+    pub timestamp_millis: u64,
+    pub event: LifecycleEvent,
+    pub id: TimerId,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+}
+
+impl EventLogEntry {
+    pub fn new(now: SystemTime, event: LifecycleEvent, id: TimerId, name: Option<String>) -> Self {
+        Self { timestamp_millis: clock::unix_millis(now), event, id, name }
+    }
+}
+
+/// Append `entry` to the log at `path`, creating it if needed. The file is
+/// opened afresh each time, so it can be moved away or rotated while the
+/// daemon is running.
+pub fn append<P>(path: P, entry: &EventLogEntry) -> io::Result<()>
+where
+    P: AsRef<Path>,
+{
+    let mut line = serde_json::to_string(entry).expect("failed to serialize EventLogEntry");
+    line.push('\n');
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    file.write_all(line.as_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use super::*;
+
+    #[test]
+    fn append_writes_json_lines() {
+        let path = std::env::temp_dir().join(format!("sand-test-{}-events.jsonl", std::process::id()));
+        let now = UNIX_EPOCH + Duration::from_millis(1_000);
+        append(&path, &EventLogEntry::new(now, LifecycleEvent::Started, TimerId(1), Some("tea".into()))).unwrap();
+        append(&path, &EventLogEntry::new(now, LifecycleEvent::Elapsed, TimerId(1), None)).unwrap();
+        let contents = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(contents, concat!(
+            "{\"timestamp_millis\":1000,\"event\":\"started\",\"id\":1,\"name\":\"tea\"}\n",
+            "{\"timestamp_millis\":1000,\"event\":\"elapsed\",\"id\":1}\n",
+        ));
+    }
+}
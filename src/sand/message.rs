@@ -1,16 +1,124 @@
+use std::path::PathBuf;
+
 use serde::{Deserialize, Serialize};
 use derive_more::From;
 
+use crate::sand::config::Config;
 use crate::sand::timer::*;
 
-#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+/// A line the daemon may send in place of a response, while a response is
+/// taking a long time, to check the client is still there. Clients should
+/// skip over it.
+pub const KEEPALIVE_FRAME: &str = "\"keepalive\"";
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(rename_all = "lowercase")]
 pub enum Command {
+    /// List every timer, by id. The same as `ListWith` the default
+    /// [`ListArgs`].
     List,
-    AddTimer { duration: u64 },
+    ListWith(ListArgs),
+    AddTimer(StartSpec),
+    /// Start a new timer like the last one that was started. If `name` is
+    /// given, it replaces the last timer's name.
+    Again {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        name: Option<String>,
+    },
     PauseTimer(TimerId),
     ResumeTimer(TimerId),
+    /// Have a paused timer resume by itself at a time, in milliseconds since
+    /// the unix epoch. Answered with a [`ResumeTimerResponse`].
+    ResumeAt { id: TimerId, at_millis: u64 },
+    /// Pause the timer if it's running, or resume it if it's paused.
+    ToggleTimer(TimerId),
     CancelTimer(TimerId),
+    /// Add `millis` to the time remaining on a timer, or take it away if
+    /// it's negative. The remaining time can't go below zero.
+    ExtendTimer { id: TimerId, millis: i64 },
+    /// Set the time remaining on a timer to `millis`.
+    SetTimer { id: TimerId, millis: u64 },
+    /// Start a timer which has elapsed again for `millis`, with the same id
+    /// and name.
+    Snooze { id: TimerId, millis: u64 },
+    /// Respond once the timer has elapsed or been cancelled.
+    Wait(TimerId),
+    /// The configuration the daemon is currently using.
+    GetConfig,
+    /// The audio formats the daemon can play the timer sound in.
+    Formats,
+    /// Where the timer sound was loaded from, and how long it is.
+    SoundInfo,
+    /// Every timer, in a form which can be given back to
+    /// [`Command::ImportTimer`], on this daemon or another.
+    Export,
+    /// Add a timer from [`Command::Export`] under a new id, with the time
+    /// remaining and state it was exported with. Answered with an
+    /// [`AddTimerResponse`].
+    ImportTimer(ExportedTimer),
+    DebugDump,
+    /// Check the daemon's timers for states they shouldn't be able to get
+    /// into, for debugging.
+    Fsck,
+    /// How many timers there are, and how much time they have left between
+    /// them.
+    Stats,
+    /// Turn the connection into a stream of timer lifecycle events. The
+    /// daemon responds with [`SubscribeResponse::Subscribed`], then sends an
+    /// [`EventLogEntry`](crate::sand::event_log::EventLogEntry) line for
+    /// each event, in the same format as the event log, with keepalives
+    /// while it's quiet. No further commands are answered on the connection.
+    Subscribe,
+    /// The timers matching `filter`, in the order given by `sort_by`.
+    /// Answered with a [`ListResponse`].
+    Query {
+        filter: TimerFilter,
+        #[serde(default)]
+        sort_by: SortKey,
+    },
+    /// Do nothing, and answer with [`NopResponse::Ok`]. For checking the
+    /// daemon is there and responding, without side effects.
+    Nop,
+}
+
+/// Which timers to list, and in what order.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ListArgs {
+    pub sort_by: SortKey,
+    /// Only list timers in this state.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub state: Option<TimerState>,
+}
+
+/// Which timers a [`Command::Query`] is for. A timer has to match every
+/// field which is set, so the default matches every timer.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TimerFilter {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub state: Option<TimerState>,
+    /// Only timers with a name containing this, ignoring case.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name_contains: Option<String>,
+    /// Only timers with at least this long left, in milliseconds.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub min_remaining_millis: Option<u64>,
+    /// Only timers with at most this long left, in milliseconds.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_remaining_millis: Option<u64>,
+}
+
+impl TimerFilter {
+    pub fn matches(&self, timer: &TimerInfoForClient) -> bool {
+        let remaining = timer.remaining_millis();
+        self.state.is_none_or(|state| timer.state() == state)
+            && self.name_contains.as_ref().is_none_or(|part| {
+                timer.name().is_some_and(|name| name.to_lowercase().contains(&part.to_lowercase()))
+            })
+            && self.min_remaining_millis.is_none_or(|min| remaining >= min)
+            && self.max_remaining_millis.is_none_or(|max| remaining <= max)
+    }
 }
 
 #[derive(Debug, PartialEq, Serialize, Deserialize)]
@@ -28,6 +136,15 @@ impl ListResponse {
 #[serde(rename_all = "lowercase")]
 pub enum AddTimerResponse {
     Ok { id: TimerId },
+    /// The daemon already has its maximum number of timers.
+    LimitReached { max: usize },
+    /// The timer the new one was to start after doesn't exist.
+    AfterNotFound { after: TimerId },
+    /// The duration, in milliseconds, is too long for a timer.
+    InvalidDuration { duration: u64 },
+    /// The timer was to be unique, and this running timer has the same
+    /// duration and name.
+    AlreadyExists { id: TimerId },
 }
 impl AddTimerResponse {
     pub fn ok(id: TimerId) -> AddTimerResponse {
@@ -35,6 +152,17 @@ impl AddTimerResponse {
     }
 }
 
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AgainResponse {
+    Ok { id: TimerId, duration: u64, #[serde(default)] name: Option<String> },
+    NonePrevious,
+    LimitReached { max: usize },
+    /// The last timer started was remembered with a duration, in
+    /// milliseconds, which a new timer can't have.
+    InvalidStoredDuration { duration: u64 },
+}
+
 #[derive(Debug, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "lowercase")]
 pub enum CancelTimerResponse {
@@ -58,14 +186,193 @@ pub enum ResumeTimerResponse {
     AlreadyRunning,
 }
 
-#[derive(Serialize, Deserialize, From)]
+/// Which way a [`Command::ToggleTimer`] went.
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ToggleTimerResponse {
+    Paused,
+    Resumed,
+    TimerNotFound,
+}
+
+/// The response to both [`Command::ExtendTimer`] and [`Command::SetTimer`].
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AdjustTimerResponse {
+    Ok { remaining_millis: u64 },
+    TimerNotFound,
+    /// The timer would have this many milliseconds left, which is zero or
+    /// too long for a timer. It's left as it was.
+    InvalidDuration { duration: u64 },
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum WaitResponse {
+    Elapsed,
+    Cancelled,
+    TimerNotFound,
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum GetConfigResponse {
+    Ok(Box<Config>),
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FormatsResponse {
+    Ok { formats: Vec<String> },
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SoundInfoResponse {
+    Ok {
+        path: PathBuf,
+        /// `None` when the decoder can't tell without playing the sound.
+        duration_millis: Option<u64>,
+    },
+    /// No sound could be loaded, or the daemon has no audio output.
+    NoSound { reason: String },
+}
+
+/// The daemon's internal state, for bug reports.
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct DebugDump {
+    pub version: String,
+    pub timers: Vec<TimerDebugInfo>,
+    pub max_timers: usize,
+    pub time_suspended_millis: u64,
+    pub socket_path: Option<PathBuf>,
+    pub sound_path: Option<PathBuf>,
+    pub config_path: Option<PathBuf>,
+    pub config: Config,
+}
+
+/// A timer as `sand export` writes it and `sand import` reads it. Ids
+/// aren't kept, since they may be taken where the timer is imported, so
+/// neither is what a timer was waiting for.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ExportedTimer {
+    pub spec: StartSpec,
+    pub state: TimerState,
+    pub remaining_millis: u64,
+    /// When the timer was first started, in milliseconds since the unix
+    /// epoch.
+    pub created_at_millis: u64,
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportResponse {
+    Ok { timers: Vec<ExportedTimer> },
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct TimerDebugInfo {
+    pub id: TimerId,
+    pub spec: StartSpec,
+    pub created_at_millis: u64,
+    pub state: TimerDebugState,
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TimerDebugState {
+    /// `due_in_millis` is negative if the timer is overdue.
+    Running { due_in_millis: i64, countdown_finished: bool },
+    Paused {
+        remaining_millis: u64,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        after: Option<TimerId>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        resume_at_millis: Option<u64>,
+    },
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DebugDumpResponse {
+    Ok(Box<DebugDump>),
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SnoozeResponse {
+    Ok,
+    /// No timer with that id has elapsed since the daemon started, or the
+    /// id has been used by another timer since.
+    TimerNotFound,
+    /// The timer is still running or paused.
+    NotElapsed,
+    LimitReached { max: usize },
+    InvalidDuration { duration: u64 },
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SubscribeResponse {
+    Subscribed,
+    /// Subscribing only makes sense over a connection to the daemon.
+    Unsupported,
+}
+
+/// Something wrong with a timer, found by [`Command::Fsck`].
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct FsckFinding {
+    pub id: TimerId,
+    pub problem: String,
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FsckResponse {
+    Ok { findings: Vec<FsckFinding> },
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StatsResponse {
+    /// The remaining times are added up separately for running and paused
+    /// timers, in milliseconds.
+    Ok {
+        running: usize,
+        paused: usize,
+        running_remaining_millis: u64,
+        paused_remaining_millis: u64,
+    },
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum NopResponse {
+    Ok,
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize, From)]
 #[serde(untagged)]
 pub enum Response {
     List(ListResponse),
     AddTimer(AddTimerResponse),
+    Again(AgainResponse),
     CancelTimer(CancelTimerResponse),
     PauseTimer(PauseTimerResponse),
     ResumeTimer(ResumeTimerResponse),
+    ToggleTimer(ToggleTimerResponse),
+    AdjustTimer(AdjustTimerResponse),
+    Wait(WaitResponse),
+    GetConfig(GetConfigResponse),
+    Formats(FormatsResponse),
+    SoundInfo(SoundInfoResponse),
+    Export(ExportResponse),
+    DebugDump(DebugDumpResponse),
+    Fsck(FsckResponse),
+    Stats(StatsResponse),
+    Snooze(SnoozeResponse),
+    Subscribe(SubscribeResponse),
+    Nop(NopResponse),
 
     #[from(ignore)]
     Error(String),
@@ -73,6 +380,8 @@ pub enum Response {
 
 #[cfg(test)]
 mod test {
+    use std::time::Duration;
+
     use super::*;
 
     #[test]
@@ -85,6 +394,43 @@ mod test {
         assert_eq!(Command::List, deserialized);
     }
 
+    #[test]
+    fn serde_add_timer() {
+        let deserialized: Command = serde_json::from_str(r#"{"addtimer":{"duration":60000}}"#).unwrap();
+        assert_eq!(Command::AddTimer(StartSpec::new(Duration::from_secs(60))), deserialized);
+
+        let spec = StartSpec { name: Some("tea".into()), ..StartSpec::new(Duration::from_secs(60)) };
+        let serialized = serde_json::to_string(&Command::AddTimer(spec)).unwrap();
+        assert_eq!(r#"{"addtimer":{"duration":60000,"name":"tea"}}"#, serialized);
+
+        let quiet = StartSpec { no_notify: true, ..StartSpec::new(Duration::from_secs(60)) };
+        let serialized = serde_json::to_string(&Command::AddTimer(quiet)).unwrap();
+        assert_eq!(r#"{"addtimer":{"duration":60000,"no_notify":true}}"#, serialized);
+    }
+
+    #[test]
+    fn filter_matches_every_field() {
+        let tea: TimerInfoForClient = serde_json::from_str(
+            r#"{"id":1,"state":"Running","remaining_millis":60000,"name":"Green tea"}"#
+        ).unwrap();
+        let unnamed: TimerInfoForClient = serde_json::from_str(
+            r#"{"id":2,"state":"Paused","remaining_millis":1000}"#
+        ).unwrap();
+        assert!(TimerFilter::default().matches(&tea));
+        assert!(TimerFilter::default().matches(&unnamed));
+
+        let filter = TimerFilter { name_contains: Some("TEA".into()), ..Default::default() };
+        assert!(filter.matches(&tea));
+        assert!(!filter.matches(&unnamed));
+
+        let filter = TimerFilter { min_remaining_millis: Some(5000), max_remaining_millis: Some(60000), ..Default::default() };
+        assert!(filter.matches(&tea));
+        assert!(!filter.matches(&unnamed));
+
+        let filter = TimerFilter { state: Some(TimerState::Running), name_contains: Some("coffee".into()), ..Default::default() };
+        assert!(!filter.matches(&tea));
+    }
+
     #[test]
     fn serde_list_response() {
         let response = ListResponse::ok(vec![]);
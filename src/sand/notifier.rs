@@ -0,0 +1,309 @@
+//! How the user is told that a timer has elapsed. Which [`Notifier`] is
+//! used is chosen by [`Config::notifier`].
+
+use std::time::Duration;
+
+use notify_rust::{Hint, Notification, Timeout};
+use serde::{Deserialize, Serialize};
+
+use crate::sand::config::{Config, Urgency};
+use crate::sand::timer::TimerId;
+
+/// A timer which has just elapsed, for a [`Notifier`] to tell the user
+/// about. This is also the JSON body a [`WebhookNotifier`] posts.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ElapsedTimer {
+    pub id: TimerId,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    pub summary: String,
+    pub body: String,
+    pub urgency: Urgency,
+    /// The icon to show instead of the default.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub icon: Option<String>,
+    /// The timer was due while the system was asleep, so this is late.
+    pub elapsed_while_asleep: bool,
+}
+
+/// Something the user can do from a notification, if the notifier
+/// supports it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NotificationAction {
+    /// Start the timer again, with the same duration.
+    Restart,
+    /// Start a short timer, with the same name.
+    Snooze,
+}
+
+/// The icon desktop notifications have, unless the timer overrides it.
+pub const DEFAULT_ICON: &str = "alarm";
+
+pub type OnAction = Box<dyn FnOnce(NotificationAction) + Send>;
+
+pub trait Notifier: Send + Sync {
+    /// Tell the user that `timer` has elapsed, logging any failure to do
+    /// so. If the user picks an action from the notification, `on_action`
+    /// is called with it, possibly much later.
+    fn notify(&self, timer: &ElapsedTimer, on_action: OnAction);
+}
+
+/// Which notifier to use, in the config file.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum NotifierConfig {
+    /// A desktop notification, through the freedesktop notifications API.
+    #[default]
+    Freedesktop,
+    /// POST the [`ElapsedTimer`] as JSON to `url`.
+    Webhook { url: String },
+}
+
+impl NotifierConfig {
+    /// `actions` is whether the notification server supports actions, which
+    /// only matters for [`NotifierConfig::Freedesktop`].
+    pub fn notifier(&self, config: &Config, actions: bool) -> Box<dyn Notifier> {
+        match self {
+            Self::Freedesktop => Box::new(FreedesktopNotifier::new(config, actions)),
+            Self::Webhook { url } => Box::new(WebhookNotifier::new(url.clone())),
+        }
+    }
+}
+
+/// The longest a notification's buttons are waited on, after which it's
+/// closed, so one left on screen doesn't hold a thread forever.
+const MAX_ACTION_WAIT: Duration = Duration::from_secs(60 * 60);
+
+/// How long to wait for the user to act on a notification, given the
+/// configured `notification_timeout`. Zero means no timeout to notification
+/// servers, so it's treated like leaving it out.
+fn action_wait(timeout_secs: Option<u32>) -> Duration {
+    timeout_secs
+        .filter(|&secs| secs > 0)
+        .map_or(MAX_ACTION_WAIT, |secs| {
+            Duration::from_secs(secs.into()).min(MAX_ACTION_WAIT)
+        })
+}
+
+/// Closes the notification with the given ID, which ends any wait for its
+/// actions.
+async fn close_notification(id: u32) -> zbus::Result<()> {
+    let connection = zbus::Connection::session().await?;
+    connection
+        .call_method(
+            Some("org.freedesktop.Notifications"),
+            "/org/freedesktop/Notifications",
+            Some("org.freedesktop.Notifications"),
+            "CloseNotification",
+            &(id,),
+        )
+        .await?;
+    Ok(())
+}
+
+/// Desktop notifications, which get buttons to restart or snooze the timer
+/// if the notification server supports them.
+pub struct FreedesktopNotifier {
+    app_name: String,
+    desktop_entry: String,
+    timeout: Option<Timeout>,
+    action_wait: Duration,
+    actions: bool,
+}
+
+impl FreedesktopNotifier {
+    pub fn new(config: &Config, actions: bool) -> Self {
+        Self {
+            app_name: config.app_name.clone(),
+            desktop_entry: config.desktop_entry.clone(),
+            timeout: config.notification_timeout(),
+            action_wait: action_wait(config.notification_timeout),
+            actions,
+        }
+    }
+}
+
+impl Notifier for FreedesktopNotifier {
+    fn notify(&self, timer: &ElapsedTimer, on_action: OnAction) {
+        let mut notification = Notification::new();
+        notification
+            .appname(&self.app_name)
+            .hint(Hint::DesktopEntry(self.desktop_entry.clone()))
+            .summary(&timer.summary)
+            .body(&timer.body)
+            .icon(timer.icon.as_deref().unwrap_or(DEFAULT_ICON))
+            .urgency(timer.urgency.into());
+        if let Some(timeout) = self.timeout {
+            notification.timeout(timeout);
+        }
+        if self.actions {
+            notification
+                .action("restart", "Restart")
+                .action("snooze", "Snooze 5m");
+        }
+        match notification.show() {
+            Ok(handle) if self.actions => {
+                let id = handle.id();
+                let waiting = tokio::task::spawn_blocking(move || {
+                    handle.wait_for_action(|action| match action {
+                        "restart" => on_action(NotificationAction::Restart),
+                        "snooze" => on_action(NotificationAction::Snooze),
+                        _ => {}
+                    });
+                });
+                // Closing the notification makes the server report it
+                // closed, which is what lets the blocking wait return.
+                let action_wait = self.action_wait;
+                tokio::spawn(async move {
+                    if tokio::time::timeout(action_wait, waiting).await.is_err() {
+                        if let Err(e) = close_notification(id).await {
+                            eprintln!("Error closing notification {id}: {e}");
+                        }
+                    }
+                });
+            }
+            Ok(_) => {}
+            Err(e) => eprintln!("Error showing desktop notification: {e}"),
+        }
+    }
+}
+
+/// How long a webhook has to accept the connection.
+const WEBHOOK_CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+/// How long a webhook has to respond altogether, so one which hangs doesn't
+/// hold a thread forever.
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(15);
+
+/// Posts each elapsed timer as JSON to a URL, for forwarding notifications
+/// somewhere without a desktop. There are no actions.
+///
+/// Only plain http URLs work unless sand is built with the `webhook-tls`
+/// feature.
+pub struct WebhookNotifier {
+    url: String,
+    agent: ureq::Agent,
+}
+
+impl WebhookNotifier {
+    pub fn new(url: String) -> Self {
+        let agent = ureq::AgentBuilder::new()
+            .timeout_connect(WEBHOOK_CONNECT_TIMEOUT)
+            .timeout(WEBHOOK_TIMEOUT)
+            .build();
+        Self { url, agent }
+    }
+}
+
+impl Notifier for WebhookNotifier {
+    fn notify(&self, timer: &ElapsedTimer, _on_action: OnAction) {
+        let url = self.url.clone();
+        let agent = self.agent.clone();
+        let json = serde_json::to_string(timer).expect("failed to serialize ElapsedTimer");
+        tokio::task::spawn_blocking(move || {
+            let resp = agent.post(&url)
+                .set("Content-Type", "application/json")
+                .send_string(&json);
+            if let Err(e) = resp {
+                eprintln!("Error posting notification to {url}: {e}");
+            }
+        });
+    }
+}
+
+/// Speak `text` by running `command` with it as an extra argument, for
+/// hearing which timer elapsed without looking. Failures are logged.
+pub fn speak(command: &[String], text: &str) {
+    let Some((program, args)) = command.split_first() else {
+        eprintln!("Error speaking timer: tts_command is empty");
+        return;
+    };
+    let child = tokio::process::Command::new(program)
+        .args(args)
+        .arg(text)
+        .stdin(std::process::Stdio::null())
+        .spawn();
+    let mut child = match child {
+        Ok(child) => child,
+        Err(e) => {
+            eprintln!("Error running tts_command {program:?}: {e}");
+            return;
+        }
+    };
+    let program = program.clone();
+    tokio::spawn(async move {
+        match child.wait().await {
+            Ok(status) if status.success() => {}
+            Ok(status) => eprintln!("tts_command {program:?} failed: {status}"),
+            Err(e) => eprintln!("Error waiting for tts_command {program:?}: {e}"),
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{BufRead, BufReader, Read};
+    use std::net::TcpListener;
+
+    use super::*;
+
+    #[test]
+    fn action_wait_follows_the_notification_timeout() {
+        assert_eq!(action_wait(Some(30)), Duration::from_secs(30));
+        assert_eq!(action_wait(None), MAX_ACTION_WAIT);
+        assert_eq!(action_wait(Some(0)), MAX_ACTION_WAIT);
+        assert_eq!(action_wait(Some(u32::MAX)), MAX_ACTION_WAIT);
+    }
+
+    #[tokio::test]
+    async fn webhook_posts_json() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/sand", listener.local_addr().unwrap());
+        let timer = ElapsedTimer {
+            id: TimerId(3),
+            name: Some("tea".into()),
+            summary: "Time's up!".into(),
+            body: "tea".into(),
+            urgency: Urgency::Critical,
+            icon: None,
+            elapsed_while_asleep: false,
+        };
+        WebhookNotifier::new(url).notify(&timer, Box::new(|_| unreachable!()));
+
+        let (stream, _) = listener.accept().unwrap();
+        let mut reader = BufReader::new(stream);
+        let mut request_line = String::new();
+        reader.read_line(&mut request_line).unwrap();
+        assert_eq!(request_line, "POST /sand HTTP/1.1\r\n");
+        let mut content_length = 0;
+        loop {
+            let mut header = String::new();
+            reader.read_line(&mut header).unwrap();
+            if header == "\r\n" {
+                break;
+            }
+            if let Some(len) = header.to_lowercase().strip_prefix("content-length: ") {
+                content_length = len.trim().parse().unwrap();
+            }
+        }
+        let mut body = vec![0; content_length];
+        reader.read_exact(&mut body).unwrap();
+        let posted: ElapsedTimer = serde_json::from_slice(&body).unwrap();
+        assert_eq!(posted, timer);
+    }
+
+    #[tokio::test]
+    async fn speak_passes_the_text_last() {
+        let out = std::env::temp_dir().join(format!("sand-test-{}-spoken", std::process::id()));
+        let script = format!("printf '%s %s' \"$0\" \"$1\" > {}.tmp && mv {0}.tmp {0}", out.display());
+        speak(&["sh".into(), "-c".into(), script, "say".into()], "tea");
+        for _ in 0..100 {
+            if let Ok(spoken) = std::fs::read_to_string(&out) {
+                std::fs::remove_file(&out).unwrap();
+                assert_eq!(spoken, "say tea");
+                return;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+        panic!("tts_command wasn't run");
+    }
+}
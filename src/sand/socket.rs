@@ -0,0 +1,79 @@
+//! Where the daemon's socket lives.
+
+use std::fs::{self, DirBuilder};
+use std::io;
+use std::os::unix::fs::{DirBuilderExt, MetadataExt, PermissionsExt};
+use std::path::PathBuf;
+
+const SOCKET_FILENAME: &str = "sand.sock";
+
+/// Where the socket path came from, for explaining it to the user.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SockPathSource {
+    /// `SAND_SOCK_PATH`.
+    Env,
+    /// The user's runtime directory, `$XDG_RUNTIME_DIR`.
+    RuntimeDir,
+    /// `/tmp/sand-$UID`, since there's no runtime directory.
+    Fallback,
+}
+
+impl std::fmt::Display for SockPathSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Env => "SAND_SOCK_PATH",
+            Self::RuntimeDir => "the runtime directory, XDG_RUNTIME_DIR",
+            Self::Fallback => "the fallback directory, since XDG_RUNTIME_DIR isn't set",
+        })
+    }
+}
+
+/// The socket path used when `SAND_SOCK_PATH` isn't set: in the user's
+/// runtime directory, or if there isn't one, as in SSH sessions without a
+/// proper login session, in a private directory under `/tmp`.
+pub fn default_sock_path() -> io::Result<PathBuf> {
+    default_sock_path_with_source().map(|(path, _)| path)
+}
+
+fn default_sock_path_with_source() -> io::Result<(PathBuf, SockPathSource)> {
+    let (dir, source) = match dirs::runtime_dir() {
+        Some(dir) => (dir, SockPathSource::RuntimeDir),
+        None => (fallback_runtime_dir()?, SockPathSource::Fallback),
+    };
+    Ok((dir.join(SOCKET_FILENAME), source))
+}
+
+/// The socket path to use, from `SAND_SOCK_PATH` if it's set.
+pub fn sock_path() -> io::Result<PathBuf> {
+    sock_path_with_source().map(|(path, _)| path)
+}
+
+/// Like [`sock_path`], also saying where the path came from.
+pub fn sock_path_with_source() -> io::Result<(PathBuf, SockPathSource)> {
+    match std::env::var_os("SAND_SOCK_PATH") {
+        Some(path) => Ok((path.into(), SockPathSource::Env)),
+        None => default_sock_path_with_source(),
+    }
+}
+
+/// `/tmp/sand-$UID`, created if needed, readable only by us. Since anyone
+/// can create files in `/tmp`, an existing directory is only used if it
+/// belongs to us and nobody else can access it.
+fn fallback_runtime_dir() -> io::Result<PathBuf> {
+    // SAFETY: getuid has no preconditions and can't fail.
+    let uid = unsafe { libc::getuid() };
+    let dir = std::env::temp_dir().join(format!("sand-{uid}"));
+    match DirBuilder::new().mode(0o700).create(&dir) {
+        Ok(()) => {}
+        Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {}
+        Err(e) => return Err(e),
+    }
+    let metadata = fs::symlink_metadata(&dir)?;
+    if !metadata.is_dir() || metadata.uid() != uid || metadata.permissions().mode() & 0o077 != 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            format!("{} isn't a private directory belonging to us", dir.display()),
+        ));
+    }
+    Ok(dir)
+}
@@ -0,0 +1,78 @@
+//! State the daemon keeps across restarts, under `$XDG_STATE_HOME/sand`.
+//!
+//! For now that's just the spec of the last timer started, so `sand again`
+//! still works after the daemon restarts.
+
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::sand::timer::StartSpec;
+
+const LAST_STARTED_FILENAME: &str = "last_started.json";
+
+pub fn state_dir() -> Option<PathBuf> {
+    Some(dirs::state_dir()?.join("sand"))
+}
+
+pub fn last_started_path() -> Option<PathBuf> {
+    Some(state_dir()?.join(LAST_STARTED_FILENAME))
+}
+
+/// Load the last timer started, or `None` if no timer has been started yet.
+///
+/// A timer is remembered without what it was to start after or whether it
+/// was unique, since those only apply to starting it the first time. They're
+/// cleared here too, in case the file was edited by hand.
+pub fn load_last_started(path: &Path) -> io::Result<Option<StartSpec>> {
+    let json = match std::fs::read_to_string(path) {
+        Ok(json) => json,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    let spec: StartSpec = serde_json::from_str(&json)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    Ok(Some(StartSpec { after: None, unique: false, ..spec }))
+}
+
+/// Save the last timer started, creating its directory if needed. The file
+/// is replaced in one step, so a crash can't leave it half written.
+pub fn save_last_started(path: &Path, spec: &StartSpec) -> io::Result<()> {
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    let json = serde_json::to_string(spec).expect("failed to serialize StartSpec");
+    let tmp_path = path.with_extension("json.tmp");
+    std::fs::write(&tmp_path, json)?;
+    std::fs::rename(&tmp_path, path)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn last_started_round_trips() {
+        let dir = std::env::temp_dir().join(format!("sand-test-{}-state", std::process::id()));
+        let path = dir.join(LAST_STARTED_FILENAME);
+        assert_eq!(load_last_started(&path).unwrap(), None);
+
+        let spec = StartSpec { name: Some("tea".into()), ..StartSpec::new(Duration::from_secs(180)) };
+        save_last_started(&path, &spec).unwrap();
+        let loaded = load_last_started(&path).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(loaded, Some(spec));
+    }
+
+    #[test]
+    fn loading_forgets_after_and_unique() {
+        let dir = std::env::temp_dir().join(format!("sand-test-{}-state-edited", std::process::id()));
+        let path = dir.join(LAST_STARTED_FILENAME);
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(&path, r#"{"duration":60000,"after":3,"unique":true}"#).unwrap();
+        let loaded = load_last_started(&path).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(loaded, Some(StartSpec::new(Duration::from_secs(60))));
+    }
+}
@@ -1,9 +1,11 @@
-use std::{fmt::Display, time::{Duration, Instant}};
+use std::{fmt::Display, time::{Duration, Instant, SystemTime}};
 
 use serde::{Deserialize, Serialize};
 use tokio::task::JoinHandle;
 
-use crate::sand::duration::DurationExt;
+use crate::sand::clock;
+use crate::sand::config::Urgency;
+use crate::sand::duration::DurationFormat;
 
 
 #[derive(PartialEq, Eq, Hash, Debug, Clone, Copy, Serialize, Deserialize)]
@@ -21,61 +23,350 @@ impl Display for TimerId {
     }
 }
 
+/// The most timer IDs a single argument like `1-100` can name. Far more
+/// timers than a daemon would have, but few enough to expand a range into a
+/// list of IDs without running out of memory.
+pub const MAX_IDS_PER_ARGUMENT: u64 = 10_000;
+
 impl TimerId {
     pub fn next(self) -> Self {
         Self(self.0 + 1)
     }
 
-    pub fn parse_or_quit(timer_id: &str) -> Self {
-        u64::from_str_radix(&timer_id, 10)
-            .map(TimerId)
-            .unwrap_or_else(|e| {
-                eprintln!("Failed to parse timer id \"{timer_id}\": {e}");
-                std::process::exit(1)
-            })
+    /// Parse a single timer id, with a message fit for the user if it's
+    /// not one. Usable as a clap value parser.
+    pub fn parse(s: &str) -> Result<Self, String> {
+        let s = s.trim();
+        match s.parse::<u64>() {
+            Ok(0) => Err("0 is not a valid timer ID (IDs start at 1)".to_string()),
+            Ok(id) => Ok(TimerId(id)),
+            Err(_) => Err(format!("'{s}' is not a valid timer ID (expected a positive integer)")),
+        }
+    }
+
+    /// Parse a timer id argument, which may be a single id (`3`), an
+    /// inclusive range (`3-7`), or a comma separated list of either (`1,3-5`).
+    /// Altogether it can name at most [`MAX_IDS_PER_ARGUMENT`] ids.
+    pub fn parse_many(arg: &str) -> Result<Vec<Self>, String> {
+        let too_many = || format!("\"{arg}\" names more than {MAX_IDS_PER_ARGUMENT} timer IDs");
+        let mut ids = Vec::new();
+        for part in arg.split(',') {
+            match part.split_once('-') {
+                None => ids.push(Self::parse(part)?),
+                Some((start, end)) => {
+                    let (TimerId(start), TimerId(end)) = (Self::parse(start)?, Self::parse(end)?);
+                    if start > end {
+                        return Err(format!("invalid timer ID range \"{part}\" (start is greater than end)"));
+                    }
+                    if end - start >= MAX_IDS_PER_ARGUMENT - ids.len() as u64 {
+                        return Err(too_many());
+                    }
+                    ids.extend((start..=end).map(TimerId));
+                }
+            }
+            if ids.len() as u64 > MAX_IDS_PER_ARGUMENT {
+                return Err(too_many());
+            }
+        }
+        Ok(ids)
+    }
+
+    pub fn parse_many_or_quit(args: &[String]) -> Vec<Self> {
+        let mut ids = Vec::new();
+        for arg in args {
+            match Self::parse_many(arg) {
+                Ok(parsed) => ids.extend(parsed),
+                Err(e) => {
+                    eprintln!("Error: {e}");
+                    std::process::exit(1)
+                }
+            }
+        }
+        ids
+    }
+}
+
+/// The longest a timer can be set for, ten years. Anything longer is
+/// surely a mistake.
+pub const MAX_DURATION: Duration = Duration::from_secs(10 * 365 * 24 * 60 * 60);
+
+/// Everything the user asked for when starting a timer.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StartSpec {
+    /// In milliseconds.
+    pub duration: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    /// Overrides the configured urgency for this timer's notification.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub urgency: Option<Urgency>,
+    /// Overrides the icon for this timer's desktop notification. An icon
+    /// theme name, like `alarm`, or a path to an image.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub icon: Option<String>,
+    /// Only play the sound when the timer elapses, without a notification.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub no_notify: bool,
+    /// Start the timer again each time it elapses, rather than removing it.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub repeat: bool,
+    /// Stop repeating once the next run would end after this, in
+    /// milliseconds since the unix epoch.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub repeat_until: Option<u64>,
+    /// Wait for this timer to elapse before starting. Only used when adding
+    /// a timer; it's kept in the timer's [`RunState`] from then on.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub after: Option<TimerId>,
+    /// If a running timer has the same duration and name, or one with them
+    /// is waiting for the same `after`, don't add another. Only used when
+    /// adding a timer.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub unique: bool,
+}
+
+impl StartSpec {
+    pub fn new(duration: Duration) -> Self {
+        Self {
+            duration: duration.as_millis() as u64,
+            name: None,
+            urgency: None,
+            icon: None,
+            no_notify: false,
+            repeat: false,
+            repeat_until: None,
+            after: None,
+            unique: false,
+        }
+    }
+
+    pub fn duration(&self) -> Duration {
+        Duration::from_millis(self.duration)
+    }
+
+    /// When a timer with this spec started at `now` is due, or `None` if
+    /// its duration is zero or longer than [`MAX_DURATION`]. Every new
+    /// timer's duration is checked here, including those started by `again`
+    /// from a remembered spec.
+    pub fn due(&self, now: Instant) -> Option<Instant> {
+        let duration = self.duration();
+        if duration.is_zero() || duration > MAX_DURATION {
+            return None;
+        }
+        now.checked_add(duration)
+    }
+
+    /// Whether a timer with this spec which elapses at `now` should start
+    /// again.
+    pub fn repeats_after(&self, now: SystemTime) -> bool {
+        self.repeat && self.repeat_until.is_none_or(|until| {
+            now + self.duration() <= clock::from_unix_millis(until)
+        })
     }
 }
 
 #[derive(Debug)]
-pub enum Timer {
-    Paused { remaining: Duration },
+pub struct Timer {
+    pub spec: StartSpec,
+    pub state: RunState,
+    /// When the timer was started.
+    pub created_at: SystemTime,
+}
+
+#[derive(Debug)]
+pub enum RunState {
+    /// Not counting down. If `after` is set, the timer starts by itself when
+    /// that timer elapses, and if `resume_at` is set, it starts by itself
+    /// then.
+    Paused { remaining: Duration, after: Option<TimerId>, resume_at: Option<SystemTime> },
     Running { due: Instant, countdown: JoinHandle<()>},
 }
 
-#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
 pub enum TimerState {
     Paused,
     Running,
 }
 
+/// The order to list timers in. Ties are broken by id.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum SortKey {
+    #[default]
+    Id,
+    /// Running timers, soonest due first, then paused ones by time remaining
+    Due,
+    /// Alphabetically by name, with unnamed timers last
+    Name,
+}
+
+/// Sort `timers` by `key`, breaking ties by id.
+pub fn sort_timers(timers: &mut [TimerInfoForClient], key: SortKey) {
+    timers.sort_by(|a, b| {
+        let by_key = match key {
+            SortKey::Id => std::cmp::Ordering::Equal,
+            SortKey::Due => (a.state == TimerState::Paused, a.remaining_millis)
+                .cmp(&(b.state == TimerState::Paused, b.remaining_millis)),
+            SortKey::Name => (a.name.is_none(), &a.name).cmp(&(b.name.is_none(), &b.name)),
+        };
+        by_key.then(a.id.0.cmp(&b.id.0))
+    });
+}
+
 #[derive(Debug, PartialEq, Serialize, Deserialize)]
 pub struct TimerInfoForClient {
     id: TimerId,
     state: TimerState,
     remaining_millis: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    name: Option<String>,
+    /// When the timer was started, in milliseconds since the unix epoch.
+    #[serde(default)]
+    created_at_millis: u64,
+    /// The timer this one is waiting for, if it's paused until that elapses.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    after: Option<TimerId>,
+    /// When the timer is scheduled to resume, if it's paused until then, in
+    /// milliseconds since the unix epoch.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    resume_at_millis: Option<u64>,
 }
 
 impl TimerInfoForClient  {
     
     pub fn new(id: TimerId, timer: &Timer, now: Instant) -> Self {
-        let (state, remaining_millis) = match timer {
-            Timer::Paused { remaining } =>
-                (TimerState::Paused, remaining.as_millis() as u64),
-            Timer::Running { due, .. } => 
-                (TimerState::Running, (*due - now).as_millis() as u64),
+        let (state, remaining_millis, after, resume_at) = match timer.state {
+            RunState::Paused { remaining, after, resume_at } =>
+                (TimerState::Paused, remaining.as_millis() as u64, after, resume_at),
+            RunState::Running { due, .. } => 
+                (TimerState::Running, (due - now).as_millis() as u64, None, None),
+        };
+        let name = timer.spec.name.clone();
+        let created_at_millis = clock::unix_millis(timer.created_at);
+        let resume_at_millis = resume_at.map(clock::unix_millis);
+        Self { id, state, remaining_millis, name, created_at_millis, after, resume_at_millis }
+    }
+
+
+    pub fn id(&self) -> TimerId {
+        self.id
+    }
+
+    pub fn state(&self) -> TimerState {
+        self.state
+    }
+
+    pub fn remaining_millis(&self) -> u64 {
+        self.remaining_millis
+    }
+
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    pub fn created_at(&self) -> SystemTime {
+        clock::from_unix_millis(self.created_at_millis)
+    }
+
+    pub fn after(&self) -> Option<TimerId> {
+        self.after
+    }
+
+    pub fn resume_at(&self) -> Option<SystemTime> {
+        self.resume_at_millis.map(clock::from_unix_millis)
+    }
+
+    pub fn display(&self, format: DurationFormat) -> String {
+        self.display_with(format.format(Duration::from_millis(self.remaining_millis)))
+    }
+
+    /// Like [`TimerInfoForClient::display`], with `remaining` in place of
+    /// the time remaining.
+    pub fn display_with(&self, remaining: String) -> String {
+        match self.columns(remaining) {
+            (id, remaining, Some(name)) => format!("{id} | {remaining} | {name}"),
+            (id, remaining, None) => format!("{id} | {remaining}"),
+        }
+    }
+
+    /// The parts of [`TimerInfoForClient::display_with`], so they can be
+    /// lined up with other timers': the id, `remaining` along with whether
+    /// the timer is paused, and the name if there is one.
+    pub fn columns(&self, remaining: String) -> (String, String, Option<String>) {
+        let maybe_paused = match (self.state, self.after, self.resume_at()) {
+            (TimerState::Paused, Some(after), _) => format!(" (AFTER {after})"),
+            (TimerState::Paused, None, Some(at)) => format!(" (PAUSED UNTIL {})", clock::format_local(at)),
+            (TimerState::Paused, None, None) => " (PAUSED)".to_string(),
+            (TimerState::Running, ..) => String::new(),
         };
-        Self { id, state, remaining_millis }
+        (self.id.to_string(), format!("{remaining}{maybe_paused}"), self.name.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn repeat_stops_at_cutoff() {
+        let now = clock::from_unix_millis(1_000_000);
+        let once = StartSpec::new(Duration::from_secs(60));
+        assert!(!once.repeats_after(now));
+        let forever = StartSpec { repeat: true, ..once.clone() };
+        assert!(forever.repeats_after(now));
+        let until = |millis| StartSpec { repeat_until: Some(millis), ..forever.clone() };
+        assert!(until(1_060_000).repeats_after(now));
+        assert!(!until(1_059_999).repeats_after(now));
     }
 
+    #[test]
+    fn test_sort_timers() {
+        let info = |id, state, remaining_millis, name: Option<&str>| TimerInfoForClient {
+            id: TimerId(id),
+            state,
+            remaining_millis,
+            name: name.map(String::from),
+            created_at_millis: 0,
+            after: None,
+            resume_at_millis: None,
+        };
+        let mut timers = vec![
+            info(1, TimerState::Paused, 100, None),
+            info(2, TimerState::Running, 500, Some("tea")),
+            info(3, TimerState::Running, 200, Some("eggs")),
+            info(4, TimerState::Running, 200, None),
+        ];
+        let ids = |timers: &[TimerInfoForClient]| timers.iter().map(|t| t.id.0).collect::<Vec<_>>();
 
-    pub fn display(&self) -> String {
-        let remaining: String = Duration::from_millis(self.remaining_millis)
-            .format_colon_separated();
-        let id = self.id;
-        const PAUSED: &'static str = " (PAUSED)";
-        const NOT_PAUSED: &'static str = "";
-        let maybe_paused = 
-            if self.state == TimerState::Paused { PAUSED } else { NOT_PAUSED };
-        format!("{id} | {remaining}{maybe_paused}")
+        sort_timers(&mut timers, SortKey::Due);
+        assert_eq!(ids(&timers), [3, 4, 2, 1]);
+        sort_timers(&mut timers, SortKey::Name);
+        assert_eq!(ids(&timers), [3, 2, 1, 4]);
+        sort_timers(&mut timers, SortKey::Id);
+        assert_eq!(ids(&timers), [1, 2, 3, 4]);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_parse_many() {
+        let ids = |ns: &[u64]| Ok(ns.iter().copied().map(TimerId).collect::<Vec<_>>());
+        assert_eq!(TimerId::parse_many("3"), ids(&[3]));
+        assert_eq!(TimerId::parse_many("3-7"), ids(&[3, 4, 5, 6, 7]));
+        assert_eq!(TimerId::parse_many("1,3,5"), ids(&[1, 3, 5]));
+        assert_eq!(TimerId::parse_many("1,3-5"), ids(&[1, 3, 4, 5]));
+        assert_eq!(TimerId::parse_many("4-4"), ids(&[4]));
+        assert!(TimerId::parse_many("7-3").is_err());
+        assert!(TimerId::parse_many("a").is_err());
+        assert!(TimerId::parse_many("1,").is_err());
+        assert!(TimerId::parse_many("1-").is_err());
+        assert_eq!(
+            TimerId::parse_many("1,abc"),
+            Err("'abc' is not a valid timer ID (expected a positive integer)".to_string())
+        );
+        assert_eq!(TimerId::parse_many("0-2"), Err("0 is not a valid timer ID (IDs start at 1)".to_string()));
+        assert_eq!(TimerId::parse_many("1-10000").map(|ids| ids.len()), Ok(10_000));
+        assert!(TimerId::parse_many("1-10001").is_err());
+        assert!(TimerId::parse_many("1-18446744073709551615").is_err());
+        assert!(TimerId::parse_many("1-5000,6000-11000").is_err());
+        assert!(TimerId::parse_many("1-10000,20000").is_err());
+    }
+}
@@ -1,35 +1,893 @@
 
-use std::time::Instant;
+use std::sync::{Mutex, MutexGuard};
+use std::time::{Duration, Instant, SystemTime};
 
-use dashmap::{DashMap, Entry};
+use tokio::task::JoinHandle;
 
+use crate::sand::clock;
+use crate::sand::message::{
+    AddTimerResponse, AdjustTimerResponse, CancelTimerResponse, ExportedTimer, FsckFinding, PauseTimerResponse, ResumeTimerResponse, SnoozeResponse,
+    StatsResponse, TimerDebugInfo, TimerDebugState, ToggleTimerResponse,
+};
 use crate::sand::timer::*;
 
+/// The set of active timers. Timer `#n` lives in slot `n - 1`, so ids stay
+/// small, and are reused once their timer is gone.
 #[derive(Default, Debug)]
-pub struct Timers(DashMap<TimerId, Timer>);
+pub struct Timers(Mutex<Vec<Option<Timer>>>);
+
+fn slot_index(id: TimerId) -> Option<usize> {
+    id.0.checked_sub(1)?.try_into().ok()
+}
+
+fn slot_mut(slots: &mut [Option<Timer>], id: TimerId) -> Option<&mut Timer> {
+    slots.get_mut(slot_index(id)?)?.as_mut()
+}
+
+fn count(slots: &[Option<Timer>]) -> usize {
+    slots.iter().filter(|slot| slot.is_some()).count()
+}
+
+/// How long past due a running timer can be before [`Timers::fsck`] reports
+/// it. A timer is only removed once its notification has been shown, which
+/// can take a moment.
+const FSCK_OVERDUE_GRACE: Duration = Duration::from_secs(5);
+
+/// The lowest id not in use, if it's one of the first `max`. Only the first
+/// `max` slots are searched, so finding out there's no room takes no longer
+/// than that, however many slots there are.
+///
+/// With fewer than `max` timers, one of the first `max` ids must be free,
+/// so this is also how the limit on the number of timers is enforced.
+fn first_vacant_id(slots: &[Option<Timer>], max: usize) -> Option<TimerId> {
+    let index = slots.iter().take(max).position(Option::is_none).unwrap_or(slots.len());
+    (index < max).then(|| TimerId(index as u64 + 1))
+}
+
+/// The first running timer in `slots` started with `duration` and `name`.
+fn find_running_in(slots: &[Option<Timer>], duration: u64, name: Option<&str>) -> Option<TimerId> {
+    slots.iter().zip(1..).find_map(|(slot, id)| {
+        let timer = slot.as_ref()?;
+        let same = timer.spec.duration == duration && timer.spec.name.as_deref() == name;
+        (same && matches!(timer.state, RunState::Running { .. })).then_some(TimerId(id))
+    })
+}
+
+/// The first timer waiting for `after` which was started with `duration` and
+/// `name`.
+fn find_waiting_in(slots: &[Option<Timer>], after: TimerId, duration: u64, name: Option<&str>) -> Option<TimerId> {
+    slots.iter().zip(1..).find_map(|(slot, id)| {
+        let timer = slot.as_ref()?;
+        let same = timer.spec.duration == duration && timer.spec.name.as_deref() == name;
+        let waiting = matches!(timer.state, RunState::Paused { after: Some(a), .. } if a == after);
+        (same && waiting).then_some(TimerId(id))
+    })
+}
+
+/// Put `timer` in the vacant slot for `id`, as found by [`first_vacant_id`].
+fn insert_vacant(slots: &mut Vec<Option<Timer>>, id: TimerId, timer: Timer) {
+    let index = slot_index(id).unwrap_or_else(|| unreachable!("BUG: invalid timer id {id:?}"));
+    match slots.get_mut(index) {
+        Some(slot) => *slot = Some(timer),
+        None => slots.push(Some(timer)),
+    }
+}
+
+/// Take the timer out of slot `index`, leaving it vacant. Timers waiting
+/// for it are left paused, so they can't be started by a new timer which
+/// reuses its id.
+fn vacate(slots: &mut Vec<Option<Timer>>, index: usize) -> Option<Timer> {
+    let timer = slots.get_mut(index)?.take()?;
+    let id = TimerId(index as u64 + 1);
+    for slot in slots.iter_mut().flatten() {
+        if let RunState::Paused { after, .. } = &mut slot.state {
+            if *after == Some(id) {
+                *after = None;
+            }
+        }
+    }
+    while let Some(None) = slots.last() {
+        slots.pop();
+    }
+    Some(timer)
+}
+
+/// What happened when a timer elapsed, as returned by [`Timers::elapse`].
+#[derive(Debug, PartialEq)]
+pub struct Elapsed {
+    pub spec: StartSpec,
+    /// The timer started again, because it repeats.
+    pub repeated: bool,
+    /// The timers which were waiting for this one, and have now started.
+    pub started: Vec<TimerId>,
+}
 
 impl Timers{
+    fn slots(&self) -> MutexGuard<'_, Vec<Option<Timer>>> {
+        self.0.lock().expect("another thread panicked while holding this lock.")
+    }
+
     pub fn add(&self, id: TimerId, timer: Timer) {
-        if let Some(t) = self.0.insert(id, timer) {
+        let index = slot_index(id).unwrap_or_else(|| unreachable!("BUG: invalid timer id #{id:?}"));
+        let mut slots = self.slots();
+        if slots.len() <= index {
+            slots.resize_with(index + 1, || None);
+        }
+        if let Some(t) = slots[index].replace(timer) {
             unreachable!("BUG: adding timer with id #{id:?} clobbered pre-existing timer {t:?}");
         }
     }
 
-    pub fn entry(&self, id: TimerId) -> Entry<TimerId, Timer> {
-        self.0.entry(id)
+    /// Add a timer under the lowest free id. `make_timer` is given that id.
+    pub fn add_new<F>(&self, make_timer: F) -> TimerId
+    where
+        F: FnOnce(TimerId) -> Timer,
+    {
+        self.try_add_new(usize::MAX, make_timer)
+            .expect("BUG: exceeded usize::MAX timers")
+    }
+
+    /// Like [`Timers::add_new`], but only if there are fewer than `max`
+    /// timers. Returns `None` without calling `make_timer` otherwise.
+    pub fn try_add_new<F>(&self, max: usize, make_timer: F) -> Option<TimerId>
+    where
+        F: FnOnce(TimerId) -> Timer,
+    {
+        let mut slots = self.slots();
+        let id = first_vacant_id(&slots, max)?;
+        insert_vacant(&mut slots, id, make_timer(id));
+        Some(id)
+    }
+
+    /// Like [`Timers::try_add_new`], but if there's a running timer started
+    /// with the same duration and name as `spec`, its id is returned as
+    /// [`AddTimerResponse::AlreadyExists`] instead. The check and the
+    /// insertion happen under one lock, so two of these at once can't both
+    /// add a timer.
+    pub fn try_add_unique<F>(&self, max: usize, spec: &StartSpec, make_timer: F) -> AddTimerResponse
+    where
+        F: FnOnce(TimerId) -> Timer,
+    {
+        let mut slots = self.slots();
+        if let Some(id) = find_running_in(&slots, spec.duration, spec.name.as_deref()) {
+            return AddTimerResponse::AlreadyExists { id };
+        }
+        let Some(id) = first_vacant_id(&slots, max) else {
+            return AddTimerResponse::LimitReached { max };
+        };
+        insert_vacant(&mut slots, id, make_timer(id));
+        AddTimerResponse::ok(id)
+    }
+
+    /// Add a timer under `id`, if it's free and there are fewer than `max`
+    /// timers. `make_timer` is only called if it will be added.
+    pub fn try_add_at<F>(&self, max: usize, id: TimerId, make_timer: F) -> SnoozeResponse
+    where
+        F: FnOnce() -> Timer,
+    {
+        let index = slot_index(id).unwrap_or_else(|| unreachable!("BUG: invalid timer id {id:?}"));
+        let mut slots = self.slots();
+        if slots.get(index).is_some_and(Option::is_some) {
+            return SnoozeResponse::NotElapsed;
+        }
+        if count(&slots) >= max {
+            return SnoozeResponse::LimitReached { max };
+        }
+        if slots.len() <= index {
+            slots.resize_with(index + 1, || None);
+        }
+        slots[index] = Some(make_timer());
+        SnoozeResponse::Ok
+    }
+
+    /// Add a paused timer which starts when `after` elapses, if there are
+    /// fewer than `max` timers and `after` exists.
+    ///
+    /// If `unique`, and there's a running timer started with the same
+    /// duration and name as `timer`, or one already waiting for `after`, its
+    /// id is returned as [`AddTimerResponse::AlreadyExists`] instead, checked
+    /// under the same lock as the insertion, like [`Timers::try_add_unique`].
+    ///
+    /// Timers can only wait for ones which already exist, and a new timer's
+    /// id isn't in use, so nothing can be waiting for it yet, and waits can
+    /// never form a cycle.
+    pub fn try_add_after(&self, max: usize, after: TimerId, unique: bool, timer: Timer) -> AddTimerResponse {
+        let mut slots = self.slots();
+        if slot_mut(&mut slots, after).is_none() {
+            return AddTimerResponse::AfterNotFound { after };
+        }
+        if unique {
+            let (duration, name) = (timer.spec.duration, timer.spec.name.as_deref());
+            let same = find_running_in(&slots, duration, name).or_else(|| find_waiting_in(&slots, after, duration, name));
+            if let Some(id) = same {
+                return AddTimerResponse::AlreadyExists { id };
+            }
+        }
+        let Some(id) = first_vacant_id(&slots, max) else {
+            return AddTimerResponse::LimitReached { max };
+        };
+        insert_vacant(&mut slots, id, timer);
+        AddTimerResponse::ok(id)
+    }
+
+    pub fn spec(&self, id: TimerId) -> Option<StartSpec> {
+        let slots = self.slots();
+        let timer = slots.get(slot_index(id)?)?.as_ref()?;
+        Some(timer.spec.clone())
+    }
+
+    pub fn len(&self) -> usize {
+        count(&self.slots())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// How many timers are running and paused, and the time left on each
+    /// kind added up, all as of the same moment. Overdue timers count as
+    /// having no time left.
+    pub fn stats(&self, now: Instant) -> StatsResponse {
+        let (mut running, mut paused) = (0usize, 0usize);
+        let (mut running_remaining, mut paused_remaining) = (Duration::ZERO, Duration::ZERO);
+        for timer in self.slots().iter().flatten() {
+            match timer.state {
+                RunState::Running { due, .. } => {
+                    running = running.saturating_add(1);
+                    running_remaining = running_remaining.saturating_add(due.saturating_duration_since(now));
+                }
+                RunState::Paused { remaining, .. } => {
+                    paused = paused.saturating_add(1);
+                    paused_remaining = paused_remaining.saturating_add(remaining);
+                }
+            }
+        }
+        let millis = |duration: Duration| u64::try_from(duration.as_millis()).unwrap_or(u64::MAX);
+        StatsResponse::Ok {
+            running,
+            paused,
+            running_remaining_millis: millis(running_remaining),
+            paused_remaining_millis: millis(paused_remaining),
+        }
     }
 
     pub fn get_timerinfo_for_client(&self, now: Instant) -> Vec<TimerInfoForClient> {
-        self.0.iter().map(|ref_multi| {
-            let (id, timer) = ref_multi.pair();
-            TimerInfoForClient::new(*id, timer, now)
+        self.query(now, |_| true)
+    }
+
+    /// The timers, as of `now`, for which `matches` is true, in order of id.
+    pub fn query<P>(&self, now: Instant, matches: P) -> Vec<TimerInfoForClient>
+    where
+        P: Fn(&TimerInfoForClient) -> bool,
+    {
+        self.slots().iter().zip(1..).filter_map(|(slot, id)| {
+            let info = TimerInfoForClient::new(TimerId(id), slot.as_ref()?, now);
+            matches(&info).then_some(info)
+        }).collect()
+    }
+
+    pub fn debug_info(&self, now: Instant) -> Vec<TimerDebugInfo> {
+        self.slots().iter().zip(1..).filter_map(|(slot, id)| {
+            let timer = slot.as_ref()?;
+            let state = match timer.state {
+                RunState::Running { due, ref countdown } => {
+                    let due_in_millis = if due >= now {
+                        (due - now).as_millis() as i64
+                    } else {
+                        -((now - due).as_millis() as i64)
+                    };
+                    TimerDebugState::Running { due_in_millis, countdown_finished: countdown.is_finished() }
+                }
+                RunState::Paused { remaining, after, resume_at } => TimerDebugState::Paused {
+                    remaining_millis: remaining.as_millis() as u64,
+                    after,
+                    resume_at_millis: resume_at.map(clock::unix_millis),
+                },
+            };
+            Some(TimerDebugInfo {
+                id: TimerId(id),
+                spec: timer.spec.clone(),
+                created_at_millis: clock::unix_millis(timer.created_at),
+                state,
+            })
+        }).collect()
+    }
+
+    /// Every timer, in id order, for [`crate::sand::message::Command::Export`].
+    pub fn export(&self, now: Instant) -> Vec<ExportedTimer> {
+        self.slots().iter().flatten().map(|timer| {
+            let (state, remaining) = match timer.state {
+                RunState::Running { due, .. } => (TimerState::Running, due.saturating_duration_since(now)),
+                RunState::Paused { remaining, .. } => (TimerState::Paused, remaining),
+            };
+            ExportedTimer {
+                spec: StartSpec { after: None, ..timer.spec.clone() },
+                state,
+                remaining_millis: remaining.as_millis() as u64,
+                created_at_millis: clock::unix_millis(timer.created_at),
+            }
         }).collect()
     }
+
+    /// Look for timers in states they shouldn't be able to get into.
+    pub fn fsck(&self, now: Instant) -> Vec<FsckFinding> {
+        let slots = self.slots();
+        slots.iter().zip(1..).filter_map(|(slot, id)| {
+            let problem = match slot.as_ref()?.state {
+                RunState::Running { due, ref countdown } => {
+                    let overdue = now.saturating_duration_since(due);
+                    match (overdue > FSCK_OVERDUE_GRACE, countdown.is_finished()) {
+                        (false, false) => return None,
+                        (false, true) => "running, but its countdown has stopped, so it will never elapse".to_string(),
+                        (true, finished) => format!(
+                            "overdue by {}ms, and its countdown {}",
+                            overdue.as_millis(),
+                            if finished { "has stopped" } else { "is still going" },
+                        ),
+                    }
+                }
+                RunState::Paused { after: Some(after), .. } => {
+                    let exists = slot_index(after).and_then(|index| slots.get(index)).is_some_and(Option::is_some);
+                    if exists {
+                        return None;
+                    }
+                    format!("waiting for timer {after}, which doesn't exist")
+                }
+                RunState::Paused { remaining, after: None, .. } if remaining.is_zero() => {
+                    "paused with no time remaining".to_string()
+                }
+                RunState::Paused { .. } => return None,
+            };
+            Some(FsckFinding { id: TimerId(id), problem })
+        }).collect()
+    }
+
+    pub fn pause(&self, id: TimerId, now: Instant) -> PauseTimerResponse {
+        use PauseTimerResponse as Resp;
+        use RunState as S;
+
+        let mut slots = self.slots();
+        let Some(timer) = slot_mut(&mut slots, id) else {
+            return Resp::TimerNotFound;
+        };
+        let S::Running { due, countdown } = &timer.state else {
+            return Resp::AlreadyPaused
+        };
+
+        countdown.abort();
+        timer.state = S::Paused { remaining: *due - now, after: None, resume_at: None };
+        Resp::Ok
+    }
+
+    /// Resume a paused timer. `spawn_countdown` is given when the timer is
+    /// now due, and should start the task that counts down to then. A timer
+    /// waiting for another one starts straight away.
+    pub fn resume<F>(&self, id: TimerId, now: Instant, spawn_countdown: F) -> ResumeTimerResponse
+    where
+        F: FnOnce(Instant) -> JoinHandle<()>,
+    {
+        use ResumeTimerResponse as Resp;
+        use RunState as S;
+
+        let mut slots = self.slots();
+        let Some(timer) = slot_mut(&mut slots, id) else {
+            return Resp::TimerNotFound;
+        };
+        let S::Paused { remaining, .. } = timer.state else {
+            return Resp::AlreadyRunning
+        };
+
+        let due = now + remaining;
+        let countdown = spawn_countdown(due);
+        timer.state = S::Running { due, countdown };
+        Resp::Ok
+    }
+
+    /// Have a paused timer resume by itself at `at`. It's up to the caller to
+    /// resume it then, if [`Timers::resume_at`] still says so.
+    pub fn schedule_resume(&self, id: TimerId, at: SystemTime) -> ResumeTimerResponse {
+        let mut slots = self.slots();
+        let Some(timer) = slot_mut(&mut slots, id) else {
+            return ResumeTimerResponse::TimerNotFound;
+        };
+        let RunState::Paused { resume_at, .. } = &mut timer.state else {
+            return ResumeTimerResponse::AlreadyRunning;
+        };
+        *resume_at = Some(at);
+        ResumeTimerResponse::Ok
+    }
+
+    /// When the paused timer `id` is scheduled to resume, if it is.
+    pub fn resume_at(&self, id: TimerId) -> Option<SystemTime> {
+        let slots = self.slots();
+        match slots.get(slot_index(id)?)?.as_ref()?.state {
+            RunState::Paused { resume_at, .. } => resume_at,
+            RunState::Running { .. } => None,
+        }
+    }
+
+    /// Pause a running timer, or resume a paused one, as a single step.
+    /// `spawn_countdown` is as for [`Timers::resume`].
+    pub fn toggle<F>(&self, id: TimerId, now: Instant, spawn_countdown: F) -> ToggleTimerResponse
+    where
+        F: FnOnce(Instant) -> JoinHandle<()>,
+    {
+        use ToggleTimerResponse as Resp;
+        use RunState as S;
+
+        let mut slots = self.slots();
+        let Some(timer) = slot_mut(&mut slots, id) else {
+            return Resp::TimerNotFound;
+        };
+        match &timer.state {
+            S::Running { due, countdown } => {
+                countdown.abort();
+                timer.state = S::Paused { remaining: due.saturating_duration_since(now), after: None, resume_at: None };
+                Resp::Paused
+            }
+            &S::Paused { remaining, .. } => {
+                let due = now + remaining;
+                let countdown = spawn_countdown(due);
+                timer.state = S::Running { due, countdown };
+                Resp::Resumed
+            }
+        }
+    }
+
+    /// Change the time remaining on a timer. `adjust` is given the time
+    /// currently remaining, and returns the new remaining time. If the timer
+    /// is running, its countdown is replaced by one from `spawn_countdown`,
+    /// which is given when the timer is now due.
+    pub fn adjust<A, F>(&self, id: TimerId, now: Instant, adjust: A, spawn_countdown: F) -> AdjustTimerResponse
+    where
+        A: FnOnce(Duration) -> Duration,
+        F: FnOnce(Instant) -> JoinHandle<()>,
+    {
+        use AdjustTimerResponse as Resp;
+        use RunState as S;
+
+        let mut slots = self.slots();
+        let Some(timer) = slot_mut(&mut slots, id) else {
+            return Resp::TimerNotFound;
+        };
+        let remaining = adjust(match &timer.state {
+            S::Paused { remaining, .. } => *remaining,
+            S::Running { due, .. } => due.saturating_duration_since(now),
+        });
+        let remaining_millis = u64::try_from(remaining.as_millis()).unwrap_or(u64::MAX);
+        if remaining > MAX_DURATION {
+            return Resp::InvalidDuration { duration: remaining_millis };
+        }
+        match &mut timer.state {
+            S::Paused { remaining: paused_remaining, .. } => *paused_remaining = remaining,
+            S::Running { due, countdown } => {
+                countdown.abort();
+                *due = now + remaining;
+                *countdown = spawn_countdown(*due);
+            }
+        }
+        Resp::Ok { remaining_millis }
+    }
+
+    /// Account for time the system spent asleep, during which running
+    /// timers' countdowns were frozen. Each running timer's countdown is
+    /// replaced by one from `respawn`, which is given when the timer is now
+    /// due, and whether it was due while the system was asleep. A timer due
+    /// exactly as the system woke is due now, but wasn't due while asleep.
+    pub fn reschedule_running<F>(&self, now: Instant, slept: Duration, respawn: F)
+    where
+        F: FnMut(TimerId, Instant, bool) -> JoinHandle<()>,
+    {
+        self.move_running(now, |due| due.checked_sub(slept), respawn)
+    }
+
+    /// Put off every running timer by `by`, replacing its countdown with
+    /// one from `respawn` as for [`Timers::reschedule_running`]. For when
+    /// the monotonic clock has jumped ahead of the time really passed.
+    pub fn delay_running<F>(&self, now: Instant, by: Duration, respawn: F)
+    where
+        F: FnMut(TimerId, Instant, bool) -> JoinHandle<()>,
+    {
+        self.move_running(now, |due| due.checked_add(by), respawn)
+    }
+
+    /// Give each running timer the due time `real_due` returns for its
+    /// current one, or make it due now if that's `None`.
+    fn move_running<D, F>(&self, now: Instant, real_due: D, mut respawn: F)
+    where
+        D: Fn(Instant) -> Option<Instant>,
+        F: FnMut(TimerId, Instant, bool) -> JoinHandle<()>,
+    {
+        let mut slots = self.slots();
+        for (slot, id) in slots.iter_mut().zip(1..) {
+            let Some(Timer { state: RunState::Running { due, countdown }, .. }) = slot else {
+                continue;
+            };
+            let real_due = real_due(*due);
+            let due_while_asleep = real_due.is_none_or(|real_due| real_due < now);
+            countdown.abort();
+            *due = real_due.map_or(now, |real_due| real_due.max(now));
+            *countdown = respawn(TimerId(id), *due, due_while_asleep);
+        }
+    }
+
+    pub fn cancel(&self, id: TimerId) -> CancelTimerResponse {
+        use CancelTimerResponse as Resp;
+
+        let Some(timer) = self.remove(id) else {
+            return Resp::TimerNotFound;
+        };
+        if let RunState::Running { countdown, .. } = timer.state {
+            countdown.abort();
+        }
+        Resp::Ok
+    }
     
-    pub(crate) fn elapse(&self, id: TimerId) {
-        let Entry::Occupied(occ) = self.0.entry(id) else {
-            unreachable!("BUG: tried to complete nonexistent timer #{id:?}");
+    /// Called by the countdown for a timer which was due at `due`, once it
+    /// has finished. Returns `None` if the timer is no longer due then,
+    /// because it was paused, cancelled or rescheduled after the countdown
+    /// finished but before this was called. Only one countdown can claim
+    /// each due time, so a timer never goes off twice.
+    ///
+    /// Elapsed timers are removed, since restarting one from its
+    /// notification starts a new timer, unless `repeats` says the timer
+    /// should run again. The old countdown isn't aborted, since it's the
+    /// caller. Timers waiting for this one are started. `spawn_countdown` is
+    /// given the id and due time of each timer started, as for
+    /// [`Timers::resume`].
+    pub(crate) fn elapse<R, F>(&self, id: TimerId, due: Instant, now: Instant, repeats: R, mut spawn_countdown: F) -> Option<Elapsed>
+    where
+        R: FnOnce(&StartSpec) -> bool,
+        F: FnMut(TimerId, Instant) -> JoinHandle<()>,
+    {
+        let mut slots = self.slots();
+        let index = slot_index(id)?;
+        let timer = slots.get_mut(index)?.as_mut()?;
+        if !matches!(timer.state, RunState::Running { due: current, .. } if current == due) {
+            return None;
+        }
+        let spec = timer.spec.clone();
+        let repeated = repeats(&spec);
+        if repeated {
+            let next_due = now + spec.duration();
+            timer.state = RunState::Running { due: next_due, countdown: spawn_countdown(id, next_due) };
+        }
+
+        let mut started = Vec::new();
+        for (slot, waiting_id) in slots.iter_mut().zip(1..) {
+            let Some(Timer { state, .. }) = slot else {
+                continue;
+            };
+            if let &mut RunState::Paused { remaining, after: Some(after), .. } = state {
+                if after == id {
+                    let waiting_id = TimerId(waiting_id);
+                    let due = now + remaining;
+                    *state = RunState::Running { due, countdown: spawn_countdown(waiting_id, due) };
+                    started.push(waiting_id);
+                }
+            }
+        }
+        if !repeated {
+            vacate(&mut slots, index);
+        }
+        Some(Elapsed { spec, repeated, started })
+    }
+
+    /// Remove a timer, returning it, or `None` if there was no timer with
+    /// that id, as when a user cancels one that's already gone. That's up to
+    /// the caller to report. Doesn't touch the timer's countdown.
+    /// Timers waiting for it are left paused.
+    pub fn remove(&self, id: TimerId) -> Option<Timer> {
+        let mut slots = self.slots();
+        slot_index(id).and_then(|index| vacate(&mut slots, index))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pending_countdown() -> JoinHandle<()> {
+        tokio::spawn(std::future::pending())
+    }
+
+    fn running(due: Instant) -> Timer {
+        Timer {
+            spec: StartSpec::new(Duration::from_secs(60)),
+            state: RunState::Running { due, countdown: pending_countdown() },
+            created_at: std::time::SystemTime::now(),
+        }
+    }
+
+    #[tokio::test]
+    async fn totals_split_running_and_paused() {
+        let timers = Timers::default();
+        let now = Instant::now();
+        timers.add(TimerId(1), running(now + Duration::from_secs(60)));
+        timers.add(TimerId(2), running(now + Duration::from_secs(30)));
+        timers.add(TimerId(3), running(now));
+        timers.add(TimerId(4), running(now + Duration::from_secs(10)));
+        assert_eq!(timers.pause(TimerId(4), now), PauseTimerResponse::Ok);
+
+        let stats = |now| timers.stats(now);
+        assert_eq!(stats(now), StatsResponse::Ok {
+            running: 3,
+            paused: 1,
+            running_remaining_millis: 90_000,
+            paused_remaining_millis: 10_000,
+        });
+        // Overdue timers don't take anything away.
+        assert!(matches!(
+            stats(now + Duration::from_secs(45)),
+            StatsResponse::Ok { running: 3, running_remaining_millis: 15_000, .. },
+        ));
+    }
+
+    #[tokio::test]
+    async fn adjust_respawns_running_countdown() {
+        let timers = Timers::default();
+        let id = TimerId(1);
+        let now = Instant::now();
+        timers.add(id, running(now + Duration::from_secs(60)));
+
+        let mut respawned_with = None;
+        let resp = timers.adjust(id, now, |remaining| remaining + Duration::from_secs(30), |due| {
+            respawned_with = Some(due);
+            pending_countdown()
+        });
+        assert_eq!(resp, AdjustTimerResponse::Ok { remaining_millis: 90_000 });
+        assert_eq!(respawned_with, Some(now + Duration::from_secs(90)));
+
+        assert_eq!(timers.pause(id, now), PauseTimerResponse::Ok);
+        let resp = timers.adjust(id, now, |_| Duration::ZERO, |_| unreachable!());
+        assert_eq!(resp, AdjustTimerResponse::Ok { remaining_millis: 0 });
+
+        let too_long = MAX_DURATION + Duration::from_millis(1);
+        let resp = timers.adjust(id, now, |_| too_long, |_| unreachable!());
+        assert_eq!(resp, AdjustTimerResponse::InvalidDuration { duration: too_long.as_millis() as u64 });
+        assert!(matches!(timers.stats(now), StatsResponse::Ok { paused_remaining_millis: 0, .. }));
+
+        let resp = timers.adjust(TimerId(2), now, |_| unreachable!(), |_| unreachable!());
+        assert_eq!(resp, AdjustTimerResponse::TimerNotFound);
+    }
+
+    #[tokio::test]
+    async fn pause_resume_cancel() {
+        let timers = Timers::default();
+        let id = TimerId(1);
+        let now = Instant::now();
+        timers.add(id, running(now + Duration::from_secs(60)));
+
+        let later = now + Duration::from_secs(10);
+        assert_eq!(timers.pause(id, later), PauseTimerResponse::Ok);
+        assert_eq!(timers.pause(id, later), PauseTimerResponse::AlreadyPaused);
+        let info = &timers.get_timerinfo_for_client(later)[0];
+        assert_eq!(info.remaining_millis(), 50_000);
+
+        let mut resumed_with = None;
+        let resp = timers.resume(id, later, |due| {
+            resumed_with = Some(due);
+            pending_countdown()
+        });
+        assert_eq!(resp, ResumeTimerResponse::Ok);
+        assert_eq!(resumed_with, Some(later + Duration::from_secs(50)));
+        let resp = timers.resume(id, later, |_| unreachable!());
+        assert_eq!(resp, ResumeTimerResponse::AlreadyRunning);
+
+        assert_eq!(timers.cancel(id), CancelTimerResponse::Ok);
+        assert_eq!(timers.cancel(id), CancelTimerResponse::TimerNotFound);
+        assert_eq!(timers.pause(id, later), PauseTimerResponse::TimerNotFound);
+        assert_eq!(timers.resume(id, later, |_| unreachable!()), ResumeTimerResponse::TimerNotFound);
+    }
+
+    #[tokio::test]
+    async fn toggle() {
+        let timers = Timers::default();
+        let id = TimerId(1);
+        let now = Instant::now();
+        timers.add(id, running(now + Duration::from_secs(60)));
+
+        let later = now + Duration::from_secs(10);
+        assert_eq!(timers.toggle(id, later, |_| unreachable!()), ToggleTimerResponse::Paused);
+        let mut resumed_with = None;
+        let resp = timers.toggle(id, later, |due| {
+            resumed_with = Some(due);
+            pending_countdown()
+        });
+        assert_eq!(resp, ToggleTimerResponse::Resumed);
+        assert_eq!(resumed_with, Some(later + Duration::from_secs(50)));
+        assert_eq!(timers.toggle(TimerId(2), later, |_| unreachable!()), ToggleTimerResponse::TimerNotFound);
+    }
+
+    #[tokio::test]
+    async fn fsck_finds_stuck_timers() {
+        let timers = Timers::default();
+        let now = Instant::now();
+        timers.add(TimerId(1), running(now + Duration::from_secs(60)));
+        timers.add(TimerId(2), running(now - Duration::from_secs(60)));
+        timers.add(TimerId(3), Timer {
+            state: RunState::Paused { remaining: Duration::ZERO, after: None, resume_at: None },
+            ..running(now)
+        });
+        let stopped = tokio::spawn(async {});
+        while !stopped.is_finished() {
+            tokio::task::yield_now().await;
+        }
+        timers.add(TimerId(4), Timer {
+            state: RunState::Running { due: now + Duration::from_secs(60), countdown: stopped },
+            ..running(now)
+        });
+
+        let problems: Vec<(u64, String)> = timers.fsck(now).into_iter()
+            .map(|finding| (finding.id.0, finding.problem))
+            .collect();
+        assert_eq!(problems, [
+            (2, "overdue by 60000ms, and its countdown is still going".to_string()),
+            (3, "paused with no time remaining".to_string()),
+            (4, "running, but its countdown has stopped, so it will never elapse".to_string()),
+        ]);
+    }
+
+    #[tokio::test]
+    async fn add_new_reuses_lowest_free_id() {
+        let timers = Timers::default();
+        let due = Instant::now() + Duration::from_secs(60);
+        for expected in 1..=3 {
+            assert_eq!(timers.add_new(|_| running(due)), TimerId(expected));
+        }
+        timers.cancel(TimerId(2));
+        assert_eq!(timers.add_new(|_| running(due)), TimerId(2));
+        assert_eq!(timers.add_new(|_| running(due)), TimerId(4));
+
+        timers.add(TimerId(7), running(due));
+        assert_eq!(timers.add_new(|_| running(due)), TimerId(5));
+
+        for id in [1, 2, 3, 4, 5, 7] {
+            assert_eq!(timers.cancel(TimerId(id)), CancelTimerResponse::Ok);
+        }
+        assert!(timers.is_empty());
+        assert_eq!(timers.add_new(|_| running(due)), TimerId(1));
+    }
+
+    #[tokio::test]
+    async fn reschedule_running_after_sleep() {
+        let timers = Timers::default();
+        let now = Instant::now();
+        timers.add(TimerId(1), running(now + Duration::from_secs(60)));
+        timers.add(TimerId(2), running(now + Duration::from_secs(5)));
+        timers.add(TimerId(3), running(now + Duration::from_secs(60)));
+        timers.pause(TimerId(3), now);
+        // Due exactly as the system wakes, and just after.
+        timers.add(TimerId(4), running(now + Duration::from_secs(10)));
+        timers.add(TimerId(5), running(now + Duration::from_millis(10_001)));
+
+        let mut respawned = Vec::new();
+        timers.reschedule_running(now, Duration::from_secs(10), |id, due, due_while_asleep| {
+            respawned.push((id, due - now, due_while_asleep));
+            pending_countdown()
+        });
+        assert_eq!(respawned, vec![
+            (TimerId(1), Duration::from_secs(50), false),
+            (TimerId(2), Duration::ZERO, true),
+            (TimerId(4), Duration::ZERO, false),
+            (TimerId(5), Duration::from_millis(1), false),
+        ]);
+        let remaining: Vec<u64> = timers.get_timerinfo_for_client(now).iter()
+            .map(TimerInfoForClient::remaining_millis)
+            .collect();
+        assert_eq!(remaining, vec![50_000, 0, 60_000, 0, 1]);
+    }
+
+    #[tokio::test]
+    async fn elapse_only_once_per_due_time() {
+        let timers = Timers::default();
+        let now = Instant::now();
+        let due = now + Duration::from_secs(5);
+        timers.add(TimerId(1), running(due));
+
+        // A countdown for the old due time, after the timer was rescheduled.
+        let stale = now + Duration::from_secs(15);
+        assert_eq!(timers.elapse(TimerId(1), stale, due, |_| false, |_, _| unreachable!()), None);
+        assert!(!timers.is_empty());
+
+        let elapsed = timers.elapse(TimerId(1), due, due, |_| false, |_, _| unreachable!());
+        let spec = StartSpec::new(Duration::from_secs(60));
+        assert_eq!(elapsed, Some(Elapsed { spec, repeated: false, started: vec![] }));
+        assert!(timers.is_empty());
+        assert_eq!(timers.elapse(TimerId(1), due, due, |_| false, |_, _| unreachable!()), None);
+    }
+
+    #[tokio::test]
+    async fn waiting_timers_start_when_waited_for_elapses() {
+        let timers = Timers::default();
+        let now = Instant::now();
+        let due = now + Duration::from_secs(5);
+        let waiting = |after| Timer {
+            spec: StartSpec::new(Duration::from_secs(60)),
+            state: RunState::Paused { remaining: Duration::from_secs(60), after: Some(TimerId(after)), resume_at: None },
+            created_at: std::time::SystemTime::now(),
         };
-        occ.remove();
+        timers.add(TimerId(1), running(due));
+        assert_eq!(timers.try_add_after(10, TimerId(3), false, waiting(3)), AddTimerResponse::AfterNotFound { after: TimerId(3) });
+        assert_eq!(timers.try_add_after(10, TimerId(1), false, waiting(1)), AddTimerResponse::ok(TimerId(2)));
+        assert_eq!(timers.try_add_after(10, TimerId(2), false, waiting(2)), AddTimerResponse::ok(TimerId(3)));
+        assert_eq!(timers.pause(TimerId(2), now), PauseTimerResponse::AlreadyPaused);
+
+        let mut spawned = Vec::new();
+        let elapsed = timers.elapse(TimerId(1), due, due, |_| false, |id, due| {
+            spawned.push((id, due));
+            pending_countdown()
+        }).unwrap();
+        assert_eq!(elapsed.started, [TimerId(2)]);
+        assert_eq!(spawned, [(TimerId(2), due + Duration::from_secs(60))]);
+
+        // Cancelling a timer leaves those waiting for it paused, rather than
+        // waiting for whatever timer gets its id next.
+        assert_eq!(timers.cancel(TimerId(2)), CancelTimerResponse::Ok);
+        let info = timers.get_timerinfo_for_client(due);
+        assert_eq!(info.iter().map(|timer| (timer.id(), timer.after())).collect::<Vec<_>>(), [(TimerId(3), None)]);
+    }
+
+    #[tokio::test]
+    async fn delay_running_after_clock_jump() {
+        let timers = Timers::default();
+        let now = Instant::now();
+        timers.add(TimerId(1), running(now + Duration::from_secs(60)));
+        timers.add(TimerId(2), running(now + Duration::from_secs(60)));
+        timers.pause(TimerId(2), now);
+
+        let mut respawned = Vec::new();
+        timers.delay_running(now, Duration::from_secs(30), |id, due, _| {
+            respawned.push((id, due - now));
+            pending_countdown()
+        });
+        assert_eq!(respawned, vec![(TimerId(1), Duration::from_secs(90))]);
+    }
+
+    #[tokio::test]
+    async fn remove_returns_removed_timer() {
+        let timers = Timers::default();
+        let due = Instant::now() + Duration::from_secs(60);
+        timers.add(TimerId(2), running(due));
+
+        assert!(timers.remove(TimerId(1)).is_none());
+        assert!(timers.remove(TimerId(0)).is_none());
+        let removed = timers.remove(TimerId(2)).expect("timer #2 should exist");
+        assert!(matches!(removed.state, RunState::Running { due: d, .. } if d == due));
+        assert!(timers.remove(TimerId(2)).is_none());
+        assert!(timers.is_empty());
+    }
+
+    #[tokio::test]
+    async fn try_add_new_respects_max() {
+        let timers = Timers::default();
+        let due = Instant::now() + Duration::from_secs(60);
+        assert_eq!(timers.try_add_new(2, |_| running(due)), Some(TimerId(1)));
+        assert_eq!(timers.try_add_new(2, |_| running(due)), Some(TimerId(2)));
+        assert_eq!(timers.try_add_new(2, |_| unreachable!()), None);
+        assert_eq!(timers.len(), 2);
+
+        timers.pause(TimerId(1), Instant::now());
+        assert_eq!(timers.try_add_new(2, |_| unreachable!()), None);
+
+        timers.cancel(TimerId(1));
+        assert_eq!(timers.try_add_new(2, |_| running(due)), Some(TimerId(1)));
+    }
+
+    #[tokio::test]
+    async fn filling_to_the_limit_stops_there() {
+        let timers = Timers::default();
+        let due = Instant::now() + Duration::from_secs(60);
+        let max = 100;
+        for expected in 1..=max {
+            assert_eq!(timers.try_add_new(max, |_| running(due)), Some(TimerId(expected as u64)));
+        }
+        assert_eq!(timers.try_add_new(max, |_| unreachable!()), None);
+        assert_eq!(timers.try_add_after(max, TimerId(1), false, running(due)), AddTimerResponse::LimitReached { max });
+
+        // Slots past the limit, as `add` can make, don't count as room.
+        timers.add(TimerId(max as u64 + 5), running(due));
+        assert_eq!(timers.try_add_new(max, |_| unreachable!()), None);
+        assert_eq!(timers.len(), max + 1);
     }
-}
\ No newline at end of file
+}
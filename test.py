@@ -67,7 +67,7 @@ def daemon_socket():
 @pytest.fixture
 def daemon(daemon_socket):
 
-    daemon_args = ["daemon"]
+    daemon_args = ["daemon", "--no-state"]
     sock_fd = daemon_socket.fileno()
     try:
         with open("daemon_stderr.log", "w") as daemon_stderr:
@@ -127,9 +127,13 @@ def msg_and_response(msg):
     return response
 
 # Since the amount of time elapsed is not deterministic, for most tests we want
-# to ignore the specific amount of time elapsed/remaining.
+# to ignore the specific amount of time elapsed/remaining, and when timers were
+# created.
 IGNORE_MILLIS = r".+\['millis'\]$"
-IGNORE_REMAINING_MILLIS = r".+\['remaining_millis'\]$"
+IGNORE_REMAINING_MILLIS = [
+    r".+\['remaining_millis'\]$",
+    r".+\['created_at_millis'\]$",
+]
 
 class TestDaemon:
     def test_list_none(self, daemon):